@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use brs::dht::{Dht, DhtConfig};
+
+/// Start a DHT node and join the network. `None` if the socket can't be bound.
+pub(crate) async fn start(config: DhtConfig) -> Option<Arc<Dht>> {
+    let dht = match Dht::bind(config).await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return None;
+        }
+    };
+    match dht.bootstrap().await {
+        Ok(nodes) => println!("Joined the DHT through {nodes} nodes"),
+        Err(e) => eprintln!("Failed to join the DHT: {e}"),
+    }
+
+    Some(Arc::new(dht))
+}
+
+/// Keep the routing table for the next run
+pub(crate) fn save(dht: Option<Arc<Dht>>) {
+    if let Some(dht) = dht {
+        if let Err(e) = dht.save_state() {
+            eprintln!("Failed to save DHT state: {e}");
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use brs::{
+    bandwidth::{Bandwidth, SpeedLimits},
+    dht::DhtConfig,
+    engine::{Engine, EngineError, EngineProgress, PickMode, Priority, ResumeData, SeedTarget},
+    torrent::v1,
+};
+use glob::Pattern;
+use human_bytes::human_bytes;
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn download(
+    path: String,
+    output: String,
+    port: u16,
+    max_peers: usize,
+    sequential: bool,
+    only: Vec<String>,
+    skip: Vec<String>,
+    udp_retries: u32,
+    limits: SpeedLimits,
+    dht: Option<DhtConfig>,
+) {
+    let bytes = match fs::read(&path) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to read torrent file {path}: {e}"),
+    };
+    let torrent = match v1::Torrent::parse_bytes(&bytes) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+    let priorities = match file_priorities(&torrent, &only, &skip) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+    let mode = if sequential {
+        PickMode::Sequential
+    } else {
+        PickMode::RarestFirst
+    };
+    let mut engine = match Engine::new(&torrent, Path::new(&output)) {
+        Ok(v) => v
+            .port(port)
+            .max_peers(max_peers)
+            .pick_mode(mode)
+            .file_priorities(priorities)
+            .udp_retries(udp_retries)
+            .resume_file(ResumeData::path_for(Path::new(&path)))
+            .rate_limit(Bandwidth::new(limits)),
+        Err(e) => return eprintln!("Failed to start download: {e}"),
+    };
+    let dht = match dht {
+        Some(config) => crate::dht::start(config).await,
+        None => None,
+    };
+    if let Some(dht) = &dht {
+        engine = engine.dht(Arc::clone(dht));
+    }
+    stop_on_interrupt(&engine);
+
+    println!("Downloading {} into {output}", torrent.info.name);
+    let res = engine.run(print_progress).await;
+    eprintln!();
+    crate::dht::save(dht);
+
+    match res {
+        Ok(_) => println!("Download complete: {}", torrent.info.name),
+        Err(EngineError::Cancelled) => println!("Download interrupted"),
+        Err(e) => eprintln!("Download failed: {e}"),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn seed(
+    path: String,
+    dir: String,
+    port: u16,
+    max_peers: usize,
+    ratio: Option<f64>,
+    minutes: Option<u64>,
+    udp_retries: u32,
+    limits: SpeedLimits,
+    dht: Option<DhtConfig>,
+) {
+    let bytes = match fs::read(&path) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to read torrent file {path}: {e}"),
+    };
+    let torrent = match v1::Torrent::parse_bytes(&bytes) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+
+    let report = torrent.verify_with_progress(Path::new(&dir), 0, |p| {
+        eprint!(
+            "\rChecking pieces: {}/{} ({:.1}%)",
+            p.hashed_pieces,
+            p.total_pieces,
+            p.hashed_bytes as f64 * 100.0 / p.total_bytes as f64
+        );
+    });
+    eprintln!();
+    let report = match report {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to verify torrent content: {e}"),
+    };
+    if !report.is_complete() {
+        return eprintln!(
+            "Torrent content is incomplete: {}/{} pieces valid",
+            report.valid_pieces(),
+            report.pieces.len()
+        );
+    }
+
+    let target = SeedTarget {
+        ratio,
+        duration: minutes.map(|m| Duration::from_secs(m * 60)),
+    };
+    let mut engine = match Engine::new(&torrent, Path::new(&dir)) {
+        Ok(v) => v
+            .port(port)
+            .max_peers(max_peers)
+            .verified(report.pieces)
+            .seed(target)
+            .udp_retries(udp_retries)
+            .resume_file(ResumeData::path_for(Path::new(&path)))
+            .rate_limit(Bandwidth::new(limits)),
+        Err(e) => return eprintln!("Failed to start seeding: {e}"),
+    };
+    let dht = match dht {
+        Some(config) => crate::dht::start(config).await,
+        None => None,
+    };
+    if let Some(dht) = &dht {
+        engine = engine.dht(Arc::clone(dht));
+    }
+    stop_on_interrupt(&engine);
+
+    println!("Seeding {} from {dir}", torrent.info.name);
+    let res = engine.run(print_progress).await;
+    eprintln!();
+    crate::dht::save(dht);
+
+    match res {
+        Ok(_) => println!("Seeding stopped: {}", torrent.info.name),
+        Err(e) => eprintln!("Seeding failed: {e}"),
+    }
+}
+
+/// Skip the files not matching any of `only` when set, then the files matching `skip`.
+/// Patterns are either file indices, as listed by `brs torrent files`, or globs matched
+/// against the file paths.
+fn file_priorities(
+    torrent: &v1::Torrent,
+    only: &[String],
+    skip: &[String],
+) -> Result<Vec<Priority>, String> {
+    let only = parse_selection(only)?;
+    let skip = parse_selection(skip)?;
+    let files = torrent.file_paths();
+    let priorities: Vec<Priority> = files
+        .iter()
+        .enumerate()
+        .map(|(i, (path, _))| {
+            let wanted = only.is_empty() || only.iter().any(|s| s.matches(i, path));
+            if wanted && !skip.iter().any(|s| s.matches(i, path)) {
+                Priority::Normal
+            } else {
+                Priority::Skip
+            }
+        })
+        .collect();
+    if priorities.iter().all(|p| *p == Priority::Skip) {
+        return Err("Every file is skipped, nothing to download".to_string());
+    }
+
+    Ok(priorities)
+}
+
+enum Selection {
+    Index(usize),
+    Glob(Pattern),
+}
+
+impl Selection {
+    fn matches(&self, index: usize, path: &str) -> bool {
+        match self {
+            Selection::Index(i) => *i == index,
+            Selection::Glob(p) => p.matches(path),
+        }
+    }
+}
+
+fn parse_selection(patterns: &[String]) -> Result<Vec<Selection>, String> {
+    patterns
+        .iter()
+        .map(|p| match p.parse() {
+            Ok(i) => Ok(Selection::Index(i)),
+            Err(_) => Pattern::new(p)
+                .map(Selection::Glob)
+                .map_err(|e| format!("Invalid pattern {p}: {e}")),
+        })
+        .collect()
+}
+
+/// Stop the engine gracefully on Ctrl-C, so trackers are told we left
+fn stop_on_interrupt(engine: &Engine) {
+    let cancel = engine.cancel_handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel.cancel();
+        }
+    });
+}
+
+fn print_progress(p: EngineProgress) {
+    eprint!(
+        "\rPieces: {}/{} ({:.1}%) | peers: {} | down: {}/s | up: {}/s ({} total)        ",
+        p.verified_pieces,
+        p.total_pieces,
+        p.downloaded as f64 * 100.0 / p.total.max(1) as f64,
+        p.peers,
+        human_bytes(p.download_rate as f64),
+        human_bytes(p.upload_rate as f64),
+        human_bytes(p.uploaded as f64)
+    );
+}
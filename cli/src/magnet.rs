@@ -0,0 +1,90 @@
+use std::fs;
+
+use brs::{
+    dht::DhtConfig,
+    magnet::{Magnet, MetadataFetcher},
+    torrent::v1,
+};
+use human_bytes::human_bytes;
+
+/// Print the parameters of a magnet link
+pub(crate) fn parse(uri: String) {
+    let magnet = match Magnet::parse(&uri) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+
+    if let Some(hash) = magnet.info_hash {
+        println!("Info hash: {hash}");
+    }
+    if let Some(hash) = magnet.info_hash_v2 {
+        println!("Info hash (v2): {}", hex::encode(hash));
+    }
+    if let Some(name) = &magnet.name {
+        println!("Name: {name}");
+    }
+    if let Some(length) = magnet.length {
+        println!("Length: {}", human_bytes(length as f64));
+    }
+    let lists = [
+        ("Trackers", &magnet.trackers),
+        ("Web seeds", &magnet.web_seeds),
+        ("Peers", &magnet.peers),
+    ];
+    for (title, values) in lists {
+        if !values.is_empty() {
+            println!("{title}:");
+            for v in values {
+                println!("\t{v}");
+            }
+        }
+    }
+    if !magnet.select_only.is_empty() {
+        let files: Vec<String> = magnet
+            .select_only
+            .iter()
+            .map(|r| match r.start() == r.end() {
+                true => r.start().to_string(),
+                false => format!("{}-{}", r.start(), r.end()),
+            })
+            .collect();
+        println!("Selected files: {}", files.join(", "));
+    }
+}
+
+/// Fetch the metadata of a magnet link from peers and write it as a ".torrent" file
+pub(crate) async fn fetch(uri: String, output: Option<String>, port: u16, dht: Option<DhtConfig>) {
+    let magnet = match Magnet::parse(&uri) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+
+    println!(
+        "Fetching metadata of {}",
+        magnet.name.as_deref().unwrap_or(&uri)
+    );
+    let mut fetcher = MetadataFetcher::new(magnet).port(port);
+    let dht = match dht {
+        Some(config) => crate::dht::start(config).await,
+        None => None,
+    };
+    if let Some(dht) = &dht {
+        fetcher = fetcher.dht(std::sync::Arc::clone(dht));
+    }
+    let res = fetcher.fetch().await;
+    crate::dht::save(dht);
+    let bytes = match res {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to fetch metadata: {e}"),
+    };
+    let name = match v1::Torrent::parse_bytes(&bytes) {
+        Ok(v) => v.info.name,
+        Err(e) => return eprintln!("Fetched metadata is not a valid torrent: {e}"),
+    };
+
+    let output = output.unwrap_or_else(|| format!("{name}.torrent"));
+    match fs::write(&output, bytes) {
+        Ok(_) => println!("Torrent written to {output}"),
+        Err(e) => eprintln!("Failed to write {output}: {e}"),
+    }
+}
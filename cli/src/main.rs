@@ -1,12 +1,25 @@
+mod dht;
+mod download;
+mod magnet;
+mod session;
 mod torrent;
 mod tracker;
 
-use std::io;
+use std::{io, net::Ipv4Addr, path::PathBuf};
 
-use clap::{Command, CommandFactory, Parser, Subcommand, ValueHint};
+use brs::{
+    bandwidth::{SpeedLimits, SpeedSchedule},
+    dht::DhtConfig,
+    tracker::tracker::UDP_DEFAULT_RETRIES,
+};
+use chrono::NaiveTime;
+
+use clap::{Args, Command, CommandFactory, Parser, Subcommand, ValueHint};
 use clap_complete::{generate, Generator, Shell};
-use torrent::{create, metadata, raw};
-use tracker::peers;
+use download::{download, seed};
+use session::session;
+use torrent::{create, files, magnet, metadata, raw, verify};
+use tracker::{peers, scrape};
 
 #[derive(Parser)]
 #[command(version, about)]
@@ -28,6 +41,170 @@ enum Cmds {
         #[command(subcommand)]
         commands: TrackerCmds,
     },
+    /// Magnet link tooling
+    Magnet {
+        #[command(subcommand)]
+        commands: MagnetCmds,
+    },
+    /// Download the content of a ".torrent" file
+    Download {
+        /// Path to an existing torrent file
+        #[arg(value_hint = ValueHint::FilePath)]
+        torrent: String,
+        /// Directory where the torrent content is written
+        #[arg(short, long, default_value = ".", value_hint = ValueHint::DirPath)]
+        output: String,
+        /// Port announced to trackers
+        #[arg(short, long, default_value_t = 6881)]
+        port: u16,
+        /// Maximum number of connected peers
+        #[arg(long, default_value_t = 50)]
+        max_peers: usize,
+        /// Download pieces in order instead of rarest first
+        #[arg(long)]
+        sequential: bool,
+        /// Only download the files matching this glob or index. Can be repeated
+        #[arg(long, value_name = "PATTERN")]
+        only: Vec<String>,
+        /// Skip the files matching this glob or index. Can be repeated
+        #[arg(long, value_name = "PATTERN")]
+        skip: Vec<String>,
+        /// Retransmissions before giving up on a UDP tracker, 8 for the full BEP 15 schedule
+        #[arg(long, default_value_t = UDP_DEFAULT_RETRIES)]
+        udp_retries: u32,
+        #[command(flatten)]
+        limits: RateLimits,
+        #[command(flatten)]
+        dht: DhtArgs,
+    },
+    /// Seed the content of a ".torrent" file until interrupted or a target is reached
+    Seed {
+        /// Path to an existing torrent file
+        #[arg(value_hint = ValueHint::FilePath)]
+        torrent: String,
+        /// Directory containing the torrent content
+        #[arg(value_hint = ValueHint::DirPath)]
+        dir: String,
+        /// Port announced to trackers and listened on
+        #[arg(short, long, default_value_t = 6881)]
+        port: u16,
+        /// Maximum number of connected peers
+        #[arg(long, default_value_t = 50)]
+        max_peers: usize,
+        /// Stop once uploaded bytes reach this ratio of the torrent size
+        #[arg(long)]
+        ratio: Option<f64>,
+        /// Stop after seeding for this many minutes
+        #[arg(long)]
+        minutes: Option<u64>,
+        /// Retransmissions before giving up on a UDP tracker, 8 for the full BEP 15 schedule
+        #[arg(long, default_value_t = UDP_DEFAULT_RETRIES)]
+        udp_retries: u32,
+        #[command(flatten)]
+        limits: RateLimits,
+        #[command(flatten)]
+        dht: DhtArgs,
+    },
+    /// Run many torrents in a persistent session until interrupted
+    Session {
+        /// Directory keeping the torrent list and the resume data
+        #[arg(long, default_value = ".brs", value_hint = ValueHint::DirPath)]
+        state_dir: String,
+        /// Add a ".torrent" file to the session. Can be repeated
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        add: Vec<String>,
+        /// Directory where the content of added torrents is written
+        #[arg(short, long, default_value = ".", value_hint = ValueHint::DirPath)]
+        output: String,
+        /// Port listened on and announced for every torrent
+        #[arg(short, long, default_value_t = 6881)]
+        port: u16,
+        /// Maximum number of torrents downloading at the same time
+        #[arg(long, default_value_t = 3)]
+        max_downloads: usize,
+        /// Maximum number of torrents seeding at the same time
+        #[arg(long, default_value_t = 5)]
+        max_seeds: usize,
+        /// Keep seeding completed torrents
+        #[arg(long)]
+        seed: bool,
+        #[command(flatten)]
+        limits: RateLimits,
+        /// Daily window using the alternative rates, e.g. "08:00-18:00"
+        #[arg(long, value_name = "HH:MM-HH:MM", value_parser = parse_window)]
+        alt_window: Option<(NaiveTime, NaiveTime)>,
+        /// Maximum upload rate in KiB/s during the alternative window
+        #[arg(long, value_name = "KIB/S", requires = "alt_window")]
+        alt_max_upload_rate: Option<u64>,
+        /// Maximum download rate in KiB/s during the alternative window
+        #[arg(long, value_name = "KIB/S", requires = "alt_window")]
+        alt_max_download_rate: Option<u64>,
+        #[command(flatten)]
+        dht: DhtArgs,
+    },
+}
+
+#[derive(Args)]
+struct RateLimits {
+    /// Maximum upload rate in KiB/s
+    #[arg(long, value_name = "KIB/S")]
+    max_upload_rate: Option<u64>,
+    /// Maximum download rate in KiB/s
+    #[arg(long, value_name = "KIB/S")]
+    max_download_rate: Option<u64>,
+}
+
+impl RateLimits {
+    fn to_limits(&self) -> SpeedLimits {
+        kib_limits(self.max_upload_rate, self.max_download_rate)
+    }
+}
+
+#[derive(Args)]
+struct DhtArgs {
+    /// Also find peers on the DHT
+    #[arg(long)]
+    dht: bool,
+    /// UDP port of the DHT node
+    #[arg(long, default_value_t = 6881, requires = "dht")]
+    dht_port: u16,
+    /// Node contacted to join the DHT. Can be repeated. Defaults to well known routers
+    #[arg(long, value_name = "HOST:PORT", requires = "dht")]
+    dht_bootstrap: Vec<String>,
+    /// File keeping the DHT routing table between runs
+    #[arg(long, value_hint = ValueHint::FilePath, requires = "dht")]
+    dht_state: Option<String>,
+}
+
+impl DhtArgs {
+    fn to_config(&self) -> Option<DhtConfig> {
+        if !self.dht {
+            return None;
+        }
+        let mut config = DhtConfig::new((Ipv4Addr::UNSPECIFIED, self.dht_port).into());
+        if !self.dht_bootstrap.is_empty() {
+            config.bootstrap = self.dht_bootstrap.clone();
+        }
+        config.state_file = self.dht_state.as_ref().map(PathBuf::from);
+
+        Some(config)
+    }
+}
+
+fn kib_limits(upload: Option<u64>, download: Option<u64>) -> SpeedLimits {
+    SpeedLimits {
+        upload: upload.filter(|v| *v > 0).map(|v| v * 1024),
+        download: download.filter(|v| *v > 0).map(|v| v * 1024),
+    }
+}
+
+fn parse_window(window: &str) -> Result<(NaiveTime, NaiveTime), String> {
+    let (from, to) = window
+        .split_once('-')
+        .ok_or_else(|| "expected HH:MM-HH:MM".to_string())?;
+    let parse = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|e| e.to_string());
+
+    Ok((parse(from)?, parse(to)?))
 }
 
 #[derive(Subcommand)]
@@ -44,6 +221,18 @@ enum TorrentCmds {
         #[arg(long)]
         v2: bool,
     },
+    /// List the files of a ".torrent" file with their index
+    Files {
+        /// Path to an existing torrent file
+        #[arg(value_hint = ValueHint::FilePath)]
+        path: String,
+    },
+    /// Print a magnet link to a ".torrent" file
+    Magnet {
+        /// Path to an existing torrent file
+        #[arg(value_hint = ValueHint::FilePath)]
+        path: String,
+    },
     /// Print data and types found inside a ".torrent" file
     Raw {
         /// Path to an existing torrent file
@@ -52,9 +241,61 @@ enum TorrentCmds {
     },
     /// Create a torrent file
     Create {
+        /// Path to the file or directory to share
+        #[arg(value_hint = ValueHint::AnyPath)]
+        path: String,
+        /// Tracker URL. Repeat for each tier, comma separated URLs share the same tier
+        #[arg(short, long = "tracker", value_hint = ValueHint::Url)]
+        trackers: Vec<String>,
+        /// Size of each piece in bytes. Picked from the content size when omitted
+        #[arg(long)]
+        piece_size: Option<i64>,
+        /// Mark the torrent as private
+        #[arg(long)]
+        private: bool,
+        /// Comment written in the torrent
+        #[arg(long)]
+        comment: Option<String>,
+        /// Output ".torrent" file. Defaults to "<name>.torrent"
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        output: Option<String>,
+        /// Number of threads hashing pieces. Defaults to the available parallelism
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Check data on disk against a ".torrent" file
+    Verify {
         /// Path to an existing torrent file
         #[arg(value_hint = ValueHint::FilePath)]
-        path: String,
+        torrent: String,
+        /// Directory containing the torrent content
+        #[arg(value_hint = ValueHint::DirPath)]
+        dir: String,
+        /// Number of threads hashing pieces. Defaults to the available parallelism
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MagnetCmds {
+    /// Print the parameters of a magnet link
+    Parse {
+        /// Magnet URI, quoted to keep the shell from interpreting "&"
+        uri: String,
+    },
+    /// Fetch the metadata of a magnet link from peers and save it as a ".torrent" file
+    Fetch {
+        /// Magnet URI, quoted to keep the shell from interpreting "&"
+        uri: String,
+        /// Output ".torrent" file. Defaults to "<name>.torrent"
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        output: Option<String>,
+        /// Port announced to trackers
+        #[arg(short, long, default_value_t = 6881)]
+        port: u16,
+        #[command(flatten)]
+        dht: DhtArgs,
     },
 }
 
@@ -64,6 +305,15 @@ enum TrackerCmds {
         #[arg(value_hint = ValueHint::FilePath)]
         path: String,
     },
+    /// Retrieve seeders, leechers and completed counts without announcing
+    Scrape {
+        /// Paths to existing torrent files
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        paths: Vec<String>,
+        /// Tracker to scrape. Defaults to the main tracker of each torrent
+        #[arg(short, long, value_hint = ValueHint::Url)]
+        tracker: Option<String>,
+    },
 }
 
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
@@ -82,12 +332,123 @@ async fn main() {
         match cmds {
             Cmds::Torrent { commands } => match commands {
                 TorrentCmds::Metadata { path, v1, v2 } => metadata(v1, v2, path),
-                TorrentCmds::Create { path } => create(path, String::new()),
+                TorrentCmds::Create {
+                    path,
+                    trackers,
+                    piece_size,
+                    private,
+                    comment,
+                    output,
+                    threads,
+                } => create(
+                    path, trackers, piece_size, private, comment, output, threads,
+                ),
+                TorrentCmds::Files { path } => files(path),
+                TorrentCmds::Magnet { path } => magnet(path),
                 TorrentCmds::Raw { path } => raw(path),
+                TorrentCmds::Verify {
+                    torrent,
+                    dir,
+                    threads,
+                } => verify(torrent, dir, threads),
             },
             Cmds::Tracker { commands } => match commands {
                 TrackerCmds::Peers { path } => peers(path).await,
+                TrackerCmds::Scrape { paths, tracker } => scrape(paths, tracker).await,
+            },
+            Cmds::Magnet { commands } => match commands {
+                MagnetCmds::Parse { uri } => magnet::parse(uri),
+                MagnetCmds::Fetch {
+                    uri,
+                    output,
+                    port,
+                    dht,
+                } => magnet::fetch(uri, output, port, dht.to_config()).await,
             },
+            Cmds::Download {
+                torrent,
+                output,
+                port,
+                max_peers,
+                sequential,
+                only,
+                skip,
+                udp_retries,
+                limits,
+                dht,
+            } => {
+                download(
+                    torrent,
+                    output,
+                    port,
+                    max_peers,
+                    sequential,
+                    only,
+                    skip,
+                    udp_retries,
+                    limits.to_limits(),
+                    dht.to_config(),
+                )
+                .await
+            }
+            Cmds::Seed {
+                torrent,
+                dir,
+                port,
+                max_peers,
+                ratio,
+                minutes,
+                udp_retries,
+                limits,
+                dht,
+            } => {
+                seed(
+                    torrent,
+                    dir,
+                    port,
+                    max_peers,
+                    ratio,
+                    minutes,
+                    udp_retries,
+                    limits.to_limits(),
+                    dht.to_config(),
+                )
+                .await
+            }
+            Cmds::Session {
+                state_dir,
+                add,
+                output,
+                port,
+                max_downloads,
+                max_seeds,
+                seed,
+                limits,
+                alt_window,
+                alt_max_upload_rate,
+                alt_max_download_rate,
+                dht,
+            } => {
+                let schedule = alt_window.map(|(from, to)| SpeedSchedule {
+                    normal: limits.to_limits(),
+                    alternative: kib_limits(alt_max_upload_rate, alt_max_download_rate),
+                    from,
+                    to,
+                });
+                session(
+                    state_dir,
+                    add,
+                    output,
+                    port,
+                    max_downloads,
+                    max_seeds,
+                    seed,
+                    limits.to_limits(),
+                    schedule,
+                    dht.to_config(),
+                )
+                .await
+            }
         }
     }
 }
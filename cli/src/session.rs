@@ -0,0 +1,95 @@
+use std::{fs, path::Path, time::Duration};
+
+use brs::{
+    bandwidth::{SpeedLimits, SpeedSchedule},
+    dht::DhtConfig,
+    engine::SeedTarget,
+    session::{Session, SessionConfig, TorrentState},
+};
+use human_bytes::human_bytes;
+
+/// Interval between two status reports
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn session(
+    state_dir: String,
+    add: Vec<String>,
+    output: String,
+    port: u16,
+    max_downloads: usize,
+    max_seeds: usize,
+    seed: bool,
+    limits: SpeedLimits,
+    schedule: Option<SpeedSchedule>,
+    dht: Option<DhtConfig>,
+) {
+    let mut config = SessionConfig::new(&state_dir);
+    config.port = port;
+    config.max_active_downloads = max_downloads.max(1);
+    config.max_active_seeds = max_seeds.max(1);
+    config.seed = seed.then(SeedTarget::default);
+    config.limits = limits;
+    config.schedule = schedule;
+    config.dht = dht;
+    let session = match Session::start(config).await {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to start session: {e}"),
+    };
+
+    for path in add {
+        let bytes = match fs::read(&path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Failed to read torrent file {path}: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = session.add_torrent(&bytes, Path::new(&output)) {
+            eprintln!("Failed to add {path}: {e}");
+        }
+    }
+
+    let mut interval = tokio::time::interval(STATUS_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = interval.tick() => print_status(&session),
+        }
+    }
+
+    println!("\nStopping session");
+    if let Err(e) = session.shutdown().await {
+        eprintln!("Failed to save session: {e}");
+    }
+}
+
+fn print_status(session: &Session) {
+    let torrents = session.torrents();
+    // Move the cursor back up to overwrite the previous report
+    print!("\x1b[{}F\x1b[J", torrents.len().max(1));
+    if torrents.is_empty() {
+        println!("No torrent in the session");
+    }
+    for t in torrents {
+        let state = match &t.state {
+            TorrentState::Queued => "queued".to_string(),
+            TorrentState::Downloading => "downloading".to_string(),
+            TorrentState::Seeding => "seeding".to_string(),
+            TorrentState::Paused => "paused".to_string(),
+            TorrentState::Finished => "finished".to_string(),
+            TorrentState::Failed(e) => format!("failed: {e}"),
+        };
+        match t.progress {
+            Some(p) => println!(
+                "{} [{state}] {:.1}% | peers: {} | down: {}/s | up: {}/s",
+                t.name,
+                p.downloaded as f64 * 100.0 / p.total.max(1) as f64,
+                p.peers,
+                human_bytes(p.download_rate as f64),
+                human_bytes(p.upload_rate as f64)
+            ),
+            None => println!("{} [{state}]", t.name),
+        }
+    }
+}
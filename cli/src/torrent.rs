@@ -1,6 +1,7 @@
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, fs, path::Path};
 
-use brs::torrent::v1;
+use brs::torrent::v1::{self, FileStatus};
+use human_bytes::human_bytes;
 
 pub(crate) fn metadata(v1: bool, _v2: bool, path: String) {
     if v1 {
@@ -15,12 +16,140 @@ pub(crate) fn metadata(v1: bool, _v2: bool, path: String) {
     }
 }
 
+/// List the files of a torrent with the index used to select them
+pub(crate) fn files(path: String) {
+    let bytes = match fs::read(&path) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to read torrent file {path}: {e}"),
+    };
+    let torrent = match v1::Torrent::parse_bytes(&bytes) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+
+    for (i, (path, length)) in torrent.file_paths().iter().enumerate() {
+        println!("{i:>4}  {:>10}  {path}", human_bytes(*length as f64));
+    }
+}
+
+/// Print a magnet link to the torrent
+pub(crate) fn magnet(path: String) {
+    let bytes = match fs::read(&path) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to read torrent file {path}: {e}"),
+    };
+    let magnet = v1::Torrent::parse_bytes(&bytes).and_then(|t| t.to_magnet());
+    match magnet {
+        Ok(v) => println!("{v}"),
+        Err(e) => eprintln!("{e}"),
+    }
+}
+
 pub(crate) fn raw(path: String) {
     let bytes = fs::read(path).unwrap();
     let out: HashMap<String, bendy::value::Value> = bendy::serde::from_bytes(&bytes).unwrap();
     println!("{:?}", out)
 }
 
-pub(crate) fn create(_path: String, _data: String) {
-    unimplemented!()
+pub(crate) fn create(
+    path: String,
+    trackers: Vec<String>,
+    piece_size: Option<i64>,
+    private: bool,
+    comment: Option<String>,
+    output: Option<String>,
+    threads: Option<usize>,
+) {
+    let tiers = trackers
+        .iter()
+        .map(|t| t.split(',').map(String::from).collect())
+        .collect();
+    let mut builder = v1::TorrentBuilder::new(&path)
+        .tracker_tiers(tiers)
+        .private(private);
+    if let Some(v) = piece_size {
+        builder = builder.piece_length(v);
+    }
+    if let Some(v) = comment {
+        builder = builder.comment(v);
+    }
+    if let Some(v) = threads {
+        builder = builder.threads(v);
+    }
+
+    let torrent = builder.build_with_progress(|p| {
+        eprint!(
+            "\rHashing pieces: {}/{} ({:.1}%)",
+            p.hashed_pieces,
+            p.total_pieces,
+            p.hashed_bytes as f64 * 100.0 / p.total_bytes as f64
+        );
+    });
+    eprintln!();
+    let torrent = match torrent {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to create torrent: {e}"),
+    };
+    let bytes = match torrent.to_bytes() {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+    let output = output.unwrap_or_else(|| format!("{}.torrent", torrent.info.name));
+    if let Err(e) = fs::write(&output, bytes) {
+        return eprintln!("Failed to write torrent file {output}: {e}");
+    }
+
+    println!("Torrent created: {output}");
+}
+
+pub(crate) fn verify(path: String, dir: String, threads: Option<usize>) {
+    let bytes = match fs::read(&path) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to read torrent file {path}: {e}"),
+    };
+    let torrent = match v1::Torrent::parse_bytes(&bytes) {
+        Ok(v) => v,
+        Err(e) => return eprintln!("{e}"),
+    };
+
+    let report = torrent.verify_with_progress(Path::new(&dir), threads.unwrap_or(0), |p| {
+        eprint!(
+            "\rChecking pieces: {}/{} ({:.1}%)",
+            p.hashed_pieces,
+            p.total_pieces,
+            p.hashed_bytes as f64 * 100.0 / p.total_bytes as f64
+        );
+    });
+    eprintln!();
+    let report = match report {
+        Ok(v) => v,
+        Err(e) => return eprintln!("Failed to verify torrent content: {e}"),
+    };
+
+    for f in &report.files {
+        match f.status {
+            FileStatus::Complete | FileStatus::Missing => println!("[{}] {}", f.status, f.path),
+            FileStatus::Partial => println!(
+                "[{}] {} ({}/{} pieces)",
+                f.status, f.path, f.valid_pieces, f.total_pieces
+            ),
+            FileStatus::WrongSize => println!(
+                "[{}] {} (expected {} bytes, found {})",
+                f.status,
+                f.path,
+                f.length,
+                f.size_on_disk.unwrap_or_default()
+            ),
+        }
+    }
+    println!(
+        "\n{}/{} pieces valid{}",
+        report.valid_pieces(),
+        report.pieces.len(),
+        if report.is_complete() {
+            ", torrent is complete"
+        } else {
+            ""
+        }
+    );
 }
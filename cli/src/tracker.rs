@@ -1,11 +1,10 @@
-use std::fs;
+use std::{collections::BTreeMap, fs};
 
 use brs::{
-    torrent::v1,
-    tracker::{announce::AnnounceReq, Tracker},
+    peer::PeerId,
+    torrent::{v1, InfoHash},
+    tracker::{announce::AnnounceReq, manager::TrackerManager, Tracker},
 };
-use rand::distributions::Alphanumeric;
-use rand::Rng;
 
 pub(crate) async fn peers(path: String) {
     let bytes = fs::read(path).unwrap();
@@ -15,41 +14,108 @@ pub(crate) async fn peers(path: String) {
     }
 
     let torrent = torrent.unwrap();
-    let peer_id: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(12)
-        .map(char::from)
-        .collect();
     let info_hash = match torrent.calc_hash() {
         Ok(v) => v,
         Err(e) => return eprintln!("Failed to calculate info hash: {e}"),
     };
-    let mut tracker = Tracker::new(torrent.announce.clone());
-    let rsp = tracker
+    let mut trackers = TrackerManager::from_torrent(&torrent);
+    let rsp = trackers
         .announce(AnnounceReq {
-            peer_id: format!("-BR010-{peer_id}"),
-            downloaded: "0".to_string(),
-            left: torrent.calc_download_lenght().to_string(),
-            uploaded: "0".to_string(),
+            peer_id: PeerId::generate(None),
+            left: torrent.calc_download_lenght().max(0) as u64,
             info_hash,
             compact: true,
             ..Default::default()
         })
-        .await
-        .unwrap();
-    let rsp = tracker.convert_bytes(&rsp).await;
+        .await;
 
     match rsp {
         Ok(v) => {
+            if let Some(w) = &v.warning_message {
+                eprintln!("Tracker warning: {w}");
+            }
+            match v.min_interval {
+                Some(min) => println!("interval: {}s (min {min}s)", v.interval),
+                None => println!("interval: {}s", v.interval),
+            }
+            if let (Some(seeders), Some(leechers)) = (v.complete, v.incomplete) {
+                println!("seeders: {seeders}");
+                println!("leechers: {leechers}");
+            }
+            if let Some(ip) = v.external_ip {
+                println!("external ip: {ip}");
+            }
+            println!();
             for p in v.peers {
                 println!("- ip: {}", p.ip);
                 println!("  port: {}", p.port);
                 if let Some(id) = p.id {
                     println!("  id: {}", id);
                 }
-                println!("");
+                println!();
             }
         }
         Err(e) => eprintln!("Failed to get peers: {e}"),
     }
 }
+
+pub(crate) async fn scrape(paths: Vec<String>, tracker: Option<String>) {
+    // Info hashes and names grouped by tracker, so each tracker is queried once
+    let mut by_tracker: BTreeMap<String, Vec<(InfoHash, String)>> = BTreeMap::new();
+    for path in paths {
+        let bytes = match fs::read(&path) {
+            Ok(v) => v,
+            Err(e) => return eprintln!("Failed to read torrent file {path}: {e}"),
+        };
+        let torrent = match v1::Torrent::parse_bytes(&bytes) {
+            Ok(v) => v,
+            Err(e) => return eprintln!("Failed to parse torrent {path}: {e}"),
+        };
+        let info_hash = match torrent.calc_hash() {
+            Ok(v) => v,
+            Err(e) => return eprintln!("Failed to calculate info hash: {e}"),
+        };
+        let url = match &tracker {
+            Some(v) => v.clone(),
+            None if !torrent.announce.is_empty() => torrent.announce.clone(),
+            None => match torrent.announce_list.trackers().next() {
+                Some(v) => v.clone(),
+                None => return eprintln!("No tracker found in {path}"),
+            },
+        };
+        by_tracker
+            .entry(url)
+            .or_default()
+            .push((info_hash, torrent.info.name.clone()));
+    }
+
+    for (url, torrents) in by_tracker {
+        let hashes: Vec<InfoHash> = torrents.iter().map(|(h, _)| *h).collect();
+        let stats = match Tracker::new(url.clone()).scrape(&hashes).await {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Failed to scrape {url}: {e}");
+                continue;
+            }
+        };
+
+        println!("{url}");
+        for (hash, name) in torrents {
+            println!("- hash: {hash}");
+            match stats.get(&hash) {
+                Some(s) => {
+                    let name = if s.name.is_empty() { &name } else { &s.name };
+                    println!("  name: {name}");
+                    println!("  seeders: {}", s.complete);
+                    println!("  leechers: {}", s.incomplete);
+                    println!("  completed: {}", s.downloaded);
+                }
+                None => {
+                    println!("  name: {name}");
+                    println!("  unknown to the tracker");
+                }
+            }
+        }
+        println!();
+    }
+}
@@ -0,0 +1,260 @@
+//! Token bucket rate limiting of the payload exchanged with peers.
+//!
+//! Limiters are chained from the most specific to the most global level: a peer, its torrent
+//! and the session. Payload waits for every limiter of the chain.
+
+mod clock;
+mod schedule;
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::time;
+
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use schedule::SpeedSchedule;
+
+/// Upload and download limits in bytes per second, `None` being unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpeedLimits {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+}
+
+/// RateLimiter is a token bucket refilled at a fixed rate, holding up to one second of
+/// tokens.
+///
+/// Reserving more tokens than available puts the bucket in debt: the caller must wait for
+/// the debt to be refilled. This way, a block larger than the bucket still goes through, and
+/// limiters can be chained by waiting for the longest delay.
+pub struct RateLimiter {
+    /// Bytes per second, `0` when unlimited. Shared with the limiters forked from this one.
+    rate: Arc<AtomicU64>,
+    bucket: Mutex<Bucket>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `rate` bytes per second, `None` for no limit
+    pub fn new(rate: Option<u64>) -> Self {
+        Self::with_clock(rate, Arc::new(SystemClock))
+    }
+
+    /// Same as [`RateLimiter::new`], reading the time from `clock`
+    pub fn with_clock(rate: Option<u64>, clock: Arc<dyn Clock>) -> Self {
+        Self::from_shared_rate(Arc::new(AtomicU64::new(rate.unwrap_or(0))), clock)
+    }
+
+    fn from_shared_rate(rate: Arc<AtomicU64>, clock: Arc<dyn Clock>) -> Self {
+        let tokens = rate.load(Ordering::Relaxed) as f64;
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens,
+                refilled_at: clock.now(),
+            }),
+            rate,
+            clock,
+        }
+    }
+
+    /// Create a limiter with its own bucket, always following the rate of this one
+    pub fn fork(&self) -> Self {
+        Self::from_shared_rate(Arc::clone(&self.rate), Arc::clone(&self.clock))
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        Some(self.rate.load(Ordering::Relaxed)).filter(|r| *r > 0)
+    }
+
+    /// Change the rate, taking effect on the next reservation
+    pub fn set_rate(&self, rate: Option<u64>) {
+        self.rate.store(rate.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Take `bytes` tokens from the bucket and return how long to wait before using them
+    pub fn reserve(&self, bytes: u64) -> Duration {
+        let rate = match self.rate() {
+            Some(v) => v as f64,
+            None => return Duration::ZERO,
+        };
+        let now = self.clock.now();
+        let mut bucket = self.bucket.lock().expect("rate limiter poisoned");
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.refilled_at = now;
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(-bucket.tokens / rate)
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("rate", &self.rate())
+            .finish()
+    }
+}
+
+/// Bandwidth holds the upload and download limiters of one level: a session, a torrent or
+/// a peer. Clones share the same limiters, so limits can be changed while they are in use.
+#[derive(Debug, Clone)]
+pub struct Bandwidth {
+    upload: Arc<RateLimiter>,
+    download: Arc<RateLimiter>,
+}
+
+impl Bandwidth {
+    pub fn new(limits: SpeedLimits) -> Self {
+        Self::with_clock(limits, Arc::new(SystemClock))
+    }
+
+    /// Same as [`Bandwidth::new`], reading the time from `clock`
+    pub fn with_clock(limits: SpeedLimits, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            upload: Arc::new(RateLimiter::with_clock(limits.upload, Arc::clone(&clock))),
+            download: Arc::new(RateLimiter::with_clock(limits.download, clock)),
+        }
+    }
+
+    pub fn upload(&self) -> &RateLimiter {
+        &self.upload
+    }
+
+    pub fn download(&self) -> &RateLimiter {
+        &self.download
+    }
+
+    pub fn limits(&self) -> SpeedLimits {
+        SpeedLimits {
+            upload: self.upload.rate(),
+            download: self.download.rate(),
+        }
+    }
+
+    pub fn set_limits(&self, limits: SpeedLimits) {
+        self.upload.set_rate(limits.upload);
+        self.download.set_rate(limits.download);
+    }
+
+    /// Create limiters with their own buckets, following the limits of these ones.
+    /// Used to give each peer the same limits.
+    pub fn fork(&self) -> Self {
+        Self {
+            upload: Arc::new(self.upload.fork()),
+            download: Arc::new(self.download.fork()),
+        }
+    }
+}
+
+impl Default for Bandwidth {
+    /// Unlimited bandwidth
+    fn default() -> Self {
+        Self::new(SpeedLimits::default())
+    }
+}
+
+/// Limiters consulted by a peer connection, from the most specific to the most global
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LimiterChain(Vec<Arc<RateLimiter>>);
+
+impl LimiterChain {
+    /// Chain the upload limiters of every level
+    pub fn upload(levels: &[&Bandwidth]) -> Self {
+        Self(levels.iter().map(|b| Arc::clone(&b.upload)).collect())
+    }
+
+    /// Chain the download limiters of every level
+    pub fn download(levels: &[&Bandwidth]) -> Self {
+        Self(levels.iter().map(|b| Arc::clone(&b.download)).collect())
+    }
+
+    /// Wait until every limiter allows `bytes` of payload
+    pub async fn acquire(&self, bytes: u64) {
+        let delay = self
+            .0
+            .iter()
+            .map(|l| l.reserve(bytes))
+            .max()
+            .unwrap_or_default();
+        if !delay.is_zero() {
+            time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate: Option<u64>) -> (RateLimiter, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        (RateLimiter::with_clock(rate, clock.clone()), clock)
+    }
+
+    #[test]
+    fn bucket_starts_full_and_refills() {
+        let (limiter, clock) = limiter(Some(1000));
+
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert_eq!(limiter.reserve(500), Duration::from_millis(500));
+        clock.advance(Duration::from_millis(750));
+        assert_eq!(limiter.reserve(250), Duration::ZERO);
+    }
+
+    #[test]
+    fn bucket_holds_one_second_of_tokens() {
+        let (limiter, clock) = limiter(Some(1000));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert_eq!(limiter.reserve(1), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn debt_delays_next_reservations() {
+        let (limiter, clock) = limiter(Some(1000));
+
+        // Bigger than the bucket, still allowed after paying the debt
+        assert_eq!(limiter.reserve(3000), Duration::from_secs(2));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.reserve(0), Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.reserve(0), Duration::ZERO);
+    }
+
+    #[test]
+    fn unlimited_never_waits() {
+        let (limiter, _) = limiter(None);
+
+        assert_eq!(limiter.reserve(u64::MAX), Duration::ZERO);
+        assert_eq!(limiter.rate(), None);
+    }
+
+    #[test]
+    fn forks_share_the_rate_not_the_bucket() {
+        let (limiter, _) = limiter(Some(1000));
+        let fork = limiter.fork();
+
+        assert_eq!(limiter.reserve(2000), Duration::from_secs(1));
+        assert_eq!(fork.reserve(1000), Duration::ZERO);
+        limiter.set_rate(Some(100));
+        assert_eq!(fork.rate(), Some(100));
+        assert_eq!(fork.reserve(50), Duration::from_millis(500));
+    }
+}
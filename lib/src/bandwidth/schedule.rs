@@ -0,0 +1,81 @@
+use chrono::NaiveTime;
+
+use super::{Bandwidth, SpeedLimits};
+
+/// SpeedSchedule switches to alternative limits during a daily time window, e.g. to keep
+/// the office link usable during work hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedSchedule {
+    /// Limits outside of the window
+    pub normal: SpeedLimits,
+    /// Limits inside of the window
+    pub alternative: SpeedLimits,
+    /// Start of the window, included
+    pub from: NaiveTime,
+    /// End of the window, excluded. Windows ending before they start span midnight.
+    pub to: NaiveTime,
+}
+
+impl SpeedSchedule {
+    pub fn is_alternative(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+
+    /// Limits in effect at `time`
+    pub fn limits_at(&self, time: NaiveTime) -> SpeedLimits {
+        match self.is_alternative(time) {
+            true => self.alternative,
+            false => self.normal,
+        }
+    }
+
+    /// Set the limits of `bandwidth` to the ones in effect at `time`
+    pub fn apply(&self, bandwidth: &Bandwidth, time: NaiveTime) {
+        bandwidth.set_limits(self.limits_at(time));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn schedule(from: NaiveTime, to: NaiveTime) -> SpeedSchedule {
+        SpeedSchedule {
+            normal: SpeedLimits::default(),
+            alternative: SpeedLimits {
+                upload: Some(10),
+                download: Some(20),
+            },
+            from,
+            to,
+        }
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let schedule = schedule(time(9, 0), time(17, 0));
+
+        assert!(!schedule.is_alternative(time(8, 59)));
+        assert!(schedule.is_alternative(time(9, 0)));
+        assert!(!schedule.is_alternative(time(17, 0)));
+        assert_eq!(schedule.limits_at(time(12, 0)).download, Some(20));
+    }
+
+    #[test]
+    fn window_spanning_midnight() {
+        let schedule = schedule(time(22, 0), time(6, 0));
+
+        assert!(schedule.is_alternative(time(23, 0)));
+        assert!(schedule.is_alternative(time(1, 0)));
+        assert!(!schedule.is_alternative(time(12, 0)));
+        assert_eq!(schedule.limits_at(time(12, 0)), SpeedLimits::default());
+    }
+}
@@ -0,0 +1,23 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DhtError {
+    #[error("Failed to bind the DHT socket: {0}")]
+    Bind(io::Error),
+    #[error("DHT socket error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to encode KRPC message: {0}")]
+    Encode(bendy::serde::Error),
+    #[error("Invalid KRPC message: {0}")]
+    InvalidMessage(String),
+    #[error("Node responded with error {code}: {message}")]
+    Remote { code: i64, message: String },
+    #[error("Node did not respond in time")]
+    Timeout,
+    #[error("No DHT node is reachable")]
+    NoNodes,
+    #[error("Invalid DHT state file: {0}")]
+    InvalidState(String),
+}
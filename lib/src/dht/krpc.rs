@@ -0,0 +1,364 @@
+//! KRPC messages: bencoded dictionaries sent over UDP, either a query, a response or an error
+//! matched to their query by the transaction ID `t`.
+
+use std::net::{IpAddr, SocketAddr};
+
+use bendy::serde::{from_bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+
+use crate::torrent::InfoHash;
+
+use super::{
+    errors::DhtError,
+    node::{NodeId, NodeInfo},
+};
+
+pub(super) const ERROR_GENERIC: i64 = 201;
+pub(super) const ERROR_PROTOCOL: i64 = 203;
+pub(super) const ERROR_METHOD_UNKNOWN: i64 = 204;
+
+const PING: &str = "ping";
+const FIND_NODE: &str = "find_node";
+const GET_PEERS: &str = "get_peers";
+const ANNOUNCE_PEER: &str = "announce_peer";
+
+/// Arguments of a query and values of a response, absent values being `0` or empty
+#[serde_as]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(super) struct Body {
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    id: Vec<u8>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    implied_port: u8,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    info_hash: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nodes: Vec<u8>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    port: u16,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    target: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    token: Vec<u8>,
+    /// Peers in the compact form
+    #[serde_as(as = "Vec<Bytes>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    values: Vec<Vec<u8>>,
+}
+
+fn is_zero<T: Default + PartialEq>(v: &T) -> bool {
+    *v == T::default()
+}
+
+impl Body {
+    fn is_empty(&self) -> bool {
+        self.id.is_empty()
+    }
+}
+
+/// Error code and message
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(super) struct ErrorBody(i64, String);
+
+impl ErrorBody {
+    fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Keys are declared in bencode order
+#[serde_as]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(super) struct KrpcMessage {
+    #[serde(default, skip_serializing_if = "Body::is_empty")]
+    a: Body,
+    #[serde(default, skip_serializing_if = "ErrorBody::is_empty")]
+    e: ErrorBody,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    q: String,
+    #[serde(default, skip_serializing_if = "Body::is_empty")]
+    r: Body,
+    /// Transaction ID
+    #[serde_as(as = "Bytes")]
+    pub t: Vec<u8>,
+    /// Client name and version
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    v: Vec<u8>,
+    /// Message type: `q`, `r` or `e`
+    pub y: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Query {
+    Ping,
+    FindNode {
+        target: NodeId,
+    },
+    GetPeers {
+        info_hash: InfoHash,
+    },
+    AnnouncePeer {
+        info_hash: InfoHash,
+        port: u16,
+        /// Use the source port of the query instead of `port`
+        implied_port: bool,
+        token: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Response {
+    pub id: NodeId,
+    /// Closest nodes to the target of `find_node` and `get_peers`
+    pub nodes: Vec<NodeInfo>,
+    /// Peers of the info hash of `get_peers`
+    pub values: Vec<SocketAddr>,
+    /// Token to send with `announce_peer`, answering `get_peers`
+    pub token: Vec<u8>,
+}
+
+impl KrpcMessage {
+    pub fn query(t: Vec<u8>, id: NodeId, query: &Query) -> Self {
+        let mut a = Body {
+            id: id.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        let q = match query {
+            Query::Ping => PING,
+            Query::FindNode { target } => {
+                a.target = target.as_bytes().to_vec();
+                FIND_NODE
+            }
+            Query::GetPeers { info_hash } => {
+                a.info_hash = info_hash.as_bytes().to_vec();
+                GET_PEERS
+            }
+            Query::AnnouncePeer {
+                info_hash,
+                port,
+                implied_port,
+                token,
+            } => {
+                a.info_hash = info_hash.as_bytes().to_vec();
+                a.port = *port;
+                a.implied_port = *implied_port as u8;
+                a.token = token.clone();
+                ANNOUNCE_PEER
+            }
+        };
+
+        Self {
+            a,
+            q: q.to_string(),
+            t,
+            v: super::CLIENT_VERSION.to_vec(),
+            y: "q".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn response(t: Vec<u8>, rsp: &Response) -> Self {
+        let r = Body {
+            id: rsp.id.as_bytes().to_vec(),
+            nodes: NodeInfo::to_compact(&rsp.nodes),
+            token: rsp.token.clone(),
+            values: rsp.values.iter().map(compact_peer).collect(),
+            ..Default::default()
+        };
+
+        Self {
+            r,
+            t,
+            v: super::CLIENT_VERSION.to_vec(),
+            y: "r".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn error(t: Vec<u8>, code: i64, message: &str) -> Self {
+        Self {
+            e: ErrorBody(code, message.to_string()),
+            t,
+            y: "e".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DhtError> {
+        from_bytes(bytes).map_err(|e| DhtError::InvalidMessage(e.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DhtError> {
+        to_bytes(self).map_err(DhtError::Encode)
+    }
+
+    /// Sender ID and query. Invalid queries fail with the error code and message to answer.
+    pub fn parse_query(&self) -> Result<(NodeId, Query), (i64, String)> {
+        let id = NodeId::from_bytes(&self.a.id)
+            .ok_or((ERROR_PROTOCOL, "invalid or missing id".to_string()))?;
+        let info_hash = || {
+            InfoHash::try_from(self.a.info_hash.as_slice())
+                .map_err(|_| (ERROR_PROTOCOL, "invalid or missing info_hash".to_string()))
+        };
+        let query = match self.q.as_str() {
+            PING => Query::Ping,
+            FIND_NODE => Query::FindNode {
+                target: NodeId::from_bytes(&self.a.target)
+                    .ok_or((ERROR_PROTOCOL, "invalid or missing target".to_string()))?,
+            },
+            GET_PEERS => Query::GetPeers {
+                info_hash: info_hash()?,
+            },
+            ANNOUNCE_PEER => Query::AnnouncePeer {
+                info_hash: info_hash()?,
+                port: self.a.port,
+                implied_port: self.a.implied_port != 0,
+                token: self.a.token.clone(),
+            },
+            _ => return Err((ERROR_METHOD_UNKNOWN, "Method Unknown".to_string())),
+        };
+
+        Ok((id, query))
+    }
+
+    /// Response to a query, or the error sent instead
+    pub fn parse_response(&self) -> Result<Response, DhtError> {
+        if self.y == "e" {
+            return Err(DhtError::Remote {
+                code: self.e.0,
+                message: self.e.1.clone(),
+            });
+        }
+        let id = NodeId::from_bytes(&self.r.id)
+            .ok_or_else(|| DhtError::InvalidMessage("invalid or missing id".into()))?;
+
+        Ok(Response {
+            id,
+            nodes: NodeInfo::parse_compact(&self.r.nodes),
+            values: self.r.values.iter().filter_map(|v| parse_peer(v)).collect(),
+            token: self.r.token.clone(),
+        })
+    }
+}
+
+/// Peer in the compact form: the address followed by the port, BigEndian
+fn compact_peer(addr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend(addr.port().to_be_bytes());
+
+    bytes
+}
+
+fn parse_peer(bytes: &[u8]) -> Option<SocketAddr> {
+    let ip = match bytes.len() {
+        6 => IpAddr::from(<[u8; 4]>::try_from(&bytes[..4]).ok()?),
+        18 => IpAddr::from(<[u8; 16]>::try_from(&bytes[..16]).ok()?),
+        _ => return None,
+    };
+    let port = u16::from_be_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]);
+
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(msg: &KrpcMessage) -> KrpcMessage {
+        KrpcMessage::from_bytes(&msg.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parse_bep5_ping() {
+        let msg =
+            KrpcMessage::from_bytes(b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe")
+                .unwrap();
+
+        assert_eq!(msg.t, b"aa");
+        assert_eq!(
+            msg.parse_query(),
+            Ok((NodeId(*b"abcdefghij0123456789"), Query::Ping))
+        );
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let id = NodeId([1; 20]);
+        let queries = [
+            Query::Ping,
+            Query::FindNode {
+                target: NodeId([2; 20]),
+            },
+            Query::GetPeers {
+                info_hash: InfoHash([3; 20]),
+            },
+            Query::AnnouncePeer {
+                info_hash: InfoHash([3; 20]),
+                port: 6881,
+                implied_port: true,
+                token: b"token".to_vec(),
+            },
+        ];
+        for query in queries {
+            let msg = round_trip(&KrpcMessage::query(vec![0, 1], id, &query));
+
+            assert_eq!(msg.y, "q");
+            assert_eq!(msg.t, [0, 1]);
+            assert_eq!(msg.parse_query(), Ok((id, query)));
+        }
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let rsp = Response {
+            id: NodeId([1; 20]),
+            nodes: vec![NodeInfo {
+                id: NodeId([2; 20]),
+                addr: "1.2.3.4:5".parse().unwrap(),
+            }],
+            values: vec!["6.7.8.9:10".parse().unwrap(), "[::1]:11".parse().unwrap()],
+            token: b"token".to_vec(),
+        };
+        let parsed = round_trip(&KrpcMessage::response(vec![0], &rsp))
+            .parse_response()
+            .unwrap();
+
+        assert_eq!(parsed.id, rsp.id);
+        assert_eq!(parsed.nodes, rsp.nodes);
+        assert_eq!(parsed.values, rsp.values);
+        assert_eq!(parsed.token, rsp.token);
+    }
+
+    #[test]
+    fn error_round_trip() {
+        let msg = round_trip(&KrpcMessage::error(vec![0], ERROR_GENERIC, "oops"));
+
+        assert!(matches!(
+            msg.parse_response(),
+            Err(DhtError::Remote { code: ERROR_GENERIC, message }) if message == "oops"
+        ));
+    }
+
+    #[test]
+    fn invalid_queries_get_an_error_code() {
+        let mut msg = KrpcMessage::query(vec![0], NodeId([1; 20]), &Query::Ping);
+        msg.q = "vote".to_string();
+        assert_eq!(msg.parse_query().unwrap_err().0, ERROR_METHOD_UNKNOWN);
+
+        msg.q = GET_PEERS.to_string();
+        assert_eq!(msg.parse_query().unwrap_err().0, ERROR_PROTOCOL);
+
+        msg.a.id.pop();
+        assert_eq!(msg.parse_query().unwrap_err().0, ERROR_PROTOCOL);
+    }
+}
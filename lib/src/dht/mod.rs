@@ -0,0 +1,718 @@
+//! Mainline DHT, as defined by [BEP 0005](https://www.bittorrent.org/beps/bep_0005.html).
+//!
+//! Nodes form a Kademlia network over UDP, storing the peers of the info hashes closest to
+//! their ID. It lets peers find each other without any tracker.
+//!
+//! ```
+//! use brs::{dht::{Dht, DhtConfig}, torrent::InfoHash};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let config = DhtConfig::new("127.0.0.1:0".parse().unwrap());
+//! let router = Dht::bind(config.clone()).await.unwrap();
+//! let config = DhtConfig {
+//!     bootstrap: vec![router.local_addr().unwrap().to_string()],
+//!     ..config
+//! };
+//! let seeder = Dht::bind(config.clone()).await.unwrap();
+//! let leecher = Dht::bind(config).await.unwrap();
+//! seeder.bootstrap().await.unwrap();
+//! leecher.bootstrap().await.unwrap();
+//!
+//! let info_hash = InfoHash::from([1; 20]);
+//! seeder.announce(info_hash, Some(6881)).await.unwrap();
+//! let peers = leecher.get_peers(info_hash).await.unwrap();
+//! assert_eq!(peers[0].port, 6881);
+//! # }
+//! ```
+
+mod errors;
+mod krpc;
+mod node;
+mod peers;
+mod routing;
+mod token;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bendy::serde::{from_bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+use tokio::{
+    net,
+    net::UdpSocket,
+    sync::oneshot,
+    task::{JoinHandle, JoinSet},
+    time,
+};
+
+use crate::{torrent::InfoHash, tracker::Peer};
+
+use krpc::{KrpcMessage, Query, Response, ERROR_GENERIC, ERROR_PROTOCOL};
+use peers::PeerStore;
+use routing::{RoutingTable, K};
+use token::Tokens;
+
+pub use errors::DhtError;
+pub use node::{NodeId, NodeInfo};
+
+/// Nodes contacted to join the network by default
+pub const DEFAULT_BOOTSTRAP: [&str; 3] = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+/// Default port of the DHT socket
+pub const DEFAULT_PORT: u16 = 6881;
+/// Client `BR`, version 0.1
+const CLIENT_VERSION: &[u8] = b"BR\x00\x01";
+/// Number of queries in flight during a lookup
+const ALPHA: usize = 3;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_PACKET_LEN: usize = 65535;
+/// Interval between two rounds of pinging questionable nodes and refreshing buckets
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+/// Buckets not changed for this long are refreshed
+const BUCKET_REFRESH: Duration = Duration::from_secs(15 * 60);
+/// Maximum number of buckets refreshed in a maintenance round
+const MAX_REFRESH: usize = 3;
+
+/// Settings of a [`Dht`] node
+#[derive(Debug, Clone)]
+pub struct DhtConfig {
+    /// Address of the UDP socket
+    pub bind: SocketAddr,
+    /// ID of the node. Defaults to the one saved in `state_file`, or a random one.
+    pub id: Option<NodeId>,
+    /// Nodes contacted to join the network, as `host:port`
+    pub bootstrap: Vec<String>,
+    /// File keeping the node ID and the routing table between runs
+    pub state_file: Option<PathBuf>,
+    /// Time to wait for a response
+    pub timeout: Duration,
+}
+
+impl DhtConfig {
+    pub fn new(bind: SocketAddr) -> Self {
+        Self {
+            bind,
+            id: None,
+            bootstrap: DEFAULT_BOOTSTRAP.iter().map(|v| v.to_string()).collect(),
+            state_file: None,
+            timeout: QUERY_TIMEOUT,
+        }
+    }
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self::new((Ipv4Addr::UNSPECIFIED, DEFAULT_PORT).into())
+    }
+}
+
+/// State saved in [`DhtConfig::state_file`]
+#[serde_as]
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SavedState {
+    #[serde_as(as = "Bytes")]
+    id: Vec<u8>,
+    /// Nodes of the routing table in the compact form
+    #[serde_as(as = "Bytes")]
+    nodes: Vec<u8>,
+}
+
+/// Dht is a running DHT node. It answers queries from other nodes in the background until
+/// dropped or [shut down](Dht::shutdown).
+#[derive(Debug)]
+pub struct Dht {
+    node: Arc<Node>,
+    bootstrap: Vec<String>,
+    state_file: Option<PathBuf>,
+    receiver: JoinHandle<()>,
+    maintenance: JoinHandle<()>,
+}
+
+/// State shared by the API and the background tasks
+#[derive(Debug)]
+struct Node {
+    id: NodeId,
+    socket: UdpSocket,
+    timeout: Duration,
+    routing: Mutex<RoutingTable>,
+    /// Queries waiting for a response, by transaction ID, with the queried address
+    pending: Mutex<HashMap<u16, PendingQuery>>,
+    next_transaction: AtomicU16,
+    tokens: Mutex<Tokens>,
+    peers: Mutex<PeerStore>,
+}
+
+/// Queried address and the channel receiving the response
+type PendingQuery = (SocketAddr, oneshot::Sender<Result<Response, DhtError>>);
+
+/// Outcome of an iterative lookup
+#[derive(Debug, Default)]
+struct Lookup {
+    /// Closest nodes that responded, with the token they sent
+    nodes: Vec<(NodeInfo, Vec<u8>)>,
+    peers: Vec<SocketAddr>,
+}
+
+/// State of a node during a lookup
+#[derive(Debug, Clone, PartialEq)]
+enum Candidate {
+    Waiting,
+    Querying,
+    Responded(Vec<u8>),
+    Failed,
+}
+
+impl Dht {
+    /// Bind the UDP socket and start answering queries, restoring the routing table from
+    /// [`DhtConfig::state_file`]. Missing or invalid state is ignored.
+    /// Call [`Dht::bootstrap`] to join the network.
+    pub async fn bind(config: DhtConfig) -> Result<Self, DhtError> {
+        let saved = config.state_file.as_deref().and_then(load_state);
+        let id = config
+            .id
+            .or_else(|| saved.as_ref().and_then(|s| NodeId::from_bytes(&s.id)))
+            .unwrap_or_else(NodeId::generate);
+        let mut routing = RoutingTable::new(id);
+        for info in saved.iter().flat_map(|s| NodeInfo::parse_compact(&s.nodes)) {
+            routing.insert(info);
+        }
+
+        let socket = UdpSocket::bind(config.bind).await.map_err(DhtError::Bind)?;
+        let node = Arc::new(Node {
+            id,
+            socket,
+            timeout: config.timeout,
+            routing: Mutex::new(routing),
+            pending: Mutex::new(HashMap::new()),
+            next_transaction: AtomicU16::new(rand::random()),
+            tokens: Mutex::new(Tokens::new()),
+            peers: Mutex::new(PeerStore::default()),
+        });
+
+        Ok(Self {
+            receiver: tokio::spawn(Arc::clone(&node).receive()),
+            maintenance: tokio::spawn(maintain(
+                Arc::clone(&node),
+                config.bootstrap.clone(),
+                config.state_file.clone(),
+            )),
+            node,
+            bootstrap: config.bootstrap,
+            state_file: config.state_file,
+        })
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.node.id
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, DhtError> {
+        Ok(self.node.socket.local_addr()?)
+    }
+
+    /// Nodes of the routing table, bad nodes excluded
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.node.routing().nodes()
+    }
+
+    /// Join the network through the bootstrap nodes and the nodes already known, filling
+    /// the routing table with the nodes closest to ours. Returns the number of known nodes.
+    pub async fn bootstrap(&self) -> Result<usize, DhtError> {
+        self.node.bootstrap(&self.bootstrap).await
+    }
+
+    /// Check that the node at `addr` is alive, adding it to the routing table
+    pub async fn ping(&self, addr: SocketAddr) -> Result<NodeId, DhtError> {
+        Ok(self.node.query(addr, Query::Ping).await?.id)
+    }
+
+    /// Look up the peers of a torrent
+    pub async fn get_peers(&self, info_hash: InfoHash) -> Result<Vec<Peer>, DhtError> {
+        let lookup = self.node.lookup_peers(info_hash).await?;
+        Ok(to_peers(lookup.peers))
+    }
+
+    /// Look up the peers of a torrent, and announce that we accept connections on `port`
+    /// to the closest nodes. When `port` is `None`, the source port of the DHT socket is
+    /// announced instead.
+    pub async fn announce(
+        &self,
+        info_hash: InfoHash,
+        port: Option<u16>,
+    ) -> Result<Vec<Peer>, DhtError> {
+        let lookup = self.node.lookup_peers(info_hash).await?;
+        let mut tasks = JoinSet::new();
+        for (info, token) in lookup.nodes.into_iter().filter(|(_, t)| !t.is_empty()) {
+            let query = Query::AnnouncePeer {
+                info_hash,
+                port: port.unwrap_or_default(),
+                implied_port: port.is_none(),
+                token,
+            };
+            let node = Arc::clone(&self.node);
+            tasks.spawn(async move { node.query(info.addr, query).await });
+        }
+        while tasks.join_next().await.is_some() {}
+
+        Ok(to_peers(lookup.peers))
+    }
+
+    /// Write the node ID and the routing table to [`DhtConfig::state_file`], if set
+    pub fn save_state(&self) -> Result<(), DhtError> {
+        match &self.state_file {
+            Some(path) => self.node.save_state(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop answering queries, saving the state first
+    pub fn shutdown(self) -> Result<(), DhtError> {
+        self.save_state()
+    }
+}
+
+impl Drop for Dht {
+    fn drop(&mut self) {
+        self.receiver.abort();
+        self.maintenance.abort();
+    }
+}
+
+impl Node {
+    fn routing(&self) -> std::sync::MutexGuard<'_, RoutingTable> {
+        self.routing
+            .lock()
+            .expect("routing table lock should not be poisoned")
+    }
+
+    /// Send a query and wait for its response. Unanswered queries count as failures of the
+    /// queried node.
+    async fn query(&self, addr: SocketAddr, query: Query) -> Result<Response, DhtError> {
+        let transaction = self.next_transaction.fetch_add(1, Ordering::Relaxed);
+        let msg = KrpcMessage::query(transaction.to_be_bytes().to_vec(), self.id, &query);
+        let bytes = msg.to_bytes()?;
+
+        let (tx, rx) = oneshot::channel();
+        let pending = || {
+            self.pending
+                .lock()
+                .expect("pending queries lock should not be poisoned")
+        };
+        pending().insert(transaction, (addr, tx));
+        if let Err(e) = self.socket.send_to(&bytes, addr).await {
+            pending().remove(&transaction);
+            return Err(e.into());
+        }
+        let rsp = time::timeout(self.timeout, rx).await;
+        pending().remove(&transaction);
+
+        match rsp {
+            Ok(Ok(Ok(rsp))) => {
+                self.routing().insert(NodeInfo { id: rsp.id, addr });
+                Ok(rsp)
+            }
+            Ok(Ok(Err(e))) => Err(e),
+            _ => {
+                self.routing().failed(&addr);
+                Err(DhtError::Timeout)
+            }
+        }
+    }
+
+    /// Receive messages until the socket is closed
+    async fn receive(self: Arc<Self>) {
+        let mut buf = vec![0u8; MAX_PACKET_LEN];
+        loop {
+            // Errors such as ICMP port unreachable only concern a single message
+            let (len, addr) = match self.socket.recv_from(&mut buf).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let msg = match KrpcMessage::from_bytes(&buf[..len]) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            match msg.y.as_str() {
+                "q" => self.handle_query(msg, addr).await,
+                "r" | "e" => self.handle_response(msg, addr),
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_response(&self, msg: KrpcMessage, addr: SocketAddr) {
+        let transaction = match <[u8; 2]>::try_from(msg.t.as_slice()) {
+            Ok(v) => u16::from_be_bytes(v),
+            Err(_) => return,
+        };
+        let mut pending = self
+            .pending
+            .lock()
+            .expect("pending queries lock should not be poisoned");
+        // Responses must come from the queried address
+        if pending.get(&transaction).is_some_and(|(a, _)| *a == addr) {
+            let (_, tx) = pending
+                .remove(&transaction)
+                .expect("pending query should exist");
+            let _ = tx.send(msg.parse_response());
+        }
+    }
+
+    async fn handle_query(&self, msg: KrpcMessage, addr: SocketAddr) {
+        let rsp = msg.parse_query().and_then(|(id, query)| {
+            self.routing().insert(NodeInfo { id, addr });
+            self.answer(query, addr)
+        });
+        let rsp = match rsp {
+            Ok(rsp) => KrpcMessage::response(msg.t, &rsp),
+            Err((code, message)) => KrpcMessage::error(msg.t, code, &message),
+        };
+        if let Ok(bytes) = rsp.to_bytes() {
+            let _ = self.socket.send_to(&bytes, addr).await;
+        }
+    }
+
+    fn answer(&self, query: Query, addr: SocketAddr) -> Result<Response, (i64, String)> {
+        let mut rsp = Response {
+            id: self.id,
+            ..Default::default()
+        };
+        match query {
+            Query::Ping => {}
+            Query::FindNode { target } => rsp.nodes = self.routing().closest(&target, K),
+            Query::GetPeers { info_hash } => {
+                rsp.token = self
+                    .tokens
+                    .lock()
+                    .expect("tokens lock should not be poisoned")
+                    .generate(addr.ip());
+                rsp.values = self
+                    .peers
+                    .lock()
+                    .expect("peer store lock should not be poisoned")
+                    .get(&info_hash);
+                if rsp.values.is_empty() {
+                    rsp.nodes = self.routing().closest(&info_hash.into(), K);
+                }
+            }
+            Query::AnnouncePeer {
+                info_hash,
+                port,
+                implied_port,
+                token,
+            } => {
+                let valid = self
+                    .tokens
+                    .lock()
+                    .expect("tokens lock should not be poisoned")
+                    .validate(&token, addr.ip());
+                if !valid {
+                    return Err((ERROR_PROTOCOL, "Bad token".to_string()));
+                }
+                let port = if implied_port { addr.port() } else { port };
+                if port == 0 {
+                    return Err((ERROR_GENERIC, "Invalid port".to_string()));
+                }
+                self.peers
+                    .lock()
+                    .expect("peer store lock should not be poisoned")
+                    .add(info_hash, SocketAddr::new(addr.ip(), port));
+            }
+        }
+
+        Ok(rsp)
+    }
+
+    async fn bootstrap(self: &Arc<Self>, hosts: &[String]) -> Result<usize, DhtError> {
+        let mut tasks = JoinSet::new();
+        for host in hosts {
+            let addrs = match net::lookup_host(host.as_str()).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for addr in addrs.filter(|a| a.is_ipv4()) {
+                let node = Arc::clone(self);
+                let query = Query::FindNode { target: self.id };
+                tasks.spawn(async move { node.query(addr, query).await });
+            }
+        }
+        let mut seeds = vec![];
+        while let Some(res) = tasks.join_next().await {
+            if let Ok(Ok(rsp)) = res {
+                seeds.extend(rsp.nodes);
+            }
+        }
+
+        self.lookup(self.id, None, seeds).await?;
+        match self.routing().len() {
+            0 => Err(DhtError::NoNodes),
+            n => Ok(n),
+        }
+    }
+
+    /// Iteratively query the nodes closest to `target`, starting from the routing table and
+    /// `seeds`, until the `K` closest nodes responded. Peers are looked up along the way
+    /// when `info_hash` is set.
+    async fn lookup(
+        self: &Arc<Self>,
+        target: NodeId,
+        info_hash: Option<InfoHash>,
+        seeds: Vec<NodeInfo>,
+    ) -> Result<Lookup, DhtError> {
+        let known = self.routing().closest(&target, K);
+        if known.is_empty() && seeds.is_empty() {
+            return Err(DhtError::NoNodes);
+        }
+
+        // By distance to the target
+        let mut candidates: BTreeMap<NodeId, (NodeInfo, Candidate)> = BTreeMap::new();
+        for info in known.into_iter().chain(seeds) {
+            if info.id != self.id {
+                candidates.insert(info.id.distance(&target), (info, Candidate::Waiting));
+            }
+        }
+        let mut peers = HashSet::new();
+        let mut tasks = JoinSet::new();
+        loop {
+            while tasks.len() < ALPHA {
+                let next = candidates
+                    .iter_mut()
+                    .filter(|(_, (_, c))| *c != Candidate::Failed)
+                    .take(K)
+                    .find(|(_, (_, c))| *c == Candidate::Waiting);
+                let (distance, (info, state)) = match next {
+                    Some(v) => v,
+                    None => break,
+                };
+                *state = Candidate::Querying;
+                let query = match info_hash {
+                    Some(info_hash) => Query::GetPeers { info_hash },
+                    None => Query::FindNode { target },
+                };
+                let (node, distance, addr) = (Arc::clone(self), *distance, info.addr);
+                tasks.spawn(async move { (distance, node.query(addr, query).await) });
+            }
+
+            let (distance, res) = match tasks.join_next().await {
+                Some(Ok(v)) => v,
+                Some(Err(_)) => continue,
+                None => break,
+            };
+            let state = match candidates.get_mut(&distance) {
+                Some((_, state)) => state,
+                None => continue,
+            };
+            let rsp = match res {
+                Ok(v) => v,
+                Err(_) => {
+                    *state = Candidate::Failed;
+                    continue;
+                }
+            };
+            *state = Candidate::Responded(rsp.token);
+            peers.extend(rsp.values.into_iter().filter(|p| p.port() != 0));
+            for info in rsp.nodes {
+                if info.id != self.id && info.addr.port() != 0 {
+                    candidates
+                        .entry(info.id.distance(&target))
+                        .or_insert((info, Candidate::Waiting));
+                }
+            }
+        }
+
+        Ok(Lookup {
+            nodes: candidates
+                .into_values()
+                .filter_map(|(info, c)| match c {
+                    Candidate::Responded(token) => Some((info, token)),
+                    _ => None,
+                })
+                .take(K)
+                .collect(),
+            peers: peers.into_iter().collect(),
+        })
+    }
+
+    /// Look up the peers of a torrent, including the ones announced to this node
+    async fn lookup_peers(self: &Arc<Self>, info_hash: InfoHash) -> Result<Lookup, DhtError> {
+        let mut lookup = self
+            .lookup(info_hash.into(), Some(info_hash), vec![])
+            .await?;
+        let stored = self
+            .peers
+            .lock()
+            .expect("peer store lock should not be poisoned")
+            .get(&info_hash);
+        for addr in stored {
+            if !lookup.peers.contains(&addr) {
+                lookup.peers.push(addr);
+            }
+        }
+
+        Ok(lookup)
+    }
+
+    /// A temporary file is renamed over the previous one, so an interrupted save never
+    /// leaves a truncated file behind
+    fn save_state(&self, path: &Path) -> Result<(), DhtError> {
+        let state = SavedState {
+            id: self.id.as_bytes().to_vec(),
+            nodes: NodeInfo::to_compact(&self.routing().nodes()),
+        };
+        let bytes = to_bytes(&state).map_err(DhtError::Encode)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, bytes).and_then(|_| fs::rename(&tmp, path))?;
+
+        Ok(())
+    }
+}
+
+fn load_state(path: &Path) -> Option<SavedState> {
+    let bytes = fs::read(path).ok()?;
+    from_bytes(&bytes).ok()
+}
+
+/// Ping questionable nodes, refresh stale buckets and bootstrap again when few nodes are
+/// known, saving the state after each round
+async fn maintain(node: Arc<Node>, bootstrap: Vec<String>, state_file: Option<PathBuf>) {
+    let mut interval = time::interval(MAINTENANCE_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        node.peers
+            .lock()
+            .expect("peer store lock should not be poisoned")
+            .expire();
+
+        let (questionable, targets) = {
+            let routing = node.routing();
+            (
+                routing.questionable(),
+                routing.refresh_targets(BUCKET_REFRESH),
+            )
+        };
+        let mut tasks = JoinSet::new();
+        for info in questionable {
+            let node = Arc::clone(&node);
+            tasks.spawn(async move { node.query(info.addr, Query::Ping).await });
+        }
+        while tasks.join_next().await.is_some() {}
+
+        if node.routing().len() < K {
+            let _ = node.bootstrap(&bootstrap).await;
+        }
+        for target in targets.into_iter().take(MAX_REFRESH) {
+            let _ = node.lookup(target, None, vec![]).await;
+        }
+        if let Some(path) = &state_file {
+            let _ = node.save_state(path);
+        }
+    }
+}
+
+fn to_peers(addrs: Vec<SocketAddr>) -> Vec<Peer> {
+    addrs
+        .into_iter()
+        .map(|addr| Peer {
+            id: None,
+            ip: addr.ip(),
+            port: addr.port(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn config() -> DhtConfig {
+        DhtConfig {
+            bootstrap: vec![],
+            timeout: Duration::from_millis(500),
+            ..DhtConfig::new((Ipv4Addr::LOCALHOST, 0).into())
+        }
+    }
+
+    /// Bind `count` nodes bootstrapped through the first one
+    async fn network(count: usize) -> Vec<Dht> {
+        let router = Dht::bind(config()).await.unwrap();
+        let config = DhtConfig {
+            bootstrap: vec![router.local_addr().unwrap().to_string()],
+            ..config()
+        };
+        let mut nodes = vec![router];
+        for _ in 1..count {
+            let node = Dht::bind(config.clone()).await.unwrap();
+            node.bootstrap().await.unwrap();
+            nodes.push(node);
+        }
+
+        nodes
+    }
+
+    #[tokio::test]
+    async fn lookup_across_nodes() {
+        let nodes = network(12).await;
+        let info_hash = InfoHash::from([0x5a; 20]);
+
+        let announced = nodes[3].announce(info_hash, Some(7000)).await.unwrap();
+        assert!(announced.is_empty());
+        for node in &nodes[4..] {
+            // Bootstrapping later nodes filled the routing tables of the earlier ones
+            assert!(node.nodes().len() > 1);
+
+            let peers = node.get_peers(info_hash).await.unwrap();
+            assert!(peers
+                .iter()
+                .any(|p| p.ip == Ipv4Addr::LOCALHOST && p.port == 7000));
+        }
+    }
+
+    #[tokio::test]
+    async fn bootstrap_fails_without_nodes() {
+        let node = Dht::bind(config()).await.unwrap();
+
+        assert!(matches!(node.bootstrap().await, Err(DhtError::NoNodes)));
+        assert!(matches!(
+            node.get_peers(InfoHash::from([1; 20])).await,
+            Err(DhtError::NoNodes)
+        ));
+    }
+
+    #[tokio::test]
+    async fn state_is_restored() {
+        let path = env::temp_dir().join(format!("brs-dht-state-{}", std::process::id()));
+        let nodes = network(3).await;
+        let config = DhtConfig {
+            state_file: Some(path.clone()),
+            ..config()
+        };
+        let node = Dht::bind(config.clone()).await.unwrap();
+        node.ping(nodes[1].local_addr().unwrap()).await.unwrap();
+        let (id, known) = (node.id(), node.nodes());
+        node.shutdown().unwrap();
+
+        let node = Dht::bind(config).await.unwrap();
+        assert_eq!(node.id(), id);
+        assert_eq!(node.nodes(), known);
+        fs::remove_file(path).unwrap();
+    }
+}
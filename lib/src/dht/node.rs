@@ -0,0 +1,140 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+};
+
+use rand::RngCore;
+
+use crate::torrent::InfoHash;
+
+/// Length of a node in the compact form: ID, IPv4 address and port
+pub(super) const COMPACT_NODE_LEN: usize = 26;
+
+/// NodeId is the 160 bits identifier of a DHT node, sharing the space of info hashes.
+/// Nodes store the peers of the info hashes closest to their ID, by XOR distance.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub [u8; 20]);
+
+impl NodeId {
+    pub fn generate() -> Self {
+        let mut id = [0u8; 20];
+        rand::thread_rng().fill_bytes(&mut id);
+
+        Self(id)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        <[u8; 20]>::try_from(bytes).ok().map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// XOR distance to `other`. Distances compare as big endian numbers.
+    pub fn distance(&self, other: &NodeId) -> NodeId {
+        let mut d = [0u8; 20];
+        for (i, b) in d.iter_mut().enumerate() {
+            *b = self.0[i] ^ other.0[i];
+        }
+
+        Self(d)
+    }
+
+    /// Number of leading bits shared with `other`, `160` when equal
+    pub fn common_prefix(&self, other: &NodeId) -> usize {
+        let d = self.distance(other);
+        d.0.iter()
+            .position(|b| *b != 0)
+            .map_or(160, |i| i * 8 + d.0[i].leading_zeros() as usize)
+    }
+
+    /// Random ID sharing exactly `prefix` leading bits with this one
+    pub(super) fn random_with_prefix(&self, prefix: usize) -> Self {
+        let mut id = Self::generate();
+        for bit in 0..prefix.min(160) {
+            set_bit(&mut id.0, bit, get_bit(&self.0, bit));
+        }
+        if prefix < 160 {
+            set_bit(&mut id.0, prefix, !get_bit(&self.0, prefix));
+        }
+
+        id
+    }
+}
+
+fn get_bit(bytes: &[u8; 20], bit: usize) -> bool {
+    bytes[bit / 8] & (0x80 >> (bit % 8)) != 0
+}
+
+fn set_bit(bytes: &mut [u8; 20], bit: usize, value: bool) {
+    if value {
+        bytes[bit / 8] |= 0x80 >> (bit % 8);
+    } else {
+        bytes[bit / 8] &= !(0x80 >> (bit % 8));
+    }
+}
+
+impl From<[u8; 20]> for NodeId {
+    fn from(v: [u8; 20]) -> Self {
+        Self(v)
+    }
+}
+
+impl From<InfoHash> for NodeId {
+    fn from(v: InfoHash) -> Self {
+        Self(v.0)
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({self})")
+    }
+}
+
+/// NodeInfo is the contact of a DHT node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+}
+
+impl NodeInfo {
+    /// Parse nodes in the compact form. IPv6 nodes can't be represented and incomplete
+    /// entries are ignored.
+    pub fn parse_compact(bytes: &[u8]) -> Vec<NodeInfo> {
+        bytes
+            .chunks_exact(COMPACT_NODE_LEN)
+            .map(|c| {
+                let id = NodeId::from_bytes(&c[..20]).expect("node chunk should hold an ID");
+                let ip = Ipv4Addr::new(c[20], c[21], c[22], c[23]);
+                let port = u16::from_be_bytes([c[24], c[25]]);
+                NodeInfo {
+                    id,
+                    addr: SocketAddr::V4(SocketAddrV4::new(ip, port)),
+                }
+            })
+            .collect()
+    }
+
+    /// Encode IPv4 nodes in the compact form, others are skipped
+    pub fn to_compact<'a>(nodes: impl IntoIterator<Item = &'a NodeInfo>) -> Vec<u8> {
+        let mut bytes = vec![];
+        for node in nodes {
+            if let IpAddr::V4(ip) = node.addr.ip() {
+                bytes.extend(node.id.as_bytes());
+                bytes.extend(ip.octets());
+                bytes.extend(node.addr.port().to_be_bytes());
+            }
+        }
+
+        bytes
+    }
+}
@@ -0,0 +1,54 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use rand::seq::IteratorRandom;
+
+use crate::torrent::InfoHash;
+
+/// Announced peers are forgotten after this long without a new announce
+const PEER_TTL: Duration = Duration::from_secs(30 * 60);
+/// Maximum number of peers returned by `get_peers`, keeping responses in a UDP packet
+const MAX_VALUES: usize = 100;
+const MAX_PEERS_PER_HASH: usize = 1000;
+const MAX_HASHES: usize = 10000;
+
+/// PeerStore keeps the peers announced to a node
+#[derive(Debug, Default)]
+pub(super) struct PeerStore {
+    peers: HashMap<InfoHash, HashMap<SocketAddr, Instant>>,
+}
+
+impl PeerStore {
+    /// Record a peer. Ignored once the store is full.
+    pub fn add(&mut self, info_hash: InfoHash, addr: SocketAddr) {
+        if !self.peers.contains_key(&info_hash) && self.peers.len() >= MAX_HASHES {
+            return;
+        }
+        let peers = self.peers.entry(info_hash).or_default();
+        if peers.contains_key(&addr) || peers.len() < MAX_PEERS_PER_HASH {
+            peers.insert(addr, Instant::now());
+        }
+    }
+
+    /// Up to 100 random peers of the torrent
+    pub fn get(&self, info_hash: &InfoHash) -> Vec<SocketAddr> {
+        self.peers.get(info_hash).map_or(vec![], |peers| {
+            peers
+                .iter()
+                .filter(|(_, at)| at.elapsed() < PEER_TTL)
+                .map(|(addr, _)| *addr)
+                .choose_multiple(&mut rand::thread_rng(), MAX_VALUES)
+        })
+    }
+
+    /// Forget the peers not announced recently
+    pub fn expire(&mut self) {
+        for peers in self.peers.values_mut() {
+            peers.retain(|_, at| at.elapsed() < PEER_TTL);
+        }
+        self.peers.retain(|_, peers| !peers.is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use super::node::{NodeId, NodeInfo};
+
+/// Maximum number of nodes in a bucket, and number of nodes returned by lookups
+pub(super) const K: usize = 8;
+/// A node not heard of for this long is questionable
+const QUESTIONABLE_AFTER: Duration = Duration::from_secs(15 * 60);
+/// A node failing to respond this many times in a row is bad, and gets replaced
+const MAX_FAILURES: u8 = 2;
+
+#[derive(Debug, Clone)]
+struct RoutingNode {
+    info: NodeInfo,
+    last_seen: Instant,
+    /// Queries left unanswered in a row
+    failures: u8,
+}
+
+impl RoutingNode {
+    fn is_bad(&self) -> bool {
+        self.failures >= MAX_FAILURES
+    }
+
+    fn is_good(&self) -> bool {
+        self.failures == 0 && self.last_seen.elapsed() < QUESTIONABLE_AFTER
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    nodes: Vec<RoutingNode>,
+    /// Last time a node was added or heard of
+    changed_at: Option<Instant>,
+}
+
+/// RoutingTable keeps the nodes known by a DHT node, in Kademlia buckets.
+///
+/// Bucket `i` holds the nodes sharing exactly `i` leading bits with our ID, so the table
+/// knows many nodes close to us and a few far away ones.
+#[derive(Debug, Clone)]
+pub(super) struct RoutingTable {
+    id: NodeId,
+    buckets: Vec<Bucket>,
+}
+
+impl RoutingTable {
+    pub fn new(id: NodeId) -> Self {
+        Self {
+            id,
+            buckets: vec![Bucket::default(); 160],
+        }
+    }
+
+    fn bucket_index(&self, id: &NodeId) -> Option<usize> {
+        let prefix = self.id.common_prefix(id);
+        (prefix < 160).then_some(prefix)
+    }
+
+    /// Record a node we heard of, replacing a bad node when its bucket is full.
+    /// Returns `false` when the node doesn't fit in the table.
+    pub fn insert(&mut self, info: NodeInfo) -> bool {
+        let bucket = match self.bucket_index(&info.id) {
+            Some(i) => &mut self.buckets[i],
+            None => return false,
+        };
+        let node = RoutingNode {
+            info,
+            last_seen: Instant::now(),
+            failures: 0,
+        };
+
+        if let Some(existing) = bucket.nodes.iter_mut().find(|n| n.info.id == info.id) {
+            // Another address claiming a known ID is ignored
+            if existing.info.addr != info.addr {
+                return false;
+            }
+            *existing = node;
+        } else if bucket.nodes.len() < K {
+            bucket.nodes.push(node);
+        } else if let Some(bad) = bucket.nodes.iter_mut().find(|n| n.is_bad()) {
+            *bad = node;
+        } else {
+            return false;
+        }
+        bucket.changed_at = Some(Instant::now());
+
+        true
+    }
+
+    /// Record a query left unanswered by the node at `addr`
+    pub fn failed(&mut self, addr: &SocketAddr) {
+        let node = self
+            .buckets
+            .iter_mut()
+            .flat_map(|b| b.nodes.iter_mut())
+            .find(|n| n.info.addr == *addr);
+        if let Some(node) = node {
+            node.failures = node.failures.saturating_add(1);
+        }
+    }
+
+    /// Up to `count` nodes closest to `target`, bad nodes excluded
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<NodeInfo> {
+        let mut nodes: Vec<NodeInfo> = self
+            .buckets
+            .iter()
+            .flat_map(|b| &b.nodes)
+            .filter(|n| !n.is_bad())
+            .map(|n| n.info)
+            .collect();
+        nodes.sort_by_key(|n| n.id.distance(target));
+        nodes.truncate(count);
+
+        nodes
+    }
+
+    /// Every node of the table, bad nodes excluded
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.buckets
+            .iter()
+            .flat_map(|b| &b.nodes)
+            .filter(|n| !n.is_bad())
+            .map(|n| n.info)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.nodes.len()).sum()
+    }
+
+    /// Nodes to ping to know whether they are still alive
+    pub fn questionable(&self) -> Vec<NodeInfo> {
+        self.buckets
+            .iter()
+            .flat_map(|b| &b.nodes)
+            .filter(|n| !n.is_good() && !n.is_bad())
+            .map(|n| n.info)
+            .collect()
+    }
+
+    /// Random targets in the buckets that didn't change for `age`, up to the deepest bucket
+    /// holding nodes. Looking them up refreshes the buckets.
+    pub fn refresh_targets(&self, age: Duration) -> Vec<NodeId> {
+        let deepest = match self.buckets.iter().rposition(|b| !b.nodes.is_empty()) {
+            Some(v) => v,
+            None => return vec![],
+        };
+
+        self.buckets[..=deepest]
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.changed_at.is_none_or(|t| t.elapsed() >= age))
+            .map(|(i, _)| self.id.random_with_prefix(i))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(first: u8, port: u16) -> NodeInfo {
+        let mut id = [0u8; 20];
+        id[0] = first;
+        NodeInfo {
+            id: NodeId(id),
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+        }
+    }
+
+    #[test]
+    fn nodes_go_to_the_bucket_of_their_prefix() {
+        let mut table = RoutingTable::new(NodeId::default());
+
+        assert!(table.insert(node(0x80, 1)));
+        assert!(table.insert(node(0x01, 2)));
+        assert!(!table.insert(node(0, 3)), "our own ID");
+        assert_eq!(table.buckets[0].nodes.len(), 1);
+        assert_eq!(table.buckets[7].nodes.len(), 1);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn full_bucket_only_replaces_bad_nodes() {
+        let mut table = RoutingTable::new(NodeId::default());
+        for i in 0..K as u8 {
+            assert!(table.insert(node(0x80 | i, i as u16 + 1)));
+        }
+        let extra = node(0xff, 100);
+        assert!(!table.insert(extra));
+
+        // A single failure makes the node questionable, not bad
+        let evicted = node(0x83, 4);
+        table.failed(&evicted.addr);
+        assert_eq!(table.questionable(), [evicted]);
+        assert!(!table.insert(extra));
+
+        table.failed(&evicted.addr);
+        assert!(table.insert(extra));
+        assert_eq!(table.len(), K);
+        assert!(!table.nodes().contains(&evicted));
+        assert!(table.nodes().contains(&extra));
+    }
+
+    #[test]
+    fn known_id_keeps_its_address() {
+        let mut table = RoutingTable::new(NodeId::default());
+        let known = node(0x80, 1);
+        table.insert(known);
+        table.failed(&known.addr);
+
+        assert!(!table.insert(node(0x80, 2)));
+        // Hearing from the node again resets its failures
+        assert!(table.insert(known));
+        assert!(table.questionable().is_empty());
+        assert_eq!(table.nodes(), [known]);
+    }
+
+    #[test]
+    fn closest_sorts_by_distance() {
+        let mut table = RoutingTable::new(NodeId::default());
+        for (i, first) in [0x80, 0x40, 0x20, 0x10].into_iter().enumerate() {
+            table.insert(node(first, i as u16 + 1));
+        }
+        table.failed(&node(0x20, 3).addr);
+        table.failed(&node(0x20, 3).addr);
+
+        let closest = table.closest(&node(0x30, 0).id, 2);
+        assert_eq!(closest, [node(0x10, 4), node(0x40, 2)]);
+    }
+
+    #[test]
+    fn refresh_targets_up_to_the_deepest_bucket() {
+        let mut table = RoutingTable::new(NodeId::default());
+        assert!(table.refresh_targets(Duration::ZERO).is_empty());
+        table.insert(node(0x20, 1));
+
+        let targets = table.refresh_targets(Duration::ZERO);
+        let prefixes: Vec<_> = targets
+            .iter()
+            .map(|t| t.common_prefix(&NodeId::default()))
+            .collect();
+        assert_eq!(prefixes, [0, 1, 2]);
+        // Bucket 2 just changed
+        assert_eq!(table.refresh_targets(Duration::from_secs(60)).len(), 2);
+    }
+}
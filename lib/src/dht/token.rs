@@ -0,0 +1,67 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use rand::RngCore;
+use sha1::{Digest, Sha1};
+
+/// The secret changes this often, tokens remaining valid until the next change
+const SECRET_LIFETIME: Duration = Duration::from_secs(5 * 60);
+const TOKEN_LEN: usize = 8;
+
+/// Tokens prove that a node announcing a peer asked for the peers of the torrent from the same
+/// IP address recently: they are the hash of the IP address with a secret rotating every five
+/// minutes. Tokens made with the current and previous secrets are accepted.
+#[derive(Debug)]
+pub(super) struct Tokens {
+    secret: [u8; 20],
+    previous: [u8; 20],
+    rotated_at: Instant,
+}
+
+impl Tokens {
+    pub fn new() -> Self {
+        let secret = random_secret();
+        Self {
+            secret,
+            previous: secret,
+            rotated_at: Instant::now(),
+        }
+    }
+
+    pub fn generate(&mut self, ip: IpAddr) -> Vec<u8> {
+        self.rotate();
+        token(&self.secret, ip)
+    }
+
+    pub fn validate(&mut self, value: &[u8], ip: IpAddr) -> bool {
+        self.rotate();
+        value == token(&self.secret, ip) || value == token(&self.previous, ip)
+    }
+
+    fn rotate(&mut self) {
+        if self.rotated_at.elapsed() >= SECRET_LIFETIME {
+            self.previous = self.secret;
+            self.secret = random_secret();
+            self.rotated_at = Instant::now();
+        }
+    }
+}
+
+fn random_secret() -> [u8; 20] {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+fn token(secret: &[u8; 20], ip: IpAddr) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.update(secret);
+    match ip {
+        IpAddr::V4(ip) => hasher.update(ip.octets()),
+        IpAddr::V6(ip) => hasher.update(ip.octets()),
+    }
+
+    hasher.finalize()[..TOKEN_LEN].to_vec()
+}
@@ -0,0 +1,213 @@
+use std::{fmt, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Default number of peers unchoked at the same time, optimistic unchoke included
+pub const DEFAULT_UPLOAD_SLOTS: usize = 4;
+/// Number of rechoke rounds between two optimistic unchoke rotations (30 seconds)
+const OPTIMISTIC_ROUNDS: u64 = 3;
+/// Peers connected for less than this are three times more likely to be optimistically
+/// unchoked, so they get a chance to obtain pieces to trade
+const NEW_PEER_AGE: Duration = Duration::from_secs(60);
+
+/// Snapshot of a connected peer given to a [`Choker`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChokePeer {
+    /// Identifier of the peer, stable for the connection lifetime
+    pub id: usize,
+    /// The peer wants to download from us
+    pub interested: bool,
+    /// The peer is currently choked by us
+    pub choked: bool,
+    /// Payload bytes per second received from the peer since the last round
+    pub download_rate: u64,
+    /// Payload bytes per second sent to the peer since the last round
+    pub upload_rate: u64,
+    /// The peer didn't send any requested block for a while
+    pub snubbed: bool,
+    pub connected_for: Duration,
+}
+
+/// Choker decides which peers get an upload slot.
+/// The engine calls [`Choker::rechoke`] every 10 seconds with every connected peer.
+pub trait Choker: fmt::Debug + Send + Sync {
+    /// Return the peers to unchoke, every other peer is choked.
+    /// `seeding` is set once every piece is downloaded.
+    fn rechoke(&mut self, peers: &[ChokePeer], seeding: bool) -> Vec<usize>;
+
+    /// Number of peers unchoked at the same time. Interested peers are unchoked right away
+    /// between two rounds while fewer peers are unchoked.
+    fn upload_slots(&self) -> usize;
+}
+
+/// TitForTat is the choking algorithm described by
+/// [BEP 0003](https://www.bittorrent.org/beps/bep_0003.html).
+///
+/// Every round, the interested peers we download from the fastest are unchoked. While
+/// seeding, peers we upload to the fastest are favoured instead. One more slot is given
+/// to a random interested peer, rotated every 30 seconds (optimistic unchoke). Snubbed peers
+/// only get the optimistic slot.
+#[derive(Debug)]
+pub struct TitForTat {
+    slots: usize,
+    round: u64,
+    optimistic: Option<usize>,
+    rng: StdRng,
+}
+
+impl TitForTat {
+    /// Create a choker with `slots` upload slots, one of them being the optimistic one
+    pub fn new(slots: usize) -> Self {
+        Self::with_rng(slots, StdRng::from_entropy())
+    }
+
+    /// Same as [`TitForTat::new`] with a seeded random generator, so optimistic unchokes
+    /// are reproducible
+    pub fn with_seed(slots: usize, seed: u64) -> Self {
+        Self::with_rng(slots, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(slots: usize, rng: StdRng) -> Self {
+        Self {
+            slots: slots.max(1),
+            round: 0,
+            optimistic: None,
+            rng,
+        }
+    }
+
+    /// Pick a random interested peer outside of `excluded`, new peers being favoured
+    fn pick_optimistic(&mut self, peers: &[ChokePeer], excluded: &[usize]) -> Option<usize> {
+        let candidates: Vec<(usize, u32)> = peers
+            .iter()
+            .filter(|p| p.interested && !excluded.contains(&p.id))
+            .map(|p| (p.id, if p.connected_for < NEW_PEER_AGE { 3 } else { 1 }))
+            .collect();
+        let total: u32 = candidates.iter().map(|(_, w)| w).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut n = self.rng.gen_range(0..total);
+        for (id, w) in candidates {
+            if n < w {
+                return Some(id);
+            }
+            n -= w;
+        }
+
+        None
+    }
+}
+
+impl Default for TitForTat {
+    fn default() -> Self {
+        Self::new(DEFAULT_UPLOAD_SLOTS)
+    }
+}
+
+impl Choker for TitForTat {
+    fn rechoke(&mut self, peers: &[ChokePeer], seeding: bool) -> Vec<usize> {
+        let mut candidates: Vec<&ChokePeer> = peers
+            .iter()
+            .filter(|p| p.interested && (seeding || !p.snubbed))
+            .collect();
+        // Stable sort on the peer order, so equal rates don't depend on the hashing order
+        candidates.sort_by_key(|p| p.id);
+        candidates.sort_by_key(|p| {
+            std::cmp::Reverse(if seeding {
+                p.upload_rate
+            } else {
+                p.download_rate
+            })
+        });
+        let mut unchoked: Vec<usize> = candidates
+            .iter()
+            .take(self.slots - 1)
+            .map(|p| p.id)
+            .collect();
+
+        let current = self
+            .optimistic
+            .filter(|id| peers.iter().any(|p| p.id == *id && p.interested))
+            .filter(|id| !unchoked.contains(id));
+        self.optimistic = match current {
+            Some(id) if !self.round.is_multiple_of(OPTIMISTIC_ROUNDS) => Some(id),
+            _ => self.pick_optimistic(peers, &unchoked),
+        };
+        self.round += 1;
+
+        unchoked.extend(self.optimistic);
+        unchoked
+    }
+
+    fn upload_slots(&self) -> usize {
+        self.slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(id: usize, download_rate: u64, upload_rate: u64) -> ChokePeer {
+        ChokePeer {
+            id,
+            interested: true,
+            choked: true,
+            download_rate,
+            upload_rate,
+            snubbed: false,
+            connected_for: NEW_PEER_AGE * 2,
+        }
+    }
+
+    #[test]
+    fn fastest_uploaders_are_unchoked() {
+        let mut choker = TitForTat::with_seed(3, 1);
+        let mut peers: Vec<_> = (0..6).map(|i| peer(i, i as u64 * 100, 0)).collect();
+        peers[5].interested = false;
+        peers[4].snubbed = true;
+
+        let unchoked = choker.rechoke(&peers, false);
+        assert_eq!(unchoked[..2], [3, 2]);
+        assert_eq!(unchoked.len(), 3);
+        assert!([0, 1, 4].contains(&unchoked[2]));
+    }
+
+    #[test]
+    fn seeding_favours_fastest_downloaders() {
+        let mut choker = TitForTat::with_seed(2, 1);
+        let peers = [peer(0, 500, 10), peer(1, 0, 300), peer(2, 0, 20)];
+
+        assert_eq!(choker.rechoke(&peers, true)[0], 1);
+    }
+
+    #[test]
+    fn optimistic_unchoke_rotates_every_three_rounds() {
+        let mut choker = TitForTat::with_seed(2, 7);
+        let peers: Vec<_> = (0..20).map(|i| peer(i, 0, 0)).collect();
+
+        let rounds: Vec<_> = (0..7).map(|_| choker.rechoke(&peers, false)[1]).collect();
+        assert_eq!(rounds[0], rounds[1]);
+        assert_eq!(rounds[0], rounds[2]);
+        assert_eq!(rounds[3], rounds[4]);
+        assert_eq!(rounds[3], rounds[5]);
+
+        // Same seed, same rounds
+        let mut other = TitForTat::with_seed(2, 7);
+        let replay: Vec<_> = (0..7).map(|_| other.rechoke(&peers, false)[1]).collect();
+        assert_eq!(rounds, replay);
+    }
+
+    #[test]
+    fn optimistic_peer_is_replaced_when_not_interested() {
+        let mut choker = TitForTat::with_seed(1, 3);
+        let mut peers = vec![peer(0, 0, 0), peer(1, 0, 0)];
+        let first = choker.rechoke(&peers, false);
+        assert_eq!(first.len(), 1);
+
+        peers[first[0]].interested = false;
+        assert_eq!(choker.rechoke(&peers, false), [1 - first[0]]);
+    }
+}
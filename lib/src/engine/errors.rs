@@ -0,0 +1,37 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::{storage::StorageError, torrent::errors::TorrentError, tracker::TrackerError};
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("Invalid torrent: {0}")]
+    InvalidTorrent(String),
+    #[error("Failed to check existing content: {0}")]
+    Torrent(#[from] TorrentError),
+    #[error("Failed to announce: {0}")]
+    Tracker(#[from] TrackerError),
+    #[error("Failed to access torrent content: {0}")]
+    Storage(#[from] StorageError),
+    #[error("Failed to listen for incoming peers: {0}")]
+    Listen(io::Error),
+    #[error("Failed to save resume data: {0}")]
+    Resume(#[from] ResumeError),
+    #[error("Download was cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    #[error("Failed to read resume data at {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("Failed to write resume data at {0}: {1}")]
+    Write(PathBuf, io::Error),
+    #[error("Failed to parse resume data: {0}")]
+    Parse(bendy::serde::Error),
+    #[error("Failed to encode resume data: {0}")]
+    Encode(bendy::serde::Error),
+    #[error("Unsupported resume data version {0}")]
+    UnsupportedVersion(u32),
+}
@@ -0,0 +1,1434 @@
+//! Download engine fetching the content of a single torrent from its swarm.
+
+mod choker;
+mod errors;
+mod peer;
+mod picker;
+mod resume;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use sha1::{Digest, Sha1};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    task::{JoinHandle, JoinSet},
+    time,
+};
+
+use crate::{
+    bandwidth::{Bandwidth, LimiterChain},
+    dht::{Dht, DhtError},
+    extension::{
+        metadata::MetadataExtension,
+        pex::{PeerExchange, PexExtension, FLAG_CONNECTABLE},
+        ExtendedHandshake, Extension, ExtensionRegistry, PeerExtensions, HANDSHAKE_ID,
+    },
+    peer::PeerId,
+    storage::{FileStorage, Storage, StorageError},
+    torrent::{
+        errors::TorrentError,
+        v1::{CancelHandle, Handshake, Message, PeerConnection, PieceHasher, Pieces, Torrent},
+        InfoHash,
+    },
+    tracker::{
+        announce::{AnnounceEv, AnnounceReq, AnnounceRsp},
+        manager::TrackerManager,
+        Peer, TrackerError,
+    },
+};
+
+use peer::{Incoming, PeerEvent, PeerKey, PeerLimiters, Target};
+use picker::{Block, Picker};
+
+pub use choker::{ChokePeer, Choker, TitForTat};
+pub use errors::{EngineError, ResumeError};
+pub use picker::{PickMode, Priority};
+pub use resume::{ResumeData, ResumeFile};
+
+/// Default port announced to trackers
+pub(crate) const DEFAULT_PORT: u16 = 6881;
+/// Default maximum number of connected peers
+pub(crate) const DEFAULT_MAX_PEERS: usize = 50;
+/// Number of requests kept in flight for each unchoked peer
+const PIPELINE_DEPTH: usize = 16;
+/// Number of requests from a peer queued before serving them, advertised as `reqq`
+const MAX_QUEUED_UPLOADS: usize = 250;
+/// A peer not sending any requested block for this long is snubbed: its requests are
+/// released and it only gets one request at a time until it sends a block
+const SNUB_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval between two rounds of the choking algorithm
+const CHOKE_INTERVAL: Duration = Duration::from_secs(10);
+/// Delay before announcing again after a failure
+const ANNOUNCE_RETRY: Duration = Duration::from_secs(60);
+/// Interval between two announces on the DHT
+const DHT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Time allowed to the final announces
+const FINAL_ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between timeout checks and progress reports
+const TICK: Duration = Duration::from_secs(1);
+/// Maximum number of peer addresses kept in the resume data
+const MAX_RESUME_PEERS: usize = 200;
+
+type AnnounceTask = JoinHandle<(TrackerManager, Result<AnnounceRsp<'static>, TrackerError>)>;
+type DhtTask = JoinHandle<Result<Vec<Peer>, DhtError>>;
+/// Block read from disk for the peer that requested it
+type BlockRead = (PeerKey, Block, Result<Vec<u8>, StorageError>);
+/// Downloaded piece once hashed, and written to disk when valid
+type PieceWrite = (u32, Result<bool, StorageError>);
+
+/// Progress reported every second while downloading.
+#[derive(Debug, Clone, Copy)]
+pub struct EngineProgress {
+    /// Number of pieces verified and written to disk
+    pub verified_pieces: usize,
+    pub total_pieces: usize,
+    /// Number of bytes verified and written to disk, skipped files excluded
+    pub downloaded: u64,
+    /// Number of bytes to download, skipped files excluded
+    pub total: u64,
+    /// Number of bytes sent to peers
+    pub uploaded: u64,
+    /// Number of connected peers
+    pub peers: usize,
+    /// Payload bytes received during the last second
+    pub download_rate: u64,
+    /// Payload bytes sent during the last second
+    pub upload_rate: u64,
+}
+
+/// SeedTarget tells when to stop seeding once the download is complete.
+/// Without any limit, seeding goes on until the engine is cancelled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeedTarget {
+    /// Ratio between uploaded bytes and the torrent size
+    pub ratio: Option<f64>,
+    /// Time spent seeding
+    pub duration: Option<Duration>,
+}
+
+impl SeedTarget {
+    fn is_reached(&self, uploaded: u64, total: u64, elapsed: Duration) -> bool {
+        self.ratio
+            .is_some_and(|r| uploaded as f64 >= r * total as f64)
+            || self.duration.is_some_and(|d| elapsed >= d)
+    }
+}
+
+/// Engine downloads a torrent into an output directory, and optionally seeds it.
+///
+/// Existing content is checked first, so an interrupted download only fetches the
+/// missing pieces. Every piece is verified against `TorrentInfo.pieces` before being
+/// written to disk. Incoming connections are accepted on the announced port.
+#[derive(Debug)]
+pub struct Engine {
+    info_hash: InfoHash,
+    piece_length: u64,
+    pieces: Pieces<'static>,
+    /// Extensions negotiated through the extension protocol
+    extensions: ExtensionRegistry,
+    /// Peers exchanged with `ut_pex`, `None` for private torrents
+    pex: Option<Arc<PeerExchange>>,
+    /// DHT node looking up peers, never set for private torrents
+    dht: Option<Arc<Dht>>,
+    private: bool,
+    storage: Arc<dyn Storage>,
+    /// Content files under the output directory, in the same order as `TorrentInfo.files`
+    files: Vec<(PathBuf, u64)>,
+    total_length: u64,
+    trackers: TrackerManager,
+    peer_id: PeerId,
+    port: u16,
+    max_peers: usize,
+    key: u32,
+    seed: Option<SeedTarget>,
+    verified: Option<Vec<bool>>,
+    pick_mode: PickMode,
+    file_priorities: Vec<Priority>,
+    choker: Box<dyn Choker>,
+    cancel: CancelHandle,
+    resume_path: Option<PathBuf>,
+    /// Partfile holding the parts of skipped files shared with wanted pieces.
+    /// `None` when a custom storage is used.
+    partfile: Option<PathBuf>,
+    /// Payload bytes sent and received during the previous sessions
+    previous_totals: (u64, u64),
+    /// Connections handshaked by a shared listener, replacing the engine listener
+    incoming: Option<mpsc::UnboundedReceiver<PeerConnection>>,
+    /// Each connected peer holds a permit, possibly shared with other engines
+    connections: Arc<Semaphore>,
+    /// Limits of this torrent
+    bandwidth: Bandwidth,
+    /// Limits shared with other engines
+    shared_bandwidth: Option<Bandwidth>,
+    /// Limits given to each peer
+    peer_bandwidth: Bandwidth,
+}
+
+/// State of a peer, from the engine point of view
+#[derive(Debug)]
+struct PeerState {
+    cmds: mpsc::UnboundedSender<Message>,
+    connected: bool,
+    /// Pieces owned by the peer
+    has: Vec<bool>,
+    peer_choking: bool,
+    am_interested: bool,
+    am_choking: bool,
+    peer_interested: bool,
+    /// Blocks requested and not received yet
+    requests: Vec<Block>,
+    /// Blocks requested by the peer and not sent yet
+    uploads: VecDeque<Block>,
+    /// The first requested block is being read from disk
+    reading: bool,
+    last_block: Instant,
+    snubbed: bool,
+    connected_at: Instant,
+    /// Payload bytes received from the peer since the last choking round
+    round_received: u64,
+    /// Payload bytes sent to the peer since the last choking round
+    round_sent: u64,
+    /// Address of the peer, known once connected
+    addr: Option<SocketAddr>,
+    /// Address the peer accepts connections on: the connected address for outgoing
+    /// connections, the port of its extended handshake for incoming ones
+    listen_addr: Option<SocketAddr>,
+    /// Extensions running with the peer, once its extended handshake is received
+    extensions: Option<PeerExtensions>,
+    /// Number of outstanding requests the peer accepts, `0` if unknown
+    reqq: usize,
+    /// Connection slot, released when the peer is removed
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Mutable state of a running engine
+#[derive(Debug)]
+struct Swarm {
+    picker: Picker,
+    peers: HashMap<PeerKey, PeerState>,
+    /// Peer addresses returned by trackers, not tried yet
+    candidates: VecDeque<SocketAddr>,
+    known: HashSet<SocketAddr>,
+    next_key: PeerKey,
+    connections: Arc<Semaphore>,
+    /// Limiter levels above the peers, from the torrent to the session
+    bandwidth: Vec<Bandwidth>,
+    peer_bandwidth: Bandwidth,
+    pex: Option<Arc<PeerExchange>>,
+    events: mpsc::UnboundedSender<PeerEvent>,
+    local: Handshake,
+    /// Blocks being read from disk, at most one for each peer
+    reads: JoinSet<BlockRead>,
+    /// Downloaded pieces being hashed and written to disk
+    writes: JoinSet<PieceWrite>,
+    /// Payload bytes received since the start
+    received: u64,
+    /// Payload bytes sent since the start
+    uploaded: u64,
+}
+
+impl Engine {
+    /// Create an engine downloading `torrent` under `output`.
+    /// `output` is the directory containing the torrent content, `TorrentInfo.name` included.
+    pub fn new(torrent: &Torrent, output: &Path) -> Result<Self, EngineError> {
+        if torrent.info.piece_length <= 0 {
+            return Err(EngineError::InvalidTorrent(format!(
+                "invalid piece length {}",
+                torrent.info.piece_length
+            )));
+        }
+        let piece_length = torrent.info.piece_length as u64;
+        let storage = FileStorage::from_torrent(torrent, output);
+        let files = storage.files().to_vec();
+        let total_length = storage.total_length();
+        let expected = total_length.div_ceil(piece_length) as usize;
+        if torrent.info.pieces.len() != expected {
+            return Err(EngineError::InvalidTorrent(format!(
+                "expected {expected} pieces, found {}",
+                torrent.info.pieces.len()
+            )));
+        }
+
+        let info_hash = torrent.calc_hash()?;
+        let mut extensions = ExtensionRegistry::new();
+        extensions.register(Arc::new(MetadataExtension::new(torrent.info_bytes()?)));
+        // Private torrents only get peers from their trackers (BEP 0027)
+        let pex = (!torrent.info.additional_fields.private).then(|| {
+            let pex = Arc::new(PeerExchange::new());
+            extensions.register(Arc::new(PexExtension::new(Arc::clone(&pex))));
+            pex
+        });
+
+        Ok(Self {
+            info_hash,
+            piece_length,
+            pieces: torrent.info.pieces.clone().into_owned(),
+            extensions,
+            pex,
+            dht: None,
+            private: torrent.info.additional_fields.private,
+            storage: Arc::new(storage),
+            files,
+            total_length,
+            trackers: TrackerManager::from_torrent(torrent),
+            peer_id: PeerId::generate(None),
+            port: DEFAULT_PORT,
+            max_peers: DEFAULT_MAX_PEERS,
+            key: rand::random(),
+            seed: None,
+            verified: None,
+            pick_mode: PickMode::default(),
+            file_priorities: vec![],
+            choker: Box::new(TitForTat::default()),
+            cancel: CancelHandle::default(),
+            resume_path: None,
+            partfile: Some(output.join(format!(".{}.parts", info_hash.to_hex()))),
+            previous_totals: (0, 0),
+            incoming: None,
+            connections: Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+            bandwidth: Bandwidth::default(),
+            shared_bandwidth: None,
+            peer_bandwidth: Bandwidth::default(),
+        })
+    }
+
+    /// Override the randomly generated peer ID
+    pub fn peer_id(mut self, peer_id: PeerId) -> Self {
+        self.peer_id = peer_id;
+        self
+    }
+
+    /// Port announced to trackers. Defaults to `6881`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Maximum number of connected peers. Defaults to `50`.
+    pub fn max_peers(mut self, max_peers: usize) -> Self {
+        self.max_peers = max_peers.max(1);
+        self
+    }
+
+    /// Number of retransmissions before giving up on a UDP tracker. Defaults to
+    /// [`UDP_DEFAULT_RETRIES`](crate::tracker::tracker::UDP_DEFAULT_RETRIES), the full
+    /// `BEP 0015` schedule being [`UDP_MAX_RETRIES`](crate::tracker::tracker::UDP_MAX_RETRIES).
+    pub fn udp_retries(mut self, retries: u32) -> Self {
+        self.trackers = self.trackers.udp_retries(retries);
+        self
+    }
+
+    /// Keep seeding once the download is complete, until `target` is reached.
+    /// By default, the engine stops as soon as every piece is downloaded.
+    pub fn seed(mut self, target: SeedTarget) -> Self {
+        self.seed = Some(target);
+        self
+    }
+
+    /// Pieces known to be valid on disk, skipping the content check done on start.
+    /// Ignored if the number of pieces doesn't match the torrent.
+    pub fn verified(mut self, pieces: Vec<bool>) -> Self {
+        self.verified = Some(pieces);
+        self
+    }
+
+    /// Order in which missing pieces are downloaded. Defaults to rarest first.
+    pub fn pick_mode(mut self, mode: PickMode) -> Self {
+        self.pick_mode = mode;
+        self
+    }
+
+    /// Priority of each file, in the same order as `TorrentInfo.files`.
+    /// Files without a priority are considered normal. Skipped files are never created, the
+    /// parts of pieces they share with wanted files are kept in a partfile of the output
+    /// directory.
+    pub fn file_priorities(mut self, priorities: Vec<Priority>) -> Self {
+        self.file_priorities = priorities;
+        self
+    }
+
+    /// Where the content is read and written. Defaults to a [`FileStorage`] under the output
+    /// directory. Its length must match the torrent.
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = storage;
+        self.partfile = None;
+        self
+    }
+
+    /// Load the [`ResumeData`] stored at `path` on start, and save it there once stopped.
+    /// Missing, unreadable or outdated data falls back to checking the content.
+    pub fn resume_file(mut self, path: PathBuf) -> Self {
+        self.resume_path = Some(path);
+        self
+    }
+
+    /// Receive incoming peers from a listener shared with other engines instead of binding
+    /// one. Connections must be handshaked for this torrent.
+    pub fn incoming(mut self, connections: mpsc::UnboundedReceiver<PeerConnection>) -> Self {
+        self.incoming = Some(connections);
+        self
+    }
+
+    /// Limit the number of connections along with other engines: each connected peer takes
+    /// a permit of `limit`, on top of [`Engine::max_peers`].
+    pub fn connection_limit(mut self, limit: Arc<Semaphore>) -> Self {
+        self.connections = limit;
+        self
+    }
+
+    /// Limit the upload and download rates of this torrent. Keep a clone of `bandwidth` to
+    /// change the limits while the engine runs.
+    pub fn rate_limit(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Limit the rates along with other engines, on top of [`Engine::rate_limit`]
+    pub fn shared_rate_limit(mut self, bandwidth: Bandwidth) -> Self {
+        self.shared_bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Limit the rates of each peer. Every peer gets its own limiters, following the limits
+    /// of `bandwidth`.
+    pub fn peer_rate_limit(mut self, bandwidth: Bandwidth) -> Self {
+        self.peer_bandwidth = bandwidth;
+        self
+    }
+
+    /// HTTP client used to announce, to share its connection pool with other engines
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.trackers = self.trackers.http_client(client);
+        self
+    }
+
+    /// Algorithm deciding which peers get an upload slot. Defaults to [`TitForTat`].
+    pub fn choker<C: Choker + 'static>(mut self, choker: C) -> Self {
+        self.choker = Box::new(choker);
+        self
+    }
+
+    /// Also look up peers on the DHT, and announce the torrent there.
+    /// Ignored for private torrents, which only get peers from their trackers.
+    pub fn dht(mut self, dht: Arc<Dht>) -> Self {
+        self.dht = (!self.private).then_some(dht);
+        self
+    }
+
+    /// Support an extension of the extension protocol along with the built-in ones.
+    /// An extension with the same name as a built-in one replaces it.
+    pub fn extension<E: Extension + 'static>(mut self, extension: E) -> Self {
+        self.extensions.register(Arc::new(extension));
+        self
+    }
+
+    /// Handle used to stop the engine from another task. Trackers are told we stopped.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    pub fn info_hash(&self) -> InfoHash {
+        self.info_hash
+    }
+
+    /// Download the missing pieces, calling `progress` every second.
+    /// Returns once every piece is verified and written to disk, or once the seed target
+    /// is reached when [`Engine::seed`] is set. Cancelling before the download is complete
+    /// returns [`EngineError::Cancelled`]. Trackers are told about the stop and the resume
+    /// data is saved whichever way the engine stops.
+    pub async fn run<F>(mut self, mut progress: F) -> Result<(), EngineError>
+    where
+        F: FnMut(EngineProgress),
+    {
+        if self.storage.total_length() != self.total_length
+            || self.storage.piece_length() != self.piece_length
+        {
+            return Err(EngineError::InvalidTorrent(
+                "storage layout doesn't match the torrent".to_string(),
+            ));
+        }
+        let skipped = self.skipped_files();
+        if let (Some(partfile), true) = (&self.partfile, skipped.contains(&true)) {
+            let storage = FileStorage::new(self.files.clone(), self.piece_length)
+                .skip_files(skipped.clone(), partfile.clone());
+            self.storage = Arc::new(storage);
+        }
+        let resume = self
+            .resume_path
+            .as_deref()
+            .and_then(|p| ResumeData::load(p).ok())
+            .filter(|d| d.info_hash == self.info_hash);
+        if let Some(data) = &resume {
+            self.trackers.restore(&data.trackers);
+            self.previous_totals = (data.uploaded, data.downloaded);
+        }
+        // Files were skipped or wanted again since the last session
+        if let (Some(partfile), Some(data)) = (&self.partfile, &resume) {
+            if data.skipped.len() == skipped.len() && data.skipped != skipped {
+                let storage = FileStorage::new(self.files.clone(), self.piece_length)
+                    .skip_files(skipped, partfile.clone());
+                let previous = data.skipped.clone();
+                tokio::task::spawn_blocking(move || {
+                    storage.allocate()?;
+                    storage.migrate_parts(previous)
+                })
+                .await
+                .expect("partfile migration panicked")?;
+            }
+        }
+        let have = match (self.verified.take(), resume.as_ref()) {
+            (Some(v), _) if v.len() == self.pieces.len() => v,
+            (_, Some(d)) if d.is_valid_for(self.info_hash, self.pieces.len(), &self.files) => {
+                d.pieces.clone()
+            }
+            _ => self.check_content().await?,
+        };
+        let storage = Arc::clone(&self.storage);
+        tokio::task::spawn_blocking(move || storage.allocate())
+            .await
+            .expect("allocation panicked")?;
+
+        let mut picker = Picker::new(&have, self.piece_length, self.total_length);
+        picker.set_mode(self.pick_mode);
+        if !self.file_priorities.is_empty() {
+            let lengths: Vec<u64> = self.files.iter().map(|(_, l)| *l).collect();
+            picker.set_file_priorities(&lengths, &self.file_priorities);
+        }
+
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let mut swarm = Swarm {
+            picker,
+            peers: HashMap::new(),
+            candidates: VecDeque::new(),
+            known: HashSet::new(),
+            next_key: 0,
+            connections: Arc::clone(&self.connections),
+            bandwidth: [Some(&self.bandwidth), self.shared_bandwidth.as_ref()]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            peer_bandwidth: self.peer_bandwidth.clone(),
+            pex: self.pex.clone(),
+            events: events_tx,
+            local: Handshake::new(self.info_hash, self.peer_id).with_extension_protocol(),
+            reads: JoinSet::new(),
+            writes: JoinSet::new(),
+            received: 0,
+            uploaded: 0,
+        };
+        for addr in resume.into_iter().flat_map(|d| d.peers) {
+            swarm.add_candidate(addr);
+        }
+        let mut completed_at = swarm.picker.is_complete().then(Instant::now);
+        if completed_at.is_some() && self.seed.is_none() {
+            progress(self.progress(&swarm, 0, 0));
+            return self.save_resume(&swarm).await;
+        }
+        let mut incoming = match self.incoming.take() {
+            Some(rx) => Incoming::Shared(rx),
+            None => Incoming::Listener(
+                TcpListener::bind((Ipv4Addr::UNSPECIFIED, self.port))
+                    .await
+                    .map_err(EngineError::Listen)?,
+            ),
+        };
+
+        let mut announce = Some(self.spawn_announce(&swarm, AnnounceEv::Started));
+        let mut next_announce = None;
+        let mut first_announce = true;
+        let mut dht_announce = self.spawn_dht_announce();
+        let mut next_dht_announce = None;
+        let (mut last_received, mut last_uploaded) = (0, 0);
+        let mut last_rechoke = Instant::now();
+        let mut tick = time::interval(TICK);
+        // Errors stop the engine the same way as a cancel, announcing and saving the resume data
+        let outcome = loop {
+            if completed_at.is_none() && swarm.picker.is_complete() {
+                completed_at = Some(Instant::now());
+                if self.seed.is_none() {
+                    break Ok(vec![AnnounceEv::Completed, AnnounceEv::Stopped]);
+                }
+                if let Some(task) = announce.take() {
+                    task.abort();
+                }
+                announce = Some(self.spawn_announce(&swarm, AnnounceEv::Completed));
+            }
+
+            tokio::select! {
+                res = async { announce.as_mut().expect("announce should be running").await },
+                    if announce.is_some() =>
+                {
+                    announce = None;
+                    let (trackers, rsp) = res.expect("announce task panicked");
+                    self.trackers = trackers;
+                    match rsp {
+                        Ok(rsp) => {
+                            let interval = rsp.interval.max(rsp.min_interval.unwrap_or_default());
+                            let interval = Duration::from_secs(interval);
+                            next_announce = Some(Instant::now() + interval.max(ANNOUNCE_RETRY));
+                            for p in rsp.peers {
+                                swarm.add_candidate(SocketAddr::new(p.ip, p.port));
+                            }
+                        }
+                        // Peers may still come from the DHT
+                        Err(e) if first_announce && self.dht.is_none() => break Err(e.into()),
+                        Err(_) => next_announce = Some(Instant::now() + ANNOUNCE_RETRY),
+                    }
+                    first_announce = false;
+                    swarm.connect_peers(self.max_peers);
+                }
+                res = async { dht_announce.as_mut().expect("DHT announce should be running").await },
+                    if dht_announce.is_some() =>
+                {
+                    dht_announce = None;
+                    let interval = match res.expect("DHT announce task panicked") {
+                        Ok(peers) if !peers.is_empty() => {
+                            for p in peers {
+                                swarm.add_candidate(SocketAddr::new(p.ip, p.port));
+                            }
+                            DHT_ANNOUNCE_INTERVAL
+                        }
+                        // The network may not be joined yet
+                        _ => ANNOUNCE_RETRY,
+                    };
+                    next_dht_announce = Some(Instant::now() + interval);
+                    swarm.connect_peers(self.max_peers);
+                }
+                Some(ev) = events_rx.recv() => {
+                    swarm.handle(ev, &self);
+                }
+                Some(res) = swarm.reads.join_next(), if !swarm.reads.is_empty() => {
+                    swarm.send_block(res.expect("block reader panicked"), &self.storage);
+                }
+                Some(res) = swarm.writes.join_next(), if !swarm.writes.is_empty() => {
+                    if let Err(e) = swarm.piece_written(res.expect("piece writer panicked")) {
+                        break Err(e);
+                    }
+                }
+                Some(target) = incoming.next() => {
+                    if swarm.peers.len() < self.max_peers {
+                        swarm.spawn_peer(target);
+                    }
+                }
+                _ = tick.tick() => {
+                    swarm.check_snubbed();
+                    swarm.tick_extensions();
+                    for addr in self.pex.iter().flat_map(|p| p.take_discovered()) {
+                        swarm.add_candidate(addr);
+                    }
+                    if completed_at.is_some() {
+                        swarm.drop_seeds();
+                    }
+                    if last_rechoke.elapsed() >= CHOKE_INTERVAL {
+                        let seeding = completed_at.is_some();
+                        swarm.rechoke(self.choker.as_mut(), seeding, last_rechoke.elapsed());
+                        last_rechoke = Instant::now();
+                    }
+                    swarm.unchoke_peers(self.choker.upload_slots());
+                    swarm.connect_peers(self.max_peers);
+                    let due = next_announce.is_some_and(|t| Instant::now() >= t);
+                    if announce.is_none() && due {
+                        announce = Some(self.spawn_announce(&swarm, AnnounceEv::Empty));
+                    }
+                    let due = next_dht_announce.is_some_and(|t| Instant::now() >= t);
+                    if dht_announce.is_none() && due {
+                        dht_announce = self.spawn_dht_announce();
+                    }
+                    progress(self.progress(
+                        &swarm,
+                        swarm.received - last_received,
+                        swarm.uploaded - last_uploaded,
+                    ));
+                    (last_received, last_uploaded) = (swarm.received, swarm.uploaded);
+
+                    if self.cancel.is_cancelled() {
+                        if completed_at.is_none() {
+                            break Err(EngineError::Cancelled);
+                        }
+                        break Ok(vec![AnnounceEv::Stopped]);
+                    }
+                    if let (Some(target), Some(at)) = (self.seed, completed_at) {
+                        if target.is_reached(swarm.uploaded, self.total_length, at.elapsed()) {
+                            break Ok(vec![AnnounceEv::Stopped]);
+                        }
+                    }
+                }
+            }
+        };
+
+        if let Some(task) = announce {
+            task.abort();
+        }
+        if let Some(task) = dht_announce {
+            task.abort();
+        }
+        progress(self.progress(
+            &swarm,
+            swarm.received - last_received,
+            swarm.uploaded - last_uploaded,
+        ));
+        // Dropping the command senders closes every connection
+        swarm.peers.clear();
+        let final_events = match &outcome {
+            Ok(v) => v.clone(),
+            Err(_) => vec![AnnounceEv::Stopped],
+        };
+        for event in final_events {
+            self.final_announce(&swarm, event).await;
+        }
+
+        let saved = self.save_resume(&swarm).await;
+        outcome.and(saved)
+    }
+
+    /// Save the resume data, if a resume file is set
+    async fn save_resume(&self, swarm: &Swarm) -> Result<(), EngineError> {
+        let path = match &self.resume_path {
+            Some(v) => v.clone(),
+            None => return Ok(()),
+        };
+        let mut data = ResumeData::new(self.info_hash, swarm.picker.pieces(), &self.files);
+        data.uploaded = self.previous_totals.0 + swarm.uploaded;
+        data.downloaded = self.previous_totals.1 + swarm.received;
+        data.peers = swarm.known.iter().take(MAX_RESUME_PEERS).copied().collect();
+        data.trackers = self.trackers.state();
+        data.skipped = self.skipped_files();
+
+        tokio::task::spawn_blocking(move || data.save(&path))
+            .await
+            .expect("resume data writer panicked")?;
+
+        Ok(())
+    }
+
+    /// Files flagged as skipped by their priority, in the same order as the files
+    fn skipped_files(&self) -> Vec<bool> {
+        (0..self.files.len())
+            .map(|i| self.file_priorities.get(i) == Some(&Priority::Skip))
+            .collect()
+    }
+
+    /// Announce before stopping, failures are ignored
+    async fn final_announce(&mut self, swarm: &Swarm, event: AnnounceEv) {
+        let req = self.announce_req(swarm, event);
+        let _ = time::timeout(FINAL_ANNOUNCE_TIMEOUT, self.trackers.announce(req)).await;
+    }
+
+    /// Hash the content already on disk and return the valid pieces
+    async fn check_content(&self) -> Result<Vec<bool>, EngineError> {
+        let storage = Arc::clone(&self.storage);
+        let cancel = self.cancel.clone();
+        let hashes = tokio::task::spawn_blocking(move || {
+            PieceHasher::from_storage(storage)
+                .allow_missing(true)
+                .with_cancel(cancel)
+                .hash(|_| {})
+        })
+        .await
+        .expect("content check panicked")
+        .map_err(|e| match e {
+            TorrentError::Cancelled => EngineError::Cancelled,
+            e => e.into(),
+        })?;
+
+        Ok(self
+            .pieces
+            .iter()
+            .enumerate()
+            .map(|(i, expected)| hashes.get(i) == Some(&Some(*expected)))
+            .collect())
+    }
+
+    /// Extended handshake advertising the extensions we support to the peer at `addr`
+    fn extended_handshake(&self, addr: SocketAddr) -> ExtendedHandshake {
+        let mut handshake = self.extensions.handshake();
+        handshake.p = self.port as u64;
+        handshake.reqq = MAX_QUEUED_UPLOADS as u64;
+        handshake.set_your_ip(addr.ip());
+        handshake
+    }
+
+    fn announce_req(&self, swarm: &Swarm, event: AnnounceEv) -> AnnounceReq {
+        AnnounceReq {
+            info_hash: self.info_hash,
+            peer_id: self.peer_id,
+            port: self.port,
+            uploaded: swarm.uploaded,
+            downloaded: swarm.received,
+            left: swarm.picker.left(),
+            event,
+            compact: true,
+            key: Some(self.key),
+            ..Default::default()
+        }
+    }
+
+    fn spawn_announce(&self, swarm: &Swarm, event: AnnounceEv) -> AnnounceTask {
+        let mut trackers = self.trackers.clone();
+        let req = self.announce_req(swarm, event);
+        tokio::spawn(async move {
+            let rsp = trackers.announce(req).await;
+            (trackers, rsp)
+        })
+    }
+
+    /// Look up peers on the DHT and announce our port, if a DHT node is set
+    fn spawn_dht_announce(&self) -> Option<DhtTask> {
+        let dht = Arc::clone(self.dht.as_ref()?);
+        let (info_hash, port) = (self.info_hash, self.port);
+        Some(tokio::spawn(async move {
+            dht.announce(info_hash, Some(port)).await
+        }))
+    }
+
+    fn progress(&self, swarm: &Swarm, download_rate: u64, upload_rate: u64) -> EngineProgress {
+        EngineProgress {
+            verified_pieces: swarm.picker.have_count(),
+            total_pieces: swarm.picker.piece_count(),
+            downloaded: swarm.picker.wanted_length() - swarm.picker.left(),
+            total: swarm.picker.wanted_length(),
+            uploaded: swarm.uploaded,
+            peers: swarm.peers.values().filter(|p| p.connected).count(),
+            download_rate,
+            upload_rate,
+        }
+    }
+}
+
+impl Swarm {
+    fn add_candidate(&mut self, addr: SocketAddr) {
+        if self.known.insert(addr) {
+            self.candidates.push_back(addr);
+        }
+    }
+
+    /// Start peer tasks until `max_peers` is reached or no candidate is left
+    fn connect_peers(&mut self, max_peers: usize) {
+        while self.peers.len() < max_peers && self.connections.available_permits() > 0 {
+            match self.candidates.pop_front() {
+                Some(addr) => self.spawn_peer(Target::Connect(addr)),
+                None => return,
+            }
+        }
+    }
+
+    /// Start a peer task, unless the connection limit shared with other engines is reached
+    fn spawn_peer(&mut self, target: Target) {
+        let permit = match Arc::clone(&self.connections).try_acquire_owned() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let key = self.next_key;
+        self.next_key += 1;
+        let listen_addr = match &target {
+            Target::Connect(addr) => Some(*addr),
+            _ => None,
+        };
+        let (cmds_tx, cmds_rx) = mpsc::unbounded_channel();
+        let peer_bandwidth = self.peer_bandwidth.fork();
+        let levels: Vec<&Bandwidth> = [&peer_bandwidth]
+            .into_iter()
+            .chain(&self.bandwidth)
+            .collect();
+        let limiters = PeerLimiters {
+            upload: LimiterChain::upload(&levels),
+            download: LimiterChain::download(&levels),
+        };
+        tokio::spawn(peer::run(
+            key,
+            target,
+            self.local,
+            self.events.clone(),
+            cmds_rx,
+            limiters,
+        ));
+        self.peers.insert(
+            key,
+            PeerState {
+                cmds: cmds_tx,
+                connected: false,
+                has: vec![false; self.picker.piece_count()],
+                peer_choking: true,
+                am_interested: false,
+                am_choking: true,
+                peer_interested: false,
+                requests: vec![],
+                uploads: VecDeque::new(),
+                reading: false,
+                last_block: Instant::now(),
+                snubbed: false,
+                connected_at: Instant::now(),
+                round_received: 0,
+                round_sent: 0,
+                addr: None,
+                listen_addr,
+                extensions: None,
+                reqq: 0,
+                _permit: permit,
+            },
+        );
+    }
+
+    fn handle(&mut self, ev: PeerEvent, engine: &Engine) {
+        match ev {
+            // Trackers may return our own address
+            PeerEvent::Connected(key, remote, _) if remote.peer_id == self.local.peer_id => {
+                self.remove_peer(key)
+            }
+            PeerEvent::Connected(key, remote, addr) => {
+                let bitfield = (self.picker.have_count() > 0).then(|| self.picker.bitfield());
+                if let Some(peer) = self.peers.get_mut(&key) {
+                    peer.connected = true;
+                    peer.addr = Some(addr);
+                    if let (Some(pex), Some(listen)) = (&self.pex, peer.listen_addr) {
+                        pex.connected(listen, FLAG_CONNECTABLE);
+                    }
+                    if let Some(b) = bitfield {
+                        let _ = peer.cmds.send(Message::Bitfield(b));
+                    }
+                    if remote.supports_extension_protocol() {
+                        if let Ok(payload) = engine.extended_handshake(addr).to_bytes() {
+                            let _ = peer.cmds.send(Message::Extended {
+                                id: HANDSHAKE_ID,
+                                payload,
+                            });
+                        }
+                    }
+                }
+            }
+            PeerEvent::Message(key, msg) => self.handle_message(key, msg, engine),
+            PeerEvent::Closed(key) => self.remove_peer(key),
+        }
+    }
+
+    fn handle_message(&mut self, key: PeerKey, msg: Message, engine: &Engine) {
+        let piece_count = self.picker.piece_count();
+        let peer = match self.peers.get_mut(&key) {
+            Some(v) => v,
+            None => return,
+        };
+
+        match msg {
+            Message::Choke => {
+                peer.peer_choking = true;
+                for b in peer.requests.drain(..) {
+                    self.picker.release(b);
+                }
+            }
+            Message::Unchoke => {
+                peer.peer_choking = false;
+                self.request_blocks(key);
+            }
+            Message::Have(index) => {
+                if let Some(h) = peer.has.get_mut(index as usize) {
+                    if !*h {
+                        *h = true;
+                        self.picker.add_have(index);
+                    }
+                }
+                self.update_interest(key);
+                self.request_blocks(key);
+            }
+            Message::Bitfield(bytes) => {
+                self.picker.remove_peer(&peer.has);
+                peer.has = picker::from_bitfield(&bytes, piece_count);
+                self.picker.add_peer(&peer.has);
+                self.update_interest(key);
+                self.request_blocks(key);
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                let requested = Block {
+                    index,
+                    begin,
+                    length: block.len() as u32,
+                };
+                let pos = match peer.requests.iter().position(|b| *b == requested) {
+                    Some(v) => v,
+                    None => return,
+                };
+                peer.requests.swap_remove(pos);
+                peer.last_block = Instant::now();
+                peer.snubbed = false;
+                peer.round_received += block.len() as u64;
+                self.received += block.len() as u64;
+
+                let data = self.picker.receive(requested, &block);
+                self.cancel_duplicates(key, requested);
+                if let Some(data) = data {
+                    self.complete_piece(index, data, engine);
+                }
+                self.request_blocks(key);
+            }
+            Message::Interested => {
+                peer.peer_interested = true;
+                self.unchoke_peers(engine.choker.upload_slots());
+            }
+            Message::NotInterested => {
+                peer.peer_interested = false;
+                if !peer.am_choking {
+                    peer.am_choking = true;
+                    peer.uploads.clear();
+                    let _ = peer.cmds.send(Message::Choke);
+                }
+                self.unchoke_peers(engine.choker.upload_slots());
+            }
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => {
+                let valid = index < piece_count as u32
+                    && self.picker.has(index)
+                    && begin as u64 + length as u64 <= self.picker.piece_size(index) as u64;
+                if valid && !peer.am_choking && peer.uploads.len() < MAX_QUEUED_UPLOADS {
+                    peer.uploads.push_back(Block {
+                        index,
+                        begin,
+                        length,
+                    });
+                    self.serve_next(key, &engine.storage);
+                }
+            }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                let block = Block {
+                    index,
+                    begin,
+                    length,
+                };
+                peer.uploads.retain(|b| *b != block);
+            }
+            Message::Extended {
+                id: HANDSHAKE_ID,
+                payload,
+            } => {
+                // Later handshakes may only update values, extensions are started once
+                let (handshake, addr) = match (ExtendedHandshake::from_bytes(&payload), peer.addr) {
+                    (Ok(h), Some(addr)) => (h, addr),
+                    _ => return,
+                };
+                peer.reqq = handshake.reqq as usize;
+                let port = u16::try_from(handshake.p).unwrap_or_default();
+                if peer.listen_addr.is_none() && port != 0 {
+                    let listen = SocketAddr::new(addr.ip(), port);
+                    peer.listen_addr = Some(listen);
+                    self.known.insert(listen);
+                    if let Some(pex) = &self.pex {
+                        pex.connected(listen, 0);
+                    }
+                }
+                if peer.extensions.is_none() {
+                    let (extensions, messages) = engine.extensions.connect(handshake, addr);
+                    for msg in messages {
+                        let _ = peer.cmds.send(msg);
+                    }
+                    peer.extensions = Some(extensions);
+                }
+            }
+            Message::Extended { id, payload } => {
+                if let Some(extensions) = peer.extensions.as_mut() {
+                    for msg in extensions.on_message(id, &payload) {
+                        let _ = peer.cmds.send(msg);
+                    }
+                }
+            }
+            Message::KeepAlive => {}
+        }
+    }
+
+    /// Verify a downloaded piece and write it to disk in the background, the result is
+    /// handled by [`Swarm::piece_written`]
+    fn complete_piece(&mut self, index: u32, data: Vec<u8>, engine: &Engine) {
+        let expected = *engine
+            .pieces
+            .get(index as usize)
+            .expect("picked piece should exist");
+        let storage = Arc::clone(&engine.storage);
+        self.writes.spawn_blocking(move || {
+            if Sha1::digest(&data).as_slice() != expected {
+                return (index, Ok(false));
+            }
+            (index, storage.write_block(index, 0, &data).map(|_| true))
+        });
+    }
+
+    /// Mark a piece as verified once written, or download it again when invalid
+    fn piece_written(&mut self, write: PieceWrite) -> Result<(), EngineError> {
+        let (index, res) = write;
+        let valid = res?;
+        self.picker.verified(index, valid);
+        if valid {
+            let keys: Vec<PeerKey> = self.peers.keys().copied().collect();
+            for key in keys {
+                let peer = &self.peers[&key];
+                if peer.connected {
+                    let _ = peer.cmds.send(Message::Have(index));
+                }
+                self.update_interest(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the next block requested by a peer, unless one is already being read.
+    /// The block stays queued meanwhile, so a `cancel` can still remove it.
+    fn serve_next(&mut self, key: PeerKey, storage: &Arc<dyn Storage>) {
+        let peer = match self.peers.get_mut(&key) {
+            Some(v) if !v.reading && !v.am_choking => v,
+            _ => return,
+        };
+        let block = match peer.uploads.front() {
+            Some(v) => *v,
+            None => return,
+        };
+        peer.reading = true;
+        let storage = Arc::clone(storage);
+        self.reads.spawn_blocking(move || {
+            let data = storage.read_block(block.index, block.begin, block.length);
+            (key, block, data)
+        });
+    }
+
+    /// Send a block read from disk, unless it was cancelled or the peer choked meanwhile,
+    /// then read the next one. A block that can't be read is dropped.
+    fn send_block(&mut self, read: BlockRead, storage: &Arc<dyn Storage>) {
+        let (key, block, data) = read;
+        let peer = match self.peers.get_mut(&key) {
+            Some(v) => v,
+            None => return,
+        };
+        peer.reading = false;
+        let pos = peer.uploads.iter().position(|b| *b == block);
+        if let (Some(pos), false) = (pos, peer.am_choking) {
+            peer.uploads.remove(pos);
+            if let Ok(data) = data {
+                self.uploaded += data.len() as u64;
+                peer.round_sent += data.len() as u64;
+                let _ = peer.cmds.send(Message::Piece {
+                    index: block.index,
+                    begin: block.begin,
+                    block: data,
+                });
+            }
+        }
+
+        self.serve_next(key, storage);
+    }
+
+    /// Run a round of the choking algorithm and apply its decision
+    fn rechoke(&mut self, choker: &mut dyn Choker, seeding: bool, elapsed: Duration) {
+        let secs = elapsed.as_secs().max(1);
+        let peers: Vec<ChokePeer> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.connected)
+            .map(|(k, p)| ChokePeer {
+                id: *k,
+                interested: p.peer_interested,
+                choked: p.am_choking,
+                download_rate: p.round_received / secs,
+                upload_rate: p.round_sent / secs,
+                snubbed: p.snubbed,
+                connected_for: p.connected_at.elapsed(),
+            })
+            .collect();
+        let unchoked = choker.rechoke(&peers, seeding);
+
+        for (key, peer) in self.peers.iter_mut() {
+            (peer.round_received, peer.round_sent) = (0, 0);
+            if !peer.connected {
+                continue;
+            }
+            let choke = !unchoked.contains(key);
+            if choke != peer.am_choking {
+                peer.am_choking = choke;
+                if choke {
+                    peer.uploads.clear();
+                }
+                let _ = peer.cmds.send(if choke {
+                    Message::Choke
+                } else {
+                    Message::Unchoke
+                });
+            }
+        }
+    }
+
+    /// Unchoke interested peers while upload slots are free, without waiting for the
+    /// next choking round
+    fn unchoke_peers(&mut self, slots: usize) {
+        let mut unchoked = self.peers.values().filter(|p| !p.am_choking).count();
+        for peer in self.peers.values_mut() {
+            if unchoked >= slots {
+                return;
+            }
+            if peer.connected && peer.am_choking && peer.peer_interested {
+                peer.am_choking = false;
+                let _ = peer.cmds.send(Message::Unchoke);
+                unchoked += 1;
+            }
+        }
+    }
+
+    /// Disconnect peers owning every piece, once we own them too
+    fn drop_seeds(&mut self) {
+        let seeds: Vec<PeerKey> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.connected && p.has.iter().all(|h| *h))
+            .map(|(k, _)| *k)
+            .collect();
+        for key in seeds {
+            self.remove_peer(key);
+        }
+    }
+
+    /// Tell the peer whether it has pieces we are missing, when it changes
+    fn update_interest(&mut self, key: PeerKey) {
+        let peer = match self.peers.get_mut(&key) {
+            Some(v) => v,
+            None => return,
+        };
+        let interested = self.picker.is_interesting(&peer.has);
+        if interested != peer.am_interested && peer.connected {
+            peer.am_interested = interested;
+            let _ = peer.cmds.send(if interested {
+                Message::Interested
+            } else {
+                Message::NotInterested
+            });
+        }
+    }
+
+    /// Let the extensions of each peer send their periodic messages
+    fn tick_extensions(&mut self) {
+        for peer in self.peers.values_mut() {
+            if let Some(extensions) = peer.extensions.as_mut() {
+                for msg in extensions.on_tick() {
+                    let _ = peer.cmds.send(msg);
+                }
+            }
+        }
+    }
+
+    /// Fill the request pipeline of an unchoked peer
+    fn request_blocks(&mut self, key: PeerKey) {
+        let peer = match self.peers.get_mut(&key) {
+            Some(v) => v,
+            None => return,
+        };
+        if !peer.connected || peer.peer_choking || !peer.am_interested {
+            return;
+        }
+
+        let depth = match (peer.snubbed, peer.reqq) {
+            (true, _) => 1,
+            (false, 0) => PIPELINE_DEPTH,
+            (false, reqq) => PIPELINE_DEPTH.min(reqq),
+        };
+        while peer.requests.len() < depth {
+            let block = match self.picker.pick(&peer.has, &peer.requests) {
+                Some(v) => v,
+                None => return,
+            };
+            if peer.requests.is_empty() {
+                peer.last_block = Instant::now();
+            }
+            peer.requests.push(block);
+            let _ = peer.cmds.send(Message::Request {
+                index: block.index,
+                begin: block.begin,
+                length: block.length,
+            });
+        }
+    }
+
+    /// Cancel the copies of a received block requested to other peers in endgame mode
+    fn cancel_duplicates(&mut self, key: PeerKey, block: Block) {
+        for (_, peer) in self.peers.iter_mut().filter(|(k, _)| **k != key) {
+            if let Some(pos) = peer.requests.iter().position(|b| *b == block) {
+                peer.requests.swap_remove(pos);
+                self.picker.release(block);
+                let _ = peer.cmds.send(Message::Cancel {
+                    index: block.index,
+                    begin: block.begin,
+                    length: block.length,
+                });
+            }
+        }
+    }
+
+    fn remove_peer(&mut self, key: PeerKey) {
+        if let Some(peer) = self.peers.remove(&key) {
+            if let (Some(pex), Some(listen)) = (&self.pex, peer.listen_addr) {
+                pex.disconnected(&listen);
+            }
+            self.picker.remove_peer(&peer.has);
+            for b in peer.requests {
+                self.picker.release(b);
+            }
+        }
+    }
+
+    /// Snub peers not answering our requests, so the blocks can be requested to others
+    fn check_snubbed(&mut self) {
+        let snubbed: Vec<PeerKey> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| !p.requests.is_empty() && p.last_block.elapsed() > SNUB_TIMEOUT)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in snubbed {
+            let peer = self.peers.get_mut(&key).expect("snubbed peer should exist");
+            peer.snubbed = true;
+            for b in peer.requests.drain(..) {
+                self.picker.release(b);
+                let _ = peer.cmds.send(Message::Cancel {
+                    index: b.index,
+                    begin: b.begin,
+                    length: b.length,
+                });
+            }
+            self.request_blocks(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{
+        extension::{metadata::UT_METADATA, pex::UT_PEX},
+        storage::MemoryStorage,
+    };
+
+    use super::*;
+
+    fn torrent_bytes(private: bool) -> Vec<u8> {
+        let private = if private { "7:privatei1e" } else { "" };
+        format!(
+            "d8:announce8:http://a4:infod6:lengthi1e4:name1:a12:piece lengthi1e\
+            6:pieces20:aaaaaaaaaaaaaaaaaaaa{private}ee"
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn private_torrents_have_no_peer_exchange() {
+        let output = env::temp_dir();
+        let bytes = torrent_bytes(false);
+        let public = Engine::new(&Torrent::parse_bytes(&bytes).unwrap(), &output).unwrap();
+        assert!(public.pex.is_some());
+        assert!(public.extensions.id(UT_PEX).is_some());
+
+        let bytes = torrent_bytes(true);
+        let private = Engine::new(&Torrent::parse_bytes(&bytes).unwrap(), &output).unwrap();
+        assert!(private.pex.is_none());
+        assert_eq!(private.extensions.id(UT_PEX), None);
+        assert!(private.extensions.id(UT_METADATA).is_some());
+    }
+
+    /// HTTP tracker answering every announce with a single local peer listening on `port`
+    async fn stub_tracker(port: u16) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let mut body = b"d8:intervali1800e5:peers6:".to_vec();
+        body.extend_from_slice(&Ipv4Addr::LOCALHOST.octets());
+        body.extend_from_slice(&port.to_be_bytes());
+        body.push(b'e');
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+            }
+        });
+
+        url
+    }
+
+    async fn free_port() -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn download_from_another_engine() {
+        const PIECE_LENGTH: u64 = 32768;
+        let content: Vec<u8> = (0..80000u32).map(|i| (i % 251) as u8).collect();
+        let pieces: Vec<u8> = content
+            .chunks(PIECE_LENGTH as usize)
+            .flat_map(|c| Sha1::digest(c).to_vec())
+            .collect();
+        let seeder_port = free_port().await;
+        let tracker = stub_tracker(seeder_port).await;
+        let mut bytes = format!(
+            "d8:announce{}:{tracker}4:infod6:lengthi{}e4:name1:a12:piece lengthi{PIECE_LENGTH}e\
+            6:pieces{}:",
+            tracker.len(),
+            content.len(),
+            pieces.len()
+        )
+        .into_bytes();
+        bytes.extend_from_slice(&pieces);
+        bytes.extend_from_slice(b"ee");
+        let torrent = Torrent::parse_bytes(&bytes).unwrap();
+        let output = env::temp_dir();
+
+        let seeder = Engine::new(&torrent, &output)
+            .unwrap()
+            .port(seeder_port)
+            .storage(Arc::new(MemoryStorage::with_data(
+                content.clone(),
+                PIECE_LENGTH,
+            )))
+            .verified(vec![true; torrent.info.pieces.len()])
+            .seed(SeedTarget::default());
+        let seeder_cancel = seeder.cancel_handle();
+        let seeder = tokio::spawn(seeder.run(|_| {}));
+
+        let storage = Arc::new(MemoryStorage::new(content.len() as u64, PIECE_LENGTH));
+        let downloader = Engine::new(&torrent, &output)
+            .unwrap()
+            .port(free_port().await)
+            .storage(Arc::clone(&storage) as Arc<dyn Storage>);
+        time::timeout(Duration::from_secs(30), downloader.run(|_| {}))
+            .await
+            .expect("download should complete")
+            .unwrap();
+        assert_eq!(storage.to_vec(), content);
+
+        seeder_cancel.cancel();
+        seeder.await.unwrap().unwrap();
+    }
+}
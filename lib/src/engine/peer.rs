@@ -0,0 +1,146 @@
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time,
+};
+
+use crate::{
+    bandwidth::LimiterChain,
+    torrent::{
+        errors::TcpError,
+        v1::{Handshake, Message, PeerConnection},
+    },
+};
+
+/// A keep-alive is sent when nothing else was sent for this long
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(90);
+
+/// Identifier of a peer task, unique for the lifetime of an engine
+pub(super) type PeerKey = usize;
+
+/// How the connection with a peer is established
+#[derive(Debug)]
+pub(super) enum Target {
+    /// Outgoing connection to a peer returned by a tracker
+    Connect(SocketAddr),
+    /// Incoming connection accepted by the engine listener
+    Accept(TcpStream),
+    /// Incoming connection already handshaked by a shared listener
+    Accepted(PeerConnection),
+}
+
+/// Source of incoming connections
+#[derive(Debug)]
+pub(super) enum Incoming {
+    /// Listener owned by the engine
+    Listener(TcpListener),
+    /// Connections dispatched by a listener shared between engines
+    Shared(mpsc::UnboundedReceiver<PeerConnection>),
+}
+
+impl Incoming {
+    /// Wait for the next incoming connection. `None` if it failed or the source is closed.
+    pub async fn next(&mut self) -> Option<Target> {
+        match self {
+            Incoming::Listener(l) => l.accept().await.ok().map(|(s, _)| Target::Accept(s)),
+            Incoming::Shared(rx) => rx.recv().await.map(Target::Accepted),
+        }
+    }
+}
+
+/// Events sent by peer tasks to the engine
+#[derive(Debug)]
+pub(super) enum PeerEvent {
+    /// Handshake succeeded with the peer at the given address
+    Connected(PeerKey, Handshake, SocketAddr),
+    Message(PeerKey, Message),
+    /// Connection is over
+    Closed(PeerKey),
+}
+
+/// Rate limiters consulted before sending and after receiving block payloads
+#[derive(Debug, Clone, Default)]
+pub(super) struct PeerLimiters {
+    pub upload: LimiterChain,
+    pub download: LimiterChain,
+}
+
+/// Establish the connection and relay messages until it fails or the engine drops the
+/// command sender.
+pub(super) async fn run(
+    key: PeerKey,
+    target: Target,
+    local: Handshake,
+    events: mpsc::UnboundedSender<PeerEvent>,
+    cmds: mpsc::UnboundedReceiver<Message>,
+    limiters: PeerLimiters,
+) {
+    let _ = session(key, target, local, &events, cmds, limiters).await;
+    let _ = events.send(PeerEvent::Closed(key));
+}
+
+async fn session(
+    key: PeerKey,
+    target: Target,
+    local: Handshake,
+    events: &mpsc::UnboundedSender<PeerEvent>,
+    mut cmds: mpsc::UnboundedReceiver<Message>,
+    limiters: PeerLimiters,
+) -> Result<(), TcpError> {
+    let conn = match target {
+        Target::Connect(addr) => PeerConnection::connect(addr, local).await?,
+        Target::Accept(stream) => {
+            PeerConnection::accept(stream, |remote| {
+                (remote.info_hash == local.info_hash).then_some(local)
+            })
+            .await?
+        }
+        Target::Accepted(conn) => conn,
+    };
+    let addr = conn.peer_addr()?;
+    if events
+        .send(PeerEvent::Connected(key, *conn.remote(), addr))
+        .is_err()
+    {
+        return Ok(());
+    }
+
+    let (mut reader, mut writer) = conn.into_split();
+    let read = async {
+        loop {
+            let msg = reader.recv().await?;
+            let payload = match &msg {
+                Message::Piece { block, .. } => block.len() as u64,
+                _ => 0,
+            };
+            if events.send(PeerEvent::Message(key, msg)).is_err() {
+                return Ok::<(), TcpError>(());
+            }
+            // Not reading the socket slows the peer down once the TCP window is full
+            if payload > 0 {
+                limiters.download.acquire(payload).await;
+            }
+        }
+    };
+    let write = async {
+        loop {
+            match time::timeout(KEEPALIVE_INTERVAL, cmds.recv()).await {
+                Ok(Some(msg)) => {
+                    if let Message::Piece { block, .. } = &msg {
+                        limiters.upload.acquire(block.len() as u64).await;
+                    }
+                    writer.send(&msg).await?
+                }
+                Ok(None) => return Ok::<(), TcpError>(()),
+                Err(_) => writer.send(&Message::KeepAlive).await?,
+            }
+        }
+    };
+
+    tokio::select! {
+        res = read => res,
+        res = write => res,
+    }
+}
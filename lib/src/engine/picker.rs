@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+/// Size of the blocks requested to peers (16 KiB), as used by most clients
+pub(super) const BLOCK_LEN: u32 = 16 * 1024;
+
+/// Order in which missing pieces are downloaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PickMode {
+    /// Pieces owned by the fewest peers first, ties broken randomly.
+    /// Keeps every piece available in the swarm.
+    #[default]
+    RarestFirst,
+    /// Pieces in index order, e.g. to play a media file while downloading
+    Sequential,
+}
+
+/// Download priority of a file. Pieces overlapping several files get the highest one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// The file is not downloaded. Pieces shared with wanted files still are.
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Part of a piece requested in a single `request` message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct Block {
+    pub index: u32,
+    pub begin: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PieceState {
+    Missing,
+    /// Blocks are being downloaded or the piece is being verified
+    Downloading,
+    Have,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockState {
+    Free,
+    /// Number of peers the block is requested to. More than one in endgame mode.
+    Requested(u32),
+    Received,
+}
+
+#[derive(Debug)]
+struct PartialPiece {
+    data: Vec<u8>,
+    blocks: Vec<BlockState>,
+}
+
+/// Picker decides which blocks to request, and assembles them into pieces.
+///
+/// Pieces are picked by priority first, then pieces already started are completed before
+/// new ones are picked following the [`PickMode`]. Once every missing block is requested,
+/// the picker switches to endgame mode: blocks already requested to other peers are
+/// requested again, the first copy received wins.
+#[derive(Debug)]
+pub(super) struct Picker {
+    states: Vec<PieceState>,
+    /// Number of connected peers owning each piece
+    availability: Vec<u32>,
+    priorities: Vec<Priority>,
+    partials: HashMap<u32, PartialPiece>,
+    mode: PickMode,
+    piece_length: u64,
+    total_length: u64,
+}
+
+impl Picker {
+    /// Create a picker knowing which pieces are already available
+    pub fn new(have: &[bool], piece_length: u64, total_length: u64) -> Self {
+        Self {
+            states: have
+                .iter()
+                .map(|h| {
+                    if *h {
+                        PieceState::Have
+                    } else {
+                        PieceState::Missing
+                    }
+                })
+                .collect(),
+            availability: vec![0; have.len()],
+            priorities: vec![Priority::Normal; have.len()],
+            partials: HashMap::new(),
+            mode: PickMode::default(),
+            piece_length,
+            total_length,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: PickMode) {
+        self.mode = mode;
+    }
+
+    /// Derive piece priorities from the priority of each file, given in the same order as
+    /// their lengths. Missing priorities are considered normal.
+    pub fn set_file_priorities(&mut self, file_lengths: &[u64], priorities: &[Priority]) {
+        if self.states.is_empty() {
+            return;
+        }
+        self.priorities.fill(Priority::Skip);
+        let mut offset = 0;
+        for (i, length) in file_lengths.iter().enumerate() {
+            if *length > 0 {
+                let priority = priorities.get(i).copied().unwrap_or_default();
+                let first = (offset / self.piece_length) as usize;
+                let last = ((offset + length - 1) / self.piece_length) as usize;
+                for p in &mut self.priorities[first..=last.min(self.states.len() - 1)] {
+                    *p = (*p).max(priority);
+                }
+            }
+            offset += length;
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn has(&self, index: u32) -> bool {
+        self.states.get(index as usize) == Some(&PieceState::Have)
+    }
+
+    /// Is the piece part of a file which isn't skipped
+    fn is_wanted(&self, index: usize) -> bool {
+        self.priorities[index] != Priority::Skip
+    }
+
+    pub fn have_count(&self) -> usize {
+        self.states
+            .iter()
+            .filter(|s| **s == PieceState::Have)
+            .count()
+    }
+
+    /// Every wanted piece is verified
+    pub fn is_complete(&self) -> bool {
+        self.states
+            .iter()
+            .enumerate()
+            .all(|(i, s)| *s == PieceState::Have || !self.is_wanted(i))
+    }
+
+    /// Size of the piece at `index`, the last one being usually shorter
+    pub fn piece_size(&self, index: u32) -> u32 {
+        let start = index as u64 * self.piece_length;
+        (self.total_length - start).min(self.piece_length) as u32
+    }
+
+    /// Number of wanted bytes not verified yet
+    pub fn left(&self) -> u64 {
+        (0..self.states.len() as u32)
+            .filter(|i| !self.has(*i) && self.is_wanted(*i as usize))
+            .map(|i| self.piece_size(i) as u64)
+            .sum()
+    }
+
+    /// Number of bytes in wanted pieces
+    pub fn wanted_length(&self) -> u64 {
+        (0..self.states.len() as u32)
+            .filter(|i| self.is_wanted(*i as usize))
+            .map(|i| self.piece_size(i) as u64)
+            .sum()
+    }
+
+    /// Validity of each piece
+    pub fn pieces(&self) -> Vec<bool> {
+        self.states.iter().map(|s| *s == PieceState::Have).collect()
+    }
+
+    /// Our pieces in the `bitfield` message format
+    pub fn bitfield(&self) -> Vec<u8> {
+        to_bitfield(&self.pieces())
+    }
+
+    /// Does the peer have a wanted piece we are missing
+    pub fn is_interesting(&self, peer_has: &[bool]) -> bool {
+        self.states
+            .iter()
+            .zip(peer_has)
+            .enumerate()
+            .any(|(i, (s, h))| *h && *s != PieceState::Have && self.is_wanted(i))
+    }
+
+    /// Count the pieces of a peer in the availability, from its bitfield
+    pub fn add_peer(&mut self, peer_has: &[bool]) {
+        for (a, _) in self
+            .availability
+            .iter_mut()
+            .zip(peer_has)
+            .filter(|(_, h)| **h)
+        {
+            *a += 1;
+        }
+    }
+
+    /// Remove the pieces of a disconnected peer from the availability
+    pub fn remove_peer(&mut self, peer_has: &[bool]) {
+        for (a, _) in self
+            .availability
+            .iter_mut()
+            .zip(peer_has)
+            .filter(|(_, h)| **h)
+        {
+            *a = a.saturating_sub(1);
+        }
+    }
+
+    /// Count a piece announced by a `have` message
+    pub fn add_have(&mut self, index: u32) {
+        if let Some(a) = self.availability.get_mut(index as usize) {
+            *a += 1;
+        }
+    }
+
+    /// Pick the next block to request to a peer owning `peer_has`.
+    /// `requested` holds the blocks already requested to this peer.
+    pub fn pick(&mut self, peer_has: &[bool], requested: &[Block]) -> Option<Block> {
+        if let Some(index) = self.pick_piece(peer_has) {
+            if let Some(partial) = self.partials.get(&index) {
+                let b = partial
+                    .blocks
+                    .iter()
+                    .position(|b| *b == BlockState::Free)
+                    .expect("picked piece should have a free block");
+                return Some(self.request(index, b as u32));
+            }
+
+            let size = self.piece_size(index);
+            self.states[index as usize] = PieceState::Downloading;
+            self.partials.insert(
+                index,
+                PartialPiece {
+                    data: vec![0; size as usize],
+                    blocks: vec![BlockState::Free; size.div_ceil(BLOCK_LEN) as usize],
+                },
+            );
+
+            return Some(self.request(index, 0));
+        }
+
+        if self.is_endgame() {
+            return self.pick_endgame(peer_has, requested);
+        }
+
+        None
+    }
+
+    /// Best piece owned by the peer with a block left to request
+    fn pick_piece(&self, peer_has: &[bool]) -> Option<u32> {
+        let mut rng = rand::thread_rng();
+        let mut best: Option<(u32, (Priority, bool, u64))> = None;
+        let mut ties = 0;
+        for (i, state) in self.states.iter().enumerate() {
+            if !peer_has.get(i).copied().unwrap_or_default() {
+                continue;
+            }
+            if !self.is_wanted(i) {
+                continue;
+            }
+            let started = match state {
+                PieceState::Missing => false,
+                PieceState::Downloading => self
+                    .partials
+                    .get(&(i as u32))
+                    .is_some_and(|p| p.blocks.contains(&BlockState::Free)),
+                PieceState::Have => continue,
+            };
+            if *state == PieceState::Downloading && !started {
+                continue;
+            }
+
+            let rank = match self.mode {
+                PickMode::RarestFirst => u64::MAX - self.availability[i] as u64,
+                PickMode::Sequential => u64::MAX - i as u64,
+            };
+            // Sequential mode strictly follows the piece order, started or not
+            let started = started && self.mode == PickMode::RarestFirst;
+            let score = (self.priorities[i], started, rank);
+            match &best {
+                Some((_, s)) if score < *s => {}
+                Some((_, s)) if score == *s => {
+                    // Reservoir sampling: each tied piece has the same chance to be picked
+                    ties += 1;
+                    if rng.gen_range(0..=ties) == 0 {
+                        best = Some((i as u32, score));
+                    }
+                }
+                _ => {
+                    best = Some((i as u32, score));
+                    ties = 0;
+                }
+            }
+        }
+
+        best.map(|(i, _)| i)
+    }
+
+    /// Every missing block of the wanted pieces is requested at least once
+    fn is_endgame(&self) -> bool {
+        !self
+            .states
+            .iter()
+            .enumerate()
+            .any(|(i, s)| *s == PieceState::Missing && self.is_wanted(i))
+            && self
+                .partials
+                .values()
+                .all(|p| !p.blocks.contains(&BlockState::Free))
+    }
+
+    /// Least requested block owned by the peer and not already requested to it
+    fn pick_endgame(&mut self, peer_has: &[bool], requested: &[Block]) -> Option<Block> {
+        let mut best: Option<(u32, u32, u32)> = None;
+        for (index, partial) in &self.partials {
+            if !peer_has.get(*index as usize).copied().unwrap_or_default() {
+                continue;
+            }
+            for (b, state) in partial.blocks.iter().enumerate() {
+                let count = match state {
+                    BlockState::Requested(v) => *v,
+                    _ => continue,
+                };
+                let begin = b as u32 * BLOCK_LEN;
+                if requested
+                    .iter()
+                    .any(|r| r.index == *index && r.begin == begin)
+                {
+                    continue;
+                }
+                if best.is_none_or(|(_, _, c)| count < c) {
+                    best = Some((*index, b as u32, count));
+                }
+            }
+        }
+
+        best.map(|(index, b, _)| self.request(index, b))
+    }
+
+    fn request(&mut self, index: u32, block: u32) -> Block {
+        let partial = self
+            .partials
+            .get_mut(&index)
+            .expect("requested piece should be started");
+        let state = &mut partial.blocks[block as usize];
+        *state = match state {
+            BlockState::Requested(v) => BlockState::Requested(*v + 1),
+            _ => BlockState::Requested(1),
+        };
+        let begin = block * BLOCK_LEN;
+
+        Block {
+            index,
+            begin,
+            length: (partial.data.len() as u32 - begin).min(BLOCK_LEN),
+        }
+    }
+
+    /// Make a requested block available again, after a choke or a disconnection
+    pub fn release(&mut self, block: Block) {
+        if let Some(partial) = self.partials.get_mut(&block.index) {
+            if let Some(state) = partial.blocks.get_mut((block.begin / BLOCK_LEN) as usize) {
+                *state = match *state {
+                    BlockState::Requested(v) if v > 1 => BlockState::Requested(v - 1),
+                    BlockState::Requested(_) => BlockState::Free,
+                    s => s,
+                };
+            }
+        }
+    }
+
+    /// Store a received block. Returns the whole piece once every block is received,
+    /// it must then be verified and reported with [`Picker::verified`].
+    /// Copies of a block already received are ignored.
+    pub fn receive(&mut self, block: Block, data: &[u8]) -> Option<Vec<u8>> {
+        let partial = self.partials.get_mut(&block.index)?;
+        let b = (block.begin / BLOCK_LEN) as usize;
+        if !matches!(partial.blocks.get(b), Some(BlockState::Requested(_))) {
+            return None;
+        }
+        partial.data[block.begin as usize..(block.begin + block.length) as usize]
+            .copy_from_slice(data);
+        partial.blocks[b] = BlockState::Received;
+        if partial.blocks.iter().any(|b| *b != BlockState::Received) {
+            return None;
+        }
+
+        self.partials.remove(&block.index).map(|p| p.data)
+    }
+
+    /// Record the result of a piece verification. Invalid pieces are downloaded again.
+    pub fn verified(&mut self, index: u32, valid: bool) {
+        self.states[index as usize] = if valid {
+            PieceState::Have
+        } else {
+            PieceState::Missing
+        };
+    }
+}
+
+/// Decode a `bitfield` message into one boolean per piece. Spare bits are ignored.
+pub(super) fn from_bitfield(bytes: &[u8], piece_count: usize) -> Vec<bool> {
+    (0..piece_count)
+        .map(|i| bytes.get(i / 8).is_some_and(|b| b & (0x80 >> (i % 8)) != 0))
+        .collect()
+}
+
+pub(super) fn to_bitfield(pieces: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0u8; pieces.len().div_ceil(8)];
+    for (i, _) in pieces.iter().enumerate().filter(|(_, h)| **h) {
+        bytes[i / 8] |= 0x80 >> (i % 8);
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIECE_LEN: u64 = 2 * BLOCK_LEN as u64;
+
+    fn picker(pieces: usize) -> Picker {
+        Picker::new(&vec![false; pieces], PIECE_LEN, pieces as u64 * PIECE_LEN)
+    }
+
+    #[test]
+    fn rarest_piece_first() {
+        let mut picker = picker(3);
+        picker.add_peer(&[true, true, true]);
+        picker.add_peer(&[true, false, true]);
+        picker.add_have(0);
+
+        let block = picker.pick(&[true, true, true], &[]).unwrap();
+        assert_eq!(block.index, 1);
+    }
+
+    #[test]
+    fn sequential_mode_follows_piece_order() {
+        let mut picker = picker(3);
+        picker.set_mode(PickMode::Sequential);
+        picker.add_peer(&[true, true, false]);
+
+        let picked: Vec<_> = (0..4)
+            .map(|_| picker.pick(&[true, true, true], &[]).unwrap())
+            .map(|b| (b.index, b.begin))
+            .collect();
+        assert_eq!(picked, [(0, 0), (0, BLOCK_LEN), (1, 0), (1, BLOCK_LEN)]);
+    }
+
+    #[test]
+    fn started_pieces_are_completed_first() {
+        let mut picker = picker(2);
+        picker.add_peer(&[false, true]);
+        picker.add_peer(&[false, true]);
+        let first = picker.pick(&[false, true], &[]).unwrap();
+
+        // Piece 0 is rarer for this peer but piece 1 is already started
+        let second = picker.pick(&[true, true], &[]).unwrap();
+        assert_eq!(first.index, 1);
+        assert_eq!((second.index, second.begin), (1, BLOCK_LEN));
+    }
+
+    #[test]
+    fn high_priority_pieces_first() {
+        let mut picker = picker(3);
+        let files = [PIECE_LEN, PIECE_LEN / 2, PIECE_LEN * 3 / 2];
+        picker.set_file_priorities(&files, &[Priority::Low, Priority::High, Priority::Normal]);
+        // Piece 0 is the rarest but has the lowest priority
+        picker.add_peer(&[false, true, true]);
+
+        // Piece 1 is shared by the high and normal priority files
+        let picked: Vec<_> = (0..6)
+            .map(|_| picker.pick(&[true, true, true], &[]).unwrap().index)
+            .collect();
+        assert_eq!(picked, [1, 1, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn skipped_files_are_not_picked() {
+        let mut picker = picker(3);
+        let files = [PIECE_LEN, PIECE_LEN / 2, PIECE_LEN * 3 / 2];
+        picker.set_file_priorities(&files, &[Priority::Skip, Priority::High, Priority::Skip]);
+
+        // The wanted file shares piece 1 with the last one
+        assert_eq!(picker.wanted_length(), PIECE_LEN);
+        assert!(!picker.is_interesting(&[true, false, true]));
+        assert_eq!(picker.pick(&[true, false, true], &[]), None);
+        assert_eq!(picker.pick(&[true, true, true], &[]).unwrap().index, 1);
+    }
+
+    #[test]
+    fn receive_assembles_the_piece() {
+        let mut picker = Picker::new(&[false], PIECE_LEN, BLOCK_LEN as u64 + 10);
+        let first = picker.pick(&[true], &[]).unwrap();
+        let last = picker.pick(&[true], &[]).unwrap();
+        assert_eq!(last.length, 10);
+
+        assert_eq!(picker.receive(last, &[2; 10]), None);
+        let piece = picker.receive(first, &[1; BLOCK_LEN as usize]).unwrap();
+        assert_eq!(piece.len(), BLOCK_LEN as usize + 10);
+        assert_eq!(piece[BLOCK_LEN as usize..], [2; 10]);
+
+        picker.verified(0, true);
+        assert!(picker.is_complete());
+        assert_eq!(picker.left(), 0);
+    }
+
+    #[test]
+    fn endgame_requests_blocks_again() {
+        let mut picker = picker(1);
+        let a = picker.pick(&[true], &[]).unwrap();
+        let b = picker.pick(&[true], &[]).unwrap();
+
+        let again = picker.pick(&[true], &[a]).unwrap();
+        assert_eq!(again, b);
+        assert_eq!(picker.pick(&[true], &[a, b]), None);
+
+        // The second copy of a block is ignored
+        assert_eq!(picker.receive(b, &[0; BLOCK_LEN as usize]), None);
+        assert_eq!(picker.receive(b, &[0; BLOCK_LEN as usize]), None);
+        assert!(picker.receive(a, &[0; BLOCK_LEN as usize]).is_some());
+    }
+
+    #[test]
+    fn released_and_invalid_blocks_are_picked_again() {
+        let mut picker = picker(1);
+        let a = picker.pick(&[true], &[]).unwrap();
+        picker.release(a);
+        assert_eq!(picker.pick(&[true], &[]), Some(a));
+
+        let b = picker.pick(&[true], &[]).unwrap();
+        picker.receive(a, &[0; BLOCK_LEN as usize]);
+        picker.receive(b, &[0; BLOCK_LEN as usize]).unwrap();
+        picker.verified(0, false);
+        assert_eq!(picker.pick(&[true], &[]), Some(a));
+    }
+
+    #[test]
+    fn bitfield_round_trip() {
+        let pieces = [true, false, false, true, false, false, false, false, true];
+        let bytes = to_bitfield(&pieces);
+
+        assert_eq!(bytes, [0b1001_0000, 0b1000_0000]);
+        assert_eq!(from_bitfield(&bytes, pieces.len()), pieces);
+        assert_eq!(from_bitfield(&[0xff], 3), [true; 3]);
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use bendy::serde::{from_bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+
+use crate::{torrent::InfoHash, tracker::manager::TrackerState};
+
+use super::errors::ResumeError;
+
+/// Extension appended to the torrent file name to store its resume data
+const RESUME_EXTENSION: &str = "resume";
+/// Version of the resume data format
+const FORMAT_VERSION: u32 = 1;
+
+/// ResumeData holds the state of a torrent between two sessions, so a restarted download
+/// doesn't need to hash its content again.
+///
+/// It is stored bencoded, next to the `.torrent` file (see [`ResumeData::path_for`]).
+/// Pieces are only trusted if every file still has the size and modification time recorded
+/// when the data was saved, see [`ResumeData::is_valid_for`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ResumeData {
+    #[serde(rename = "file-version")]
+    pub version: u32,
+    #[serde(rename = "info-hash")]
+    pub info_hash: InfoHash,
+    /// Verified pieces, one byte per piece set to `1` when the piece is valid
+    #[serde(with = "piece_flags")]
+    pub pieces: Vec<bool>,
+    /// State of each file on disk, in the same order as `TorrentInfo.files`
+    #[serde(rename = "file sizes")]
+    pub files: Vec<ResumeFile>,
+    /// Payload bytes sent over every session
+    #[serde(rename = "total uploaded")]
+    pub uploaded: u64,
+    /// Payload bytes received over every session
+    #[serde(rename = "total downloaded")]
+    pub downloaded: u64,
+    /// Peers seen during the last sessions
+    #[serde(default, with = "compact_peers")]
+    pub peers: Vec<SocketAddr>,
+    #[serde(default)]
+    pub trackers: Vec<Vec<TrackerState>>,
+    /// Files skipped during the last session, one byte per file set to `1` when skipped.
+    /// Their parts kept in the partfile are moved when this changes.
+    #[serde(default, rename = "skipped files", with = "piece_flags")]
+    pub skipped: Vec<bool>,
+}
+
+/// Size and modification time of a file on disk. Both are `0` for a missing file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ResumeFile {
+    pub length: u64,
+    /// Seconds since the Unix epoch
+    pub mtime: i64,
+}
+
+impl ResumeFile {
+    /// Read the current state of the file at `path`
+    pub fn stat(path: &Path) -> Self {
+        let metadata = match fs::metadata(path) {
+            Ok(v) => v,
+            Err(_) => return Self::default(),
+        };
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as i64);
+
+        Self {
+            length: metadata.len(),
+            mtime,
+        }
+    }
+}
+
+impl ResumeData {
+    /// Capture the resume data of a torrent. `files` are the content files, given as their
+    /// path and expected length, their current state is read from disk.
+    pub fn new(info_hash: InfoHash, pieces: Vec<bool>, files: &[(PathBuf, u64)]) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            info_hash,
+            pieces,
+            files: files.iter().map(|(p, _)| ResumeFile::stat(p)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Location of the resume data of the torrent file at `torrent_path`
+    pub fn path_for(torrent_path: &Path) -> PathBuf {
+        let mut path = torrent_path.as_os_str().to_owned();
+        path.push(".");
+        path.push(RESUME_EXTENSION);
+
+        path.into()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ResumeError> {
+        let data: Self = from_bytes(bytes).map_err(ResumeError::Parse)?;
+        if data.version != FORMAT_VERSION {
+            return Err(ResumeError::UnsupportedVersion(data.version));
+        }
+
+        Ok(data)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ResumeError> {
+        to_bytes(self).map_err(ResumeError::Encode)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ResumeError> {
+        let bytes = fs::read(path).map_err(|e| ResumeError::Read(path.to_path_buf(), e))?;
+
+        Self::from_bytes(&bytes)
+    }
+
+    /// Write the resume data to `path`. A temporary file is renamed over the previous one,
+    /// so an interrupted save never leaves a truncated file behind.
+    pub fn save(&self, path: &Path) -> Result<(), ResumeError> {
+        let bytes = self.to_bytes()?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        fs::write(&tmp, bytes)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| ResumeError::Write(path.to_path_buf(), e))
+    }
+
+    /// Check the data still describes the content on disk: same torrent, same number of
+    /// pieces and files, and every file has kept its size and modification time.
+    /// When it fails, pieces must be checked again.
+    pub fn is_valid_for(
+        &self,
+        info_hash: InfoHash,
+        piece_count: usize,
+        files: &[(PathBuf, u64)],
+    ) -> bool {
+        self.info_hash == info_hash
+            && self.pieces.len() == piece_count
+            && self.files.len() == files.len()
+            && self
+                .files
+                .iter()
+                .zip(files)
+                .all(|(saved, (path, _))| *saved == ResumeFile::stat(path))
+    }
+}
+
+mod piece_flags {
+    use serde::{Deserializer, Serializer};
+    use serde_with::{Bytes, DeserializeAs, SerializeAs};
+
+    pub fn serialize<S: Serializer>(pieces: &[bool], serializer: S) -> Result<S::Ok, S::Error> {
+        let flags: Vec<u8> = pieces.iter().map(|v| *v as u8).collect();
+        Bytes::serialize_as(&flags, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<bool>, D::Error> {
+        let flags: Vec<u8> = Bytes::deserialize_as(deserializer)?;
+        Ok(flags.into_iter().map(|v| v != 0).collect())
+    }
+}
+
+/// Peers are stored as a list of compact addresses: 4 or 16 bytes of IP followed by the port
+mod compact_peers {
+    use super::*;
+
+    use serde::{de, Deserializer, Serializer};
+    use serde_with::{Bytes, DeserializeAs, SerializeAs};
+
+    pub fn serialize<S: Serializer>(
+        peers: &[SocketAddr],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let compact: Vec<Vec<u8>> = peers
+            .iter()
+            .map(|p| {
+                let mut bytes = match p.ip() {
+                    IpAddr::V4(ip) => ip.octets().to_vec(),
+                    IpAddr::V6(ip) => ip.octets().to_vec(),
+                };
+                bytes.extend(p.port().to_be_bytes());
+                bytes
+            })
+            .collect();
+
+        Vec::<Bytes>::serialize_as(&compact, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<SocketAddr>, D::Error> {
+        let compact: Vec<Vec<u8>> = Vec::<Bytes>::deserialize_as(deserializer)?;
+        compact
+            .into_iter()
+            .map(|b| {
+                let ip = match b.len() {
+                    6 => IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])),
+                    18 => {
+                        let mut octets = [0u8; 16];
+                        octets.copy_from_slice(&b[..16]);
+                        IpAddr::V6(Ipv6Addr::from(octets))
+                    }
+                    n => return Err(de::Error::invalid_length(n, &"6 or 18 bytes")),
+                };
+                let port = u16::from_be_bytes([b[b.len() - 2], b[b.len() - 1]]);
+                Ok(SocketAddr::new(ip, port))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, time::Duration};
+
+    use super::*;
+
+    const HASH: InfoHash = InfoHash([7; 20]);
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("brs-resume-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = temp_dir("round-trip");
+        let path = ResumeData::path_for(&dir.join("a.torrent"));
+        let data = ResumeData {
+            uploaded: 10,
+            downloaded: 20,
+            peers: vec!["1.2.3.4:5".parse().unwrap(), "[::1]:6".parse().unwrap()],
+            skipped: vec![false, true],
+            ..ResumeData::new(HASH, vec![true, false, true], &[])
+        };
+
+        data.save(&path).unwrap();
+        assert_eq!(path, dir.join("a.torrent.resume"));
+        assert_eq!(ResumeData::load(&path).unwrap(), data);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn from_bytes_rejects_other_versions() {
+        let data = ResumeData {
+            version: FORMAT_VERSION + 1,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            ResumeData::from_bytes(&data.to_bytes().unwrap()),
+            Err(ResumeError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn is_valid_for_checks_files_on_disk() {
+        let dir = temp_dir("valid");
+        let files = vec![(dir.join("a"), 3), (dir.join("missing"), 5)];
+        fs::write(&files[0].0, b"abc").unwrap();
+        let data = ResumeData::new(HASH, vec![true, false], &files);
+
+        assert_eq!(data.files[1], ResumeFile::default());
+        assert!(data.is_valid_for(HASH, 2, &files));
+        assert!(!data.is_valid_for(InfoHash([0; 20]), 2, &files));
+        assert!(!data.is_valid_for(HASH, 3, &files));
+        assert!(!data.is_valid_for(HASH, 2, &files[..1]));
+
+        // Modified since the data was saved
+        let file = fs::File::options().write(true).open(&files[0].0).unwrap();
+        let mtime = file.metadata().unwrap().modified().unwrap();
+        file.set_modified(mtime + Duration::from_secs(10)).unwrap();
+        assert!(!data.is_valid_for(HASH, 2, &files));
+
+        // Created since the data was saved
+        let data = ResumeData::new(HASH, vec![true, false], &files);
+        fs::write(&files[1].0, b"").unwrap();
+        assert!(!data.is_valid_for(HASH, 2, &files));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
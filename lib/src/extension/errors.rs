@@ -0,0 +1,19 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ExtensionError {
+    #[error("Failed to parse extension message: {0}")]
+    Parse(bendy::serde::Error),
+    #[error("Failed to encode extension message: {0}")]
+    Encode(bendy::serde::Error),
+    #[error("Invalid extension message: {0}")]
+    InvalidMessage(String),
+    #[error("Metadata is too large: {0} bytes")]
+    MetadataTooLarge(u64),
+    #[error("Invalid metadata piece {0}")]
+    InvalidMetadataPiece(u32),
+    #[error("Peer rejected the request of metadata piece {0}")]
+    MetadataRejected(u32),
+    #[error("Metadata doesn't match the info hash")]
+    MetadataHashMismatch,
+}
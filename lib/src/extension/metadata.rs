@@ -0,0 +1,262 @@
+//! Metadata exchange extension (`ut_metadata`), as defined by
+//! [BEP 0009](https://www.bittorrent.org/beps/bep_0009.html).
+//!
+//! The `info` dictionary is split in pieces of 16 KiB, requested one by one from peers
+//! advertising its size in their extended handshake.
+
+use std::sync::Arc;
+
+use bendy::serde::{from_bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::torrent::{v1::raw_value_end, InfoHash};
+
+use super::{
+    errors::ExtensionError, ExtendedHandshake, Extension, ExtensionContext, PeerExtension,
+};
+
+/// Name of the extension in the extended handshake
+pub const UT_METADATA: &str = "ut_metadata";
+/// Size of every metadata piece but the last one
+pub const METADATA_PIECE_LEN: usize = 16 * 1024;
+/// Biggest metadata accepted from peers (8 MiB)
+pub const MAX_METADATA_SIZE: u64 = 8 * 1024 * 1024;
+
+const MSG_REQUEST: u8 = 0;
+const MSG_DATA: u8 = 1;
+const MSG_REJECT: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataMessage {
+    Request {
+        piece: u32,
+    },
+    /// Piece of the metadata, `total_size` being the size of the whole metadata
+    Data {
+        piece: u32,
+        total_size: u64,
+        data: Vec<u8>,
+    },
+    /// The peer doesn't serve the requested piece
+    Reject {
+        piece: u32,
+    },
+}
+
+/// Bencoded dictionary starting every message. `data` messages are followed by the piece.
+#[derive(Debug, Deserialize, Serialize)]
+struct Header {
+    msg_type: u8,
+    piece: u32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    total_size: u64,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+impl MetadataMessage {
+    /// Answer a request for `piece` of `metadata`, rejecting pieces out of range
+    pub fn respond(metadata: &[u8], piece: u32) -> Self {
+        let start = piece as usize * METADATA_PIECE_LEN;
+        if start >= metadata.len() {
+            return MetadataMessage::Reject { piece };
+        }
+        let end = (start + METADATA_PIECE_LEN).min(metadata.len());
+
+        MetadataMessage::Data {
+            piece,
+            total_size: metadata.len() as u64,
+            data: metadata[start..end].to_vec(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ExtensionError> {
+        let (header, data) = match self {
+            MetadataMessage::Request { piece } => (header(MSG_REQUEST, *piece, 0), &[][..]),
+            MetadataMessage::Data {
+                piece,
+                total_size,
+                data,
+            } => (header(MSG_DATA, *piece, *total_size), &data[..]),
+            MetadataMessage::Reject { piece } => (header(MSG_REJECT, *piece, 0), &[][..]),
+        };
+        let mut bytes = to_bytes(&header).map_err(ExtensionError::Encode)?;
+        bytes.extend_from_slice(data);
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        // Piece data follows the bencoded header
+        let len = raw_value_end(bytes, 0)
+            .ok_or_else(|| ExtensionError::InvalidMessage("truncated ut_metadata".into()))?;
+        let header: Header = from_bytes(&bytes[..len]).map_err(ExtensionError::Parse)?;
+
+        match header.msg_type {
+            MSG_REQUEST => Ok(MetadataMessage::Request {
+                piece: header.piece,
+            }),
+            MSG_DATA => Ok(MetadataMessage::Data {
+                piece: header.piece,
+                total_size: header.total_size,
+                data: bytes[len..].to_vec(),
+            }),
+            MSG_REJECT => Ok(MetadataMessage::Reject {
+                piece: header.piece,
+            }),
+            t => Err(ExtensionError::InvalidMessage(format!(
+                "unknown ut_metadata message type {t}"
+            ))),
+        }
+    }
+}
+
+fn header(msg_type: u8, piece: u32, total_size: u64) -> Header {
+    Header {
+        msg_type,
+        piece,
+        total_size,
+    }
+}
+
+/// MetadataAssembler collects the pieces of the metadata of a torrent and checks the result
+/// against its info hash.
+#[derive(Debug, Clone)]
+pub struct MetadataAssembler {
+    info_hash: InfoHash,
+    size: u64,
+    pieces: Vec<Option<Vec<u8>>>,
+}
+
+impl MetadataAssembler {
+    /// Prepare to receive `size` bytes of metadata, as advertised by a peer
+    pub fn new(info_hash: InfoHash, size: u64) -> Result<Self, ExtensionError> {
+        if size == 0 || size > MAX_METADATA_SIZE {
+            return Err(ExtensionError::MetadataTooLarge(size));
+        }
+        let count = (size as usize).div_ceil(METADATA_PIECE_LEN);
+
+        Ok(Self {
+            info_hash,
+            size,
+            pieces: vec![None; count],
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Pieces not received yet
+    pub fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_none())
+            .map(|(i, _)| i as u32)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(Option::is_some)
+    }
+
+    /// Store a received piece. Its length must match its position in the metadata.
+    pub fn add(&mut self, piece: u32, data: Vec<u8>) -> Result<(), ExtensionError> {
+        let start = piece as u64 * METADATA_PIECE_LEN as u64;
+        let expected = (self.size.saturating_sub(start)).min(METADATA_PIECE_LEN as u64);
+        match self.pieces.get_mut(piece as usize) {
+            Some(slot) if data.len() as u64 == expected => {
+                *slot = Some(data);
+                Ok(())
+            }
+            _ => Err(ExtensionError::InvalidMetadataPiece(piece)),
+        }
+    }
+
+    /// Concatenate the pieces and check their hash. On a mismatch, every piece is dropped so
+    /// the metadata can be requested again from another peer.
+    pub fn finish(&mut self) -> Result<Vec<u8>, ExtensionError> {
+        let metadata: Vec<u8> = self.pieces.iter().flatten().flatten().copied().collect();
+        if !self.is_complete() || Sha1::digest(&metadata).as_slice() != self.info_hash.as_bytes() {
+            self.pieces.iter_mut().for_each(|p| *p = None);
+            return Err(ExtensionError::MetadataHashMismatch);
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// MetadataExtension serves the `info` dictionary of a torrent to the peers requesting it
+#[derive(Debug, Clone)]
+pub struct MetadataExtension {
+    metadata: Arc<Vec<u8>>,
+}
+
+impl MetadataExtension {
+    pub fn new(metadata: Vec<u8>) -> Self {
+        Self {
+            metadata: Arc::new(metadata),
+        }
+    }
+}
+
+impl Extension for MetadataExtension {
+    fn name(&self) -> &str {
+        UT_METADATA
+    }
+
+    fn extend_handshake(&self, handshake: &mut ExtendedHandshake) {
+        handshake.metadata_size = self.metadata.len() as u64;
+    }
+
+    fn new_peer(&self, _: &ExtendedHandshake, _: &mut ExtensionContext) -> Box<dyn PeerExtension> {
+        Box::new(self.clone())
+    }
+}
+
+impl PeerExtension for MetadataExtension {
+    fn on_message(&mut self, ctx: &mut ExtensionContext, payload: &[u8]) {
+        if let Ok(MetadataMessage::Request { piece }) = MetadataMessage::from_bytes(payload) {
+            if let Ok(msg) = MetadataMessage::respond(&self.metadata, piece).to_bytes() {
+                ctx.send(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_message_round_trip() {
+        let msg = MetadataMessage::Data {
+            piece: 1,
+            total_size: 20000,
+            data: b"d1:ai1ee".to_vec(),
+        };
+        let bytes = msg.to_bytes().unwrap();
+
+        assert_eq!(MetadataMessage::from_bytes(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn from_bytes_rejects_oversized_string_length() {
+        let bytes = format!("d{}:msg_typei1ee", usize::MAX);
+
+        assert!(MetadataMessage::from_bytes(bytes.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn from_bytes_rejects_truncated_header() {
+        assert!(MetadataMessage::from_bytes(b"d8:msg_typei0e5:piec").is_err());
+        assert!(MetadataMessage::from_bytes(&[b'l'; 100_000]).is_err());
+    }
+}
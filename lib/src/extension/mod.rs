@@ -0,0 +1,99 @@
+//! Extension protocol, as defined by [BEP 0010](https://www.bittorrent.org/beps/bep_0010.html).
+//!
+//! Peers setting the extension bit of their handshake (see
+//! [`Handshake::with_extension_protocol`](crate::torrent::v1::Handshake::with_extension_protocol))
+//! exchange an [`ExtendedHandshake`] listing the extensions they support, each with the
+//! message ID the other peer must use.
+//!
+//! Extensions are plugged in an [`ExtensionRegistry`] by implementing [`Extension`].
+
+mod errors;
+pub mod metadata;
+pub mod pex;
+mod registry;
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use bendy::serde::{from_bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+
+pub use errors::ExtensionError;
+pub use registry::{Extension, ExtensionContext, ExtensionRegistry, PeerExtension, PeerExtensions};
+
+/// Extended message ID of the extended handshake
+pub const HANDSHAKE_ID: u8 = 0;
+/// Client name and version sent in the extended handshake
+pub(crate) const CLIENT_VERSION: &str = concat!("brs ", env!("CARGO_PKG_VERSION"));
+
+/// Dictionary sent as the first extended message. Absent values are `0` or empty.
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExtendedHandshake {
+    /// Supported extensions with their message ID, `0` disabling an extension
+    #[serde(default)]
+    pub m: BTreeMap<String, i64>,
+    /// Size of the `info` dictionary, advertised by peers serving it (`BEP 0009`)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub metadata_size: u64,
+    /// Listening port of the peer
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub p: u64,
+    /// Client name and version
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub v: String,
+    /// Number of outstanding requests the peer accepts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reqq: u64,
+    /// Our IP address as seen by the peer, 4 or 16 bytes
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub yourip: Vec<u8>,
+}
+
+impl ExtendedHandshake {
+    /// Message ID to use when sending `extension` messages to the peer, `None` when the peer
+    /// doesn't support it
+    pub fn extension_id(&self, extension: &str) -> Option<u8> {
+        self.m
+            .get(extension)
+            .and_then(|id| u8::try_from(*id).ok())
+            .filter(|id| *id != HANDSHAKE_ID)
+    }
+
+    /// Our IP address as seen by the peer
+    pub fn your_ip(&self) -> Option<IpAddr> {
+        match self.yourip.len() {
+            4 => <[u8; 4]>::try_from(&self.yourip[..])
+                .ok()
+                .map(|b| Ipv4Addr::from(b).into()),
+            16 => <[u8; 16]>::try_from(&self.yourip[..])
+                .ok()
+                .map(|b| Ipv6Addr::from(b).into()),
+            _ => None,
+        }
+    }
+
+    /// Tell the peer the IP address we see it with
+    pub fn set_your_ip(&mut self, ip: IpAddr) {
+        self.yourip = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        from_bytes(bytes).map_err(ExtensionError::Parse)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ExtensionError> {
+        to_bytes(self).map_err(ExtensionError::Encode)
+    }
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
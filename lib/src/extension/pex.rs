@@ -0,0 +1,485 @@
+//! Peer exchange (`ut_pex`), as defined by [BEP 0011](https://www.bittorrent.org/beps/bep_0011.html).
+//!
+//! Peers periodically tell each other which peers they connected to and disconnected from
+//! since their previous message, so swarms keep growing when trackers are unreachable.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bendy::serde::{from_bytes, to_bytes};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+
+use super::{
+    errors::ExtensionError, ExtendedHandshake, Extension, ExtensionContext, PeerExtension,
+};
+
+/// Name of the extension in the extended handshake
+pub const UT_PEX: &str = "ut_pex";
+/// Minimum time between two messages sent to the same peer
+pub const PEX_INTERVAL: Duration = Duration::from_secs(60);
+/// Maximum number of added and dropped peers in a single message
+pub const MAX_PEX_PEERS: usize = 50;
+
+/// The peer prefers encrypted connections
+pub const FLAG_ENCRYPTION: u8 = 0x01;
+/// The peer is a seed
+pub const FLAG_SEED: u8 = 0x02;
+/// The peer supports uTP
+pub const FLAG_UTP: u8 = 0x04;
+/// The peer supports the holepunch extension
+pub const FLAG_HOLEPUNCH: u8 = 0x08;
+/// The peer accepts incoming connections
+pub const FLAG_CONNECTABLE: u8 = 0x10;
+
+const IPV4_LEN: usize = 6;
+const IPV6_LEN: usize = 18;
+
+/// Peers connected and disconnected since the previous message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PexMessage {
+    /// New peers with their flags
+    pub added: Vec<(SocketAddr, u8)>,
+    pub dropped: Vec<SocketAddr>,
+}
+
+/// Bencoded form of [`PexMessage`], peers being in the compact form
+#[serde_as]
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct WireMessage {
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    added: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(rename = "added.f", default, skip_serializing_if = "Vec::is_empty")]
+    added_f: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    added6: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(rename = "added6.f", default, skip_serializing_if = "Vec::is_empty")]
+    added6_f: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dropped: Vec<u8>,
+    #[serde_as(as = "Bytes")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dropped6: Vec<u8>,
+}
+
+impl PexMessage {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.dropped.is_empty()
+    }
+
+    /// Parse a message. Missing flags default to `0` and incomplete entries are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let wire: WireMessage = from_bytes(bytes).map_err(ExtensionError::Parse)?;
+        let with_flags = |peers: Vec<SocketAddr>, flags: &[u8]| {
+            peers
+                .into_iter()
+                .enumerate()
+                .map(|(i, addr)| (addr, flags.get(i).copied().unwrap_or_default()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut added = with_flags(parse_compact(&wire.added, IPV4_LEN), &wire.added_f);
+        added.extend(with_flags(
+            parse_compact(&wire.added6, IPV6_LEN),
+            &wire.added6_f,
+        ));
+        let mut dropped = parse_compact(&wire.dropped, IPV4_LEN);
+        dropped.extend(parse_compact(&wire.dropped6, IPV6_LEN));
+
+        Ok(Self { added, dropped })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ExtensionError> {
+        let mut wire = WireMessage::default();
+        for (addr, flags) in &self.added {
+            match addr.ip() {
+                IpAddr::V4(_) => {
+                    push_compact(&mut wire.added, addr);
+                    wire.added_f.push(*flags);
+                }
+                IpAddr::V6(_) => {
+                    push_compact(&mut wire.added6, addr);
+                    wire.added6_f.push(*flags);
+                }
+            }
+        }
+        for addr in &self.dropped {
+            match addr.ip() {
+                IpAddr::V4(_) => push_compact(&mut wire.dropped, addr),
+                IpAddr::V6(_) => push_compact(&mut wire.dropped6, addr),
+            }
+        }
+
+        to_bytes(&wire).map_err(ExtensionError::Encode)
+    }
+}
+
+/// Parse compact peers: the address followed by the port, BigEndian
+fn parse_compact(bytes: &[u8], len: usize) -> Vec<SocketAddr> {
+    bytes
+        .chunks_exact(len)
+        .filter_map(|c| {
+            let (ip, port) = c.split_at(len - 2);
+            let ip = match ip.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(ip).ok()?),
+                _ => IpAddr::from(<[u8; 16]>::try_from(ip).ok()?),
+            };
+            Some(SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])))
+        })
+        .collect()
+}
+
+fn push_compact(bytes: &mut Vec<u8>, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => bytes.extend(ip.octets()),
+        IpAddr::V6(ip) => bytes.extend(ip.octets()),
+    }
+    bytes.extend(addr.port().to_be_bytes());
+}
+
+/// PeerExchange holds the peers of a torrent shared by the `ut_pex` extension of every peer:
+/// the connected peers advertised to others, and the peers they advertised to us.
+#[derive(Debug, Default)]
+pub struct PeerExchange {
+    state: Mutex<ExchangeState>,
+}
+
+#[derive(Debug, Default)]
+struct ExchangeState {
+    /// Listening address of connected peers with their flags
+    connected: HashMap<SocketAddr, u8>,
+    /// Peers advertised by others, not taken yet
+    discovered: Vec<SocketAddr>,
+}
+
+impl PeerExchange {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advertise a connected peer listening on `addr`, or update its flags
+    pub fn connected(&self, addr: SocketAddr, flags: u8) {
+        self.lock().connected.insert(addr, flags);
+    }
+
+    /// Stop advertising the peer listening on `addr`
+    pub fn disconnected(&self, addr: &SocketAddr) {
+        self.lock().connected.remove(addr);
+    }
+
+    /// Peers advertised by others since the last call
+    pub fn take_discovered(&self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.lock().discovered)
+    }
+
+    fn discover(&self, peers: impl IntoIterator<Item = SocketAddr>) {
+        self.lock().discovered.extend(peers);
+    }
+
+    fn snapshot(&self) -> HashMap<SocketAddr, u8> {
+        self.lock().connected.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ExchangeState> {
+        self.state
+            .lock()
+            .expect("peer exchange lock should not be poisoned")
+    }
+}
+
+/// PexExtension exchanges the peers of a [`PeerExchange`] with every peer supporting it
+#[derive(Debug, Clone)]
+pub struct PexExtension {
+    exchange: Arc<PeerExchange>,
+}
+
+impl PexExtension {
+    pub fn new(exchange: Arc<PeerExchange>) -> Self {
+        Self { exchange }
+    }
+}
+
+impl Extension for PexExtension {
+    fn name(&self) -> &str {
+        UT_PEX
+    }
+
+    fn new_peer(
+        &self,
+        remote: &ExtendedHandshake,
+        ctx: &mut ExtensionContext,
+    ) -> Box<dyn PeerExtension> {
+        let listen = u16::try_from(remote.p)
+            .ok()
+            .filter(|p| *p != 0)
+            .map(|p| SocketAddr::new(ctx.addr().ip(), p));
+
+        Box::new(PexPeer {
+            exchange: Arc::clone(&self.exchange),
+            own: [Some(ctx.addr()), listen].into_iter().flatten().collect(),
+            sent: HashSet::new(),
+            next_send: Instant::now(),
+        })
+    }
+}
+
+/// State of `ut_pex` with a single peer
+#[derive(Debug)]
+struct PexPeer {
+    exchange: Arc<PeerExchange>,
+    /// Addresses of the peer itself, never advertised to it
+    own: Vec<SocketAddr>,
+    /// Peers advertised to the peer and not dropped since
+    sent: HashSet<SocketAddr>,
+    next_send: Instant,
+}
+
+impl PeerExtension for PexPeer {
+    fn on_message(&mut self, _: &mut ExtensionContext, payload: &[u8]) {
+        if let Ok(msg) = PexMessage::from_bytes(payload) {
+            let added = msg
+                .added
+                .into_iter()
+                .map(|(addr, _)| addr)
+                .filter(|addr| addr.port() != 0 && !self.own.contains(addr))
+                .take(MAX_PEX_PEERS);
+            self.exchange.discover(added);
+        }
+    }
+
+    fn on_tick(&mut self, ctx: &mut ExtensionContext) {
+        if Instant::now() < self.next_send {
+            return;
+        }
+
+        let connected = self.exchange.snapshot();
+        let msg = PexMessage {
+            added: connected
+                .iter()
+                .filter(|(addr, _)| !self.sent.contains(addr) && !self.own.contains(addr))
+                .take(MAX_PEX_PEERS)
+                .map(|(addr, flags)| (*addr, *flags))
+                .collect(),
+            dropped: self
+                .sent
+                .iter()
+                .filter(|addr| !connected.contains_key(addr))
+                .take(MAX_PEX_PEERS)
+                .copied()
+                .collect(),
+        };
+        if msg.is_empty() {
+            return;
+        }
+
+        if let Ok(payload) = msg.to_bytes() {
+            for addr in &msg.dropped {
+                self.sent.remove(addr);
+            }
+            self.sent.extend(msg.added.iter().map(|(addr, _)| *addr));
+            ctx.send(payload);
+            self.next_send = Instant::now() + PEX_INTERVAL;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn v4(last: u8, port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::new(10, 0, 0, last).into(), port)
+    }
+
+    fn v6(last: u16, port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, last).into(), port)
+    }
+
+    fn sorted<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+        v.sort();
+        v
+    }
+
+    fn pex_peer(exchange: &Arc<PeerExchange>, own: Vec<SocketAddr>) -> PexPeer {
+        PexPeer {
+            exchange: Arc::clone(exchange),
+            own,
+            sent: HashSet::new(),
+            next_send: Instant::now(),
+        }
+    }
+
+    /// Message sent by `peer` on this tick, if any
+    fn tick(peer: &mut PexPeer) -> Option<PexMessage> {
+        let mut ctx = ExtensionContext::new(v4(99, 6881));
+        peer.on_tick(&mut ctx);
+        let mut messages = ctx.take_messages();
+        assert!(messages.len() <= 1);
+        messages.pop().map(|m| PexMessage::from_bytes(&m).unwrap())
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let msg = PexMessage {
+            added: vec![
+                (v4(1, 6881), FLAG_SEED | FLAG_CONNECTABLE),
+                (v6(1, 6882), FLAG_UTP),
+                (v4(2, 6883), 0),
+            ],
+            dropped: vec![v4(3, 1), v6(2, 2)],
+        };
+        let bytes = msg.to_bytes().unwrap();
+        let wire: WireMessage = from_bytes(&bytes).unwrap();
+
+        assert_eq!(wire.added.len(), 2 * IPV4_LEN);
+        assert_eq!(wire.added_f, [FLAG_SEED | FLAG_CONNECTABLE, 0]);
+        assert_eq!(wire.added6.len(), IPV6_LEN);
+        assert_eq!(wire.added6_f, [FLAG_UTP]);
+        assert_eq!(wire.dropped, [10, 0, 0, 3, 0, 1]);
+        assert_eq!(wire.dropped6.len(), IPV6_LEN);
+        // IPv4 peers come first once parsed
+        let parsed = PexMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.added, [msg.added[0], msg.added[2], msg.added[1]]);
+        assert_eq!(parsed.dropped, msg.dropped);
+
+        assert_eq!(PexMessage::default().to_bytes().unwrap(), b"de");
+        assert!(PexMessage::from_bytes(b"de").unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_flags_default_to_zero() {
+        let msg = PexMessage::from_bytes(
+            b"d5:added12:\x0a\x00\x00\x01\x1a\xe1\x0a\x00\x00\x02\x1a\xe17:added.f1:\x12\
+            6:added618:\xfe\x80\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1a\xe1e",
+        )
+        .unwrap();
+
+        assert_eq!(
+            msg.added,
+            [(v4(1, 6881), 0x12), (v4(2, 6881), 0), (v6(1, 6881), 0)]
+        );
+    }
+
+    #[test]
+    fn truncated_entries_are_ignored() {
+        let msg = PexMessage::from_bytes(
+            b"d5:added9:\x0a\x00\x00\x01\x1a\xe1\x0a\x00\x007:dropped5:\x0a\x00\x00\x01\x1a\
+            8:dropped620:\xfe\x80\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1a\xe1\x00\x00e",
+        )
+        .unwrap();
+
+        assert_eq!(msg.added, [(v4(1, 6881), 0)]);
+        assert_eq!(msg.dropped, [v6(1, 6881)]);
+        assert!(matches!(
+            PexMessage::from_bytes(b"d5:added"),
+            Err(ExtensionError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn tick_waits_for_the_interval() {
+        let exchange = Arc::new(PeerExchange::new());
+        exchange.connected(v4(1, 1), FLAG_CONNECTABLE);
+        let mut peer = pex_peer(&exchange, vec![]);
+
+        let first = tick(&mut peer).unwrap();
+        assert_eq!(first.added, [(v4(1, 1), FLAG_CONNECTABLE)]);
+        assert!(peer.next_send >= Instant::now() + PEX_INTERVAL - Duration::from_secs(1));
+
+        exchange.connected(v4(2, 2), 0);
+        assert_eq!(tick(&mut peer), None);
+
+        // Interval elapsed: only the new peer is sent
+        peer.next_send = Instant::now();
+        assert_eq!(tick(&mut peer).unwrap().added, [(v4(2, 2), 0)]);
+        peer.next_send = Instant::now();
+        assert_eq!(tick(&mut peer), None, "nothing changed");
+    }
+
+    #[test]
+    fn tick_reports_dropped_peers() {
+        let exchange = Arc::new(PeerExchange::new());
+        exchange.connected(v4(1, 1), 0);
+        exchange.connected(v6(1, 1), 0);
+        let mut peer = pex_peer(&exchange, vec![]);
+        assert_eq!(tick(&mut peer).unwrap().added.len(), 2);
+
+        exchange.disconnected(&v4(1, 1));
+        exchange.disconnected(&v6(1, 1));
+        exchange.connected(v4(3, 3), 0);
+        peer.next_send = Instant::now();
+        let msg = tick(&mut peer).unwrap();
+        assert_eq!(msg.added, [(v4(3, 3), 0)]);
+        assert_eq!(sorted(msg.dropped), sorted(vec![v4(1, 1), v6(1, 1)]));
+
+        // Dropped peers are not reported twice, and can be added again
+        exchange.connected(v4(1, 1), 0);
+        peer.next_send = Instant::now();
+        let msg = tick(&mut peer).unwrap();
+        assert_eq!(msg.added, [(v4(1, 1), 0)]);
+        assert!(msg.dropped.is_empty());
+    }
+
+    #[test]
+    fn peer_is_never_advertised_to_itself() {
+        let exchange = Arc::new(PeerExchange::new());
+        let remote = ExtendedHandshake {
+            p: 7000,
+            ..Default::default()
+        };
+        // Connection from an ephemeral port, the peer listening on 7000
+        let mut ctx = ExtensionContext::new(v4(1, 40000));
+        let mut peer = PexExtension::new(Arc::clone(&exchange)).new_peer(&remote, &mut ctx);
+        for addr in [v4(1, 40000), v4(1, 7000), v4(2, 7000)] {
+            exchange.connected(addr, 0);
+        }
+
+        peer.on_tick(&mut ctx);
+        let messages = ctx.take_messages();
+        assert_eq!(messages.len(), 1);
+        let msg = PexMessage::from_bytes(&messages[0]).unwrap();
+        assert_eq!(msg.added, [(v4(2, 7000), 0)]);
+
+        // The peer advertising itself or a port 0 is ignored too
+        let msg = PexMessage {
+            added: vec![(v4(1, 7000), 0), (v4(3, 0), 0), (v4(4, 1), 0)],
+            dropped: vec![],
+        };
+        peer.on_message(&mut ctx, &msg.to_bytes().unwrap());
+        assert_eq!(exchange.take_discovered(), [v4(4, 1)]);
+        assert!(exchange.take_discovered().is_empty());
+    }
+
+    #[test]
+    fn messages_are_capped() {
+        let exchange = Arc::new(PeerExchange::new());
+        for i in 0..MAX_PEX_PEERS as u16 + 10 {
+            exchange.connected(v6(i, 1), 0);
+        }
+        let mut peer = pex_peer(&exchange, vec![]);
+        assert_eq!(tick(&mut peer).unwrap().added.len(), MAX_PEX_PEERS);
+
+        let msg = PexMessage {
+            added: (0..MAX_PEX_PEERS as u8 + 10)
+                .map(|i| (v4(i, 1), 0))
+                .collect(),
+            dropped: vec![],
+        };
+        peer.on_message(
+            &mut ExtensionContext::new(v4(99, 1)),
+            &msg.to_bytes().unwrap(),
+        );
+        assert_eq!(exchange.take_discovered().len(), MAX_PEX_PEERS);
+    }
+}
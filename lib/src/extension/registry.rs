@@ -0,0 +1,327 @@
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use crate::torrent::v1::Message;
+
+use super::{ExtendedHandshake, CLIENT_VERSION};
+
+/// Extension plugged into the extension protocol. Built-in extensions such as `ut_metadata`
+/// implement it too.
+///
+/// The extension is shared by every peer of a torrent, while [`Extension::new_peer`] creates
+/// its state for each peer supporting it.
+pub trait Extension: fmt::Debug + Send + Sync {
+    /// Name advertised in the `m` dictionary of the extended handshake, e.g. `ut_metadata`
+    fn name(&self) -> &str;
+
+    /// Add the fields of the extension to our extended handshake
+    fn extend_handshake(&self, _handshake: &mut ExtendedHandshake) {}
+
+    /// Start the extension with a peer advertising it in its extended handshake.
+    /// Messages sent through `ctx` are delivered right away.
+    fn new_peer(
+        &self,
+        remote: &ExtendedHandshake,
+        ctx: &mut ExtensionContext,
+    ) -> Box<dyn PeerExtension>;
+}
+
+/// State of an [`Extension`] with a single peer
+pub trait PeerExtension: fmt::Debug + Send + Sync {
+    /// Handle a message of the extension sent by the peer
+    fn on_message(&mut self, ctx: &mut ExtensionContext, payload: &[u8]);
+
+    /// Called every second, to send periodic messages
+    fn on_tick(&mut self, _ctx: &mut ExtensionContext) {}
+}
+
+/// ExtensionContext is given to extensions to reach the peer
+#[derive(Debug)]
+pub struct ExtensionContext {
+    addr: SocketAddr,
+    messages: Vec<Vec<u8>>,
+}
+
+impl ExtensionContext {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            messages: vec![],
+        }
+    }
+
+    /// Address of the peer
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send a message of the extension to the peer
+    pub fn send(&mut self, payload: Vec<u8>) {
+        self.messages.push(payload);
+    }
+
+    /// Messages sent since the last call
+    pub fn take_messages(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.messages)
+    }
+}
+
+/// ExtensionRegistry holds the extensions supported by an engine and assigns their message
+/// IDs, starting at 1 in registration order.
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionRegistry {
+    extensions: Vec<Arc<dyn Extension>>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an extension, replacing the one registered with the same name.
+    /// Extensions past the 255th are ignored, as they can't get a message ID.
+    pub fn register(&mut self, extension: Arc<dyn Extension>) {
+        match self
+            .extensions
+            .iter()
+            .position(|e| e.name() == extension.name())
+        {
+            Some(i) => self.extensions[i] = extension,
+            None if self.extensions.len() < u8::MAX as usize => self.extensions.push(extension),
+            None => {}
+        }
+    }
+
+    pub fn extensions(&self) -> &[Arc<dyn Extension>] {
+        &self.extensions
+    }
+
+    /// Message ID peers must use to send us messages of the extension `name`
+    pub fn id(&self, name: &str) -> Option<u8> {
+        self.extensions
+            .iter()
+            .position(|e| e.name() == name)
+            .map(|i| i as u8 + 1)
+    }
+
+    /// Our extended handshake, listing every extension with its fields
+    pub fn handshake(&self) -> ExtendedHandshake {
+        let mut handshake = ExtendedHandshake {
+            v: CLIENT_VERSION.to_string(),
+            ..Default::default()
+        };
+        for (i, extension) in self.extensions.iter().enumerate() {
+            handshake
+                .m
+                .insert(extension.name().to_string(), i as i64 + 1);
+            extension.extend_handshake(&mut handshake);
+        }
+
+        handshake
+    }
+
+    /// Start the extensions supported by both sides with the peer at `addr`, from its
+    /// extended handshake. Returns the messages sent by the extensions when starting.
+    pub fn connect(
+        &self,
+        remote: ExtendedHandshake,
+        addr: SocketAddr,
+    ) -> (PeerExtensions, Vec<Message>) {
+        let mut ctx = ExtensionContext::new(addr);
+        let mut messages = vec![];
+        let peers = self
+            .extensions
+            .iter()
+            .map(|extension| {
+                let id = remote.extension_id(extension.name())?;
+                let peer = extension.new_peer(&remote, &mut ctx);
+                messages.extend(wrap(id, &mut ctx));
+                Some((id, peer))
+            })
+            .collect();
+
+        let extensions = PeerExtensions {
+            addr,
+            peers,
+            remote,
+        };
+
+        (extensions, messages)
+    }
+}
+
+/// Extensions running with a single peer
+#[derive(Debug)]
+pub struct PeerExtensions {
+    addr: SocketAddr,
+    /// Indexed by our message ID minus 1, along with the message ID of the peer.
+    /// `None` for extensions the peer doesn't support.
+    peers: Vec<Option<(u8, Box<dyn PeerExtension>)>>,
+    remote: ExtendedHandshake,
+}
+
+impl PeerExtensions {
+    /// Extended handshake of the peer
+    pub fn remote(&self) -> &ExtendedHandshake {
+        &self.remote
+    }
+
+    /// Handle a message the peer sent with our message ID `id`.
+    /// Returns the messages to send back.
+    pub fn on_message(&mut self, id: u8, payload: &[u8]) -> Vec<Message> {
+        let index = match (id as usize).checked_sub(1) {
+            Some(v) => v,
+            None => return vec![],
+        };
+        match self.peers.get_mut(index) {
+            Some(Some((remote_id, peer))) => {
+                let mut ctx = ExtensionContext::new(self.addr);
+                peer.on_message(&mut ctx, payload);
+                wrap(*remote_id, &mut ctx)
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Let every extension send its periodic messages
+    pub fn on_tick(&mut self) -> Vec<Message> {
+        let mut ctx = ExtensionContext::new(self.addr);
+        let mut messages = vec![];
+        for (remote_id, peer) in self.peers.iter_mut().flatten() {
+            peer.on_tick(&mut ctx);
+            messages.extend(wrap(*remote_id, &mut ctx));
+        }
+
+        messages
+    }
+}
+
+/// Turn the messages sent through `ctx` into extended messages with the peer ID `id`
+fn wrap(id: u8, ctx: &mut ExtensionContext) -> Vec<Message> {
+    ctx.take_messages()
+        .into_iter()
+        .map(|payload| Message::Extended { id, payload })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    /// Extension answering every message with its name followed by the payload
+    #[derive(Debug)]
+    struct Echo(&'static str);
+
+    #[derive(Debug)]
+    struct EchoPeer(&'static str);
+
+    impl Extension for Echo {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn extend_handshake(&self, handshake: &mut ExtendedHandshake) {
+            handshake.reqq += 1;
+        }
+
+        fn new_peer(
+            &self,
+            _remote: &ExtendedHandshake,
+            ctx: &mut ExtensionContext,
+        ) -> Box<dyn PeerExtension> {
+            ctx.send(format!("{}:start", self.0).into_bytes());
+            Box::new(EchoPeer(self.0))
+        }
+    }
+
+    impl PeerExtension for EchoPeer {
+        fn on_message(&mut self, ctx: &mut ExtensionContext, payload: &[u8]) {
+            ctx.send([self.0.as_bytes(), b":", payload].concat());
+        }
+
+        fn on_tick(&mut self, ctx: &mut ExtensionContext) {
+            ctx.send(format!("{}:tick", self.0).into_bytes());
+        }
+    }
+
+    fn registry(names: &[&'static str]) -> ExtensionRegistry {
+        let mut registry = ExtensionRegistry::new();
+        for name in names {
+            registry.register(Arc::new(Echo(name)));
+        }
+        registry
+    }
+
+    fn remote(m: &[(&str, i64)]) -> ExtendedHandshake {
+        ExtendedHandshake {
+            m: m.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn addr() -> SocketAddr {
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, 6881).into()
+    }
+
+    fn extended(id: u8, payload: &str) -> Message {
+        Message::Extended {
+            id,
+            payload: payload.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn ids_follow_registration_order() {
+        let mut registry = registry(&["a", "b", "c"]);
+        registry.register(Arc::new(Echo("b")));
+
+        assert_eq!(registry.extensions().len(), 3);
+        assert_eq!(
+            ["a", "b", "c", "d"].map(|n| registry.id(n)),
+            [Some(1), Some(2), Some(3), None]
+        );
+        let handshake = registry.handshake();
+        assert_eq!(handshake.m, remote(&[("a", 1), ("b", 2), ("c", 3)]).m);
+        assert_eq!(handshake.v, CLIENT_VERSION);
+        assert_eq!(handshake.reqq, 3);
+    }
+
+    #[test]
+    fn messages_are_routed_by_our_id_and_sent_with_the_peer_id() {
+        let registry = registry(&["a", "b"]);
+        let (mut peer, start) = registry.connect(remote(&[("a", 7), ("b", 3), ("z", 1)]), addr());
+
+        assert_eq!(start, [extended(7, "a:start"), extended(3, "b:start")]);
+        assert_eq!(peer.on_message(1, b"x"), [extended(7, "a:x")]);
+        assert_eq!(peer.on_message(2, b"y"), [extended(3, "b:y")]);
+        assert_eq!(
+            peer.on_tick(),
+            [extended(7, "a:tick"), extended(3, "b:tick")]
+        );
+    }
+
+    #[test]
+    fn unknown_ids_are_ignored() {
+        let registry = registry(&["a", "b", "c"]);
+        // `b` is disabled by the peer, `c` is unknown to it
+        let (mut peer, start) = registry.connect(remote(&[("a", 2), ("b", 0)]), addr());
+
+        assert_eq!(start, [extended(2, "a:start")]);
+        assert!(peer.on_message(0, b"x").is_empty());
+        assert!(peer.on_message(2, b"x").is_empty());
+        assert!(peer.on_message(3, b"x").is_empty());
+        assert!(peer.on_message(4, b"x").is_empty());
+        assert_eq!(peer.on_tick(), [extended(2, "a:tick")]);
+    }
+
+    #[test]
+    fn peer_ids_out_of_range_are_ignored() {
+        let registry = registry(&["a", "b"]);
+        let (mut peer, start) = registry.connect(remote(&[("a", 256), ("b", -1)]), addr());
+
+        assert!(start.is_empty());
+        assert!(peer.on_message(1, b"x").is_empty());
+        assert!(peer.on_tick().is_empty());
+    }
+}
@@ -8,7 +8,7 @@ pub(crate) mod skip_empty {
 
     #[inline(always)]
     pub(crate) fn bool(v: &bool) -> bool {
-        *v == false
+        !*v
     }
 
     #[inline(always)]
@@ -1,6 +1,13 @@
+pub mod bandwidth;
+pub mod dht;
+pub mod engine;
+pub mod extension;
+pub mod magnet;
 pub mod torrent;
 pub mod tracker;
 pub mod peer;
+pub mod session;
+pub mod storage;
 
 mod macros;
 mod extension_parsing;
@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+use crate::{extension::ExtensionError, torrent::errors::TorrentError};
+
+#[derive(Error, Debug)]
+pub enum MagnetError {
+    #[error("Not a magnet link: {0}")]
+    InvalidScheme(String),
+    #[error("Magnet link has no BitTorrent info hash")]
+    MissingInfoHash,
+    #[error("Invalid info hash in magnet link: {0}")]
+    InvalidInfoHash(String),
+    #[error("Invalid magnet parameter {key}: {value}")]
+    InvalidParameter { key: String, value: String },
+    #[error("Fetching the metadata of v2 only torrents is not supported")]
+    V2Only,
+    #[error("No peer sent the metadata")]
+    NoPeers,
+    #[error("No peer sent the metadata in time")]
+    Timeout,
+    #[error("Invalid metadata: {0}")]
+    Metadata(#[from] ExtensionError),
+    #[error("Invalid torrent: {0}")]
+    Torrent(#[from] TorrentError),
+}
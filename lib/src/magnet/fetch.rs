@@ -0,0 +1,340 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{net, task::JoinSet, time};
+
+use crate::{
+    dht::Dht,
+    extension::{
+        metadata::{MetadataAssembler, MetadataMessage, UT_METADATA},
+        ExtendedHandshake, CLIENT_VERSION, HANDSHAKE_ID,
+    },
+    peer::PeerId,
+    torrent::{
+        v1::{Handshake, Message, PeerConnection, Torrent},
+        InfoHash,
+    },
+    tracker::{
+        announce::{AnnounceEv, AnnounceReq},
+        manager::TrackerManager,
+    },
+};
+
+use super::{Magnet, MagnetError};
+
+/// Default time allowed to fetch the metadata
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+/// Default number of peers asked at the same time
+const DEFAULT_MAX_PEERS: usize = 10;
+/// Time allowed to a single peer to send the whole metadata
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Extended message ID peers use to send us `ut_metadata` messages
+const UT_METADATA_ID: u8 = 1;
+
+/// MetadataFetcher retrieves the `info` dictionary of a magnet link from the swarm, using
+/// the `ut_metadata` extension (`BEP 0009`).
+///
+/// Peers come from the `x.pe` parameters, from the trackers of the link and from the DHT
+/// when a node is set. They are asked in parallel and the first metadata matching the info
+/// hash wins.
+#[derive(Debug, Clone)]
+pub struct MetadataFetcher {
+    magnet: Magnet,
+    peer_id: PeerId,
+    port: u16,
+    timeout: Duration,
+    max_peers: usize,
+    dht: Option<Arc<Dht>>,
+}
+
+impl MetadataFetcher {
+    pub fn new(magnet: Magnet) -> Self {
+        Self {
+            magnet,
+            peer_id: PeerId::generate(None),
+            port: crate::engine::DEFAULT_PORT,
+            timeout: DEFAULT_TIMEOUT,
+            max_peers: DEFAULT_MAX_PEERS,
+            dht: None,
+        }
+    }
+
+    /// Override the randomly generated peer ID
+    pub fn peer_id(mut self, peer_id: PeerId) -> Self {
+        self.peer_id = peer_id;
+        self
+    }
+
+    /// Port announced to trackers
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Number of peers asked at the same time
+    pub fn max_peers(mut self, max_peers: usize) -> Self {
+        self.max_peers = max_peers.max(1);
+        self
+    }
+
+    /// Also look up peers on the DHT
+    pub fn dht(mut self, dht: Arc<Dht>) -> Self {
+        self.dht = Some(dht);
+        self
+    }
+
+    /// Fetch the metadata and build the `.torrent` file of the magnet link,
+    /// see [`Magnet::to_torrent`]. The file is parsed before being returned.
+    pub async fn fetch(&self) -> Result<Vec<u8>, MagnetError> {
+        let info_hash = self.magnet.info_hash.ok_or(MagnetError::V2Only)?;
+        let info = time::timeout(self.timeout, self.fetch_info(info_hash))
+            .await
+            .map_err(|_| MagnetError::Timeout)??;
+
+        let torrent = self.magnet.to_torrent(&info)?;
+        // A matching info hash doesn't make a valid torrent, asking other peers won't help
+        Torrent::parse_bytes(&torrent)?;
+
+        Ok(torrent)
+    }
+
+    async fn fetch_info(&self, info_hash: InfoHash) -> Result<Vec<u8>, MagnetError> {
+        let local = Handshake::new(info_hash, self.peer_id).with_extension_protocol();
+        let mut candidates = VecDeque::new();
+        for peer in &self.magnet.peers {
+            if let Ok(addrs) = net::lookup_host(peer.as_str()).await {
+                candidates.extend(addrs);
+            }
+        }
+        let mut known: HashSet<SocketAddr> = candidates.iter().copied().collect();
+
+        let mut announce = (!self.magnet.trackers.is_empty()).then(|| {
+            // Every tracker of a magnet link is its own tier
+            let tiers = self
+                .magnet
+                .trackers
+                .iter()
+                .map(|t| vec![t.clone()])
+                .collect();
+            let mut trackers = TrackerManager::new(tiers);
+            let req = AnnounceReq {
+                info_hash,
+                peer_id: self.peer_id,
+                port: self.port,
+                // Unknown until the metadata is fetched. Not 0, or we would be seen as a seed.
+                left: self.magnet.length.unwrap_or(1),
+                event: AnnounceEv::Started,
+                compact: true,
+                ..Default::default()
+            };
+            tokio::spawn(async move { trackers.announce(req).await })
+        });
+
+        let mut lookup = self
+            .dht
+            .clone()
+            .map(|dht| tokio::spawn(async move { dht.get_peers(info_hash).await }));
+
+        let mut tasks = JoinSet::new();
+        loop {
+            while tasks.len() < self.max_peers {
+                match candidates.pop_front() {
+                    Some(addr) => tasks.spawn(fetch_from_peer(addr, local)),
+                    None => break,
+                };
+            }
+            if tasks.is_empty() && announce.is_none() && lookup.is_none() {
+                return Err(MagnetError::NoPeers);
+            }
+
+            tokio::select! {
+                Some(res) = tasks.join_next(), if !tasks.is_empty() => {
+                    if let Ok(Some(info)) = res {
+                        return Ok(info);
+                    }
+                }
+                res = async { announce.as_mut().expect("announce is running").await },
+                    if announce.is_some() =>
+                {
+                    announce = None;
+                    for p in res.ok().and_then(|r| r.ok()).map_or(vec![], |r| r.peers) {
+                        let addr = SocketAddr::new(p.ip, p.port);
+                        if known.insert(addr) {
+                            candidates.push_back(addr);
+                        }
+                    }
+                }
+                res = async { lookup.as_mut().expect("DHT lookup is running").await },
+                    if lookup.is_some() =>
+                {
+                    lookup = None;
+                    for p in res.ok().and_then(|r| r.ok()).unwrap_or_default() {
+                        let addr = SocketAddr::new(p.ip, p.port);
+                        if known.insert(addr) {
+                            candidates.push_back(addr);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Request every metadata piece from a single peer. `None` if the peer doesn't serve the
+/// metadata, sends invalid pieces or is too slow.
+async fn fetch_from_peer(addr: SocketAddr, local: Handshake) -> Option<Vec<u8>> {
+    let fetch = async {
+        let mut conn = PeerConnection::connect(addr, local).await.ok()?;
+        if !conn.remote().supports_extension_protocol() {
+            return None;
+        }
+        let handshake = ExtendedHandshake {
+            m: [(UT_METADATA.to_string(), UT_METADATA_ID as i64)].into(),
+            v: CLIENT_VERSION.to_string(),
+            ..Default::default()
+        };
+        let payload = handshake.to_bytes().ok()?;
+        conn.send(&Message::Extended {
+            id: HANDSHAKE_ID,
+            payload,
+        })
+        .await
+        .ok()?;
+
+        let mut assembler = None;
+        loop {
+            let (id, payload) = match conn.recv().await.ok()? {
+                Message::Extended { id, payload } => (id, payload),
+                _ => continue,
+            };
+            match (id, assembler.as_mut()) {
+                (HANDSHAKE_ID, None) => {
+                    let remote = ExtendedHandshake::from_bytes(&payload).ok()?;
+                    let remote_id = remote.extension_id(UT_METADATA)?;
+                    let pieces =
+                        MetadataAssembler::new(local.info_hash, remote.metadata_size).ok()?;
+                    for piece in pieces.missing() {
+                        let payload = MetadataMessage::Request { piece }.to_bytes().ok()?;
+                        let msg = Message::Extended {
+                            id: remote_id,
+                            payload,
+                        };
+                        conn.send(&msg).await.ok()?;
+                    }
+                    assembler = Some(pieces);
+                }
+                (UT_METADATA_ID, Some(pieces)) => {
+                    match MetadataMessage::from_bytes(&payload).ok()? {
+                        MetadataMessage::Data { piece, data, .. } => {
+                            pieces.add(piece, data).ok()?;
+                            if pieces.is_complete() {
+                                return pieces.finish().ok();
+                            }
+                        }
+                        MetadataMessage::Reject { .. } => return None,
+                        // We have nothing to serve yet
+                        MetadataMessage::Request { .. } => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    };
+
+    time::timeout(PEER_TIMEOUT, fetch).await.ok()?
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Peer serving `info` with `ut_metadata` to a single connection
+    async fn stub_peer(info: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = PeerConnection::accept(stream, |remote| {
+                Some(
+                    Handshake::new(remote.info_hash, PeerId::generate(None))
+                        .with_extension_protocol(),
+                )
+            })
+            .await
+            .unwrap();
+            let handshake = ExtendedHandshake {
+                m: [(UT_METADATA.to_string(), 3)].into(),
+                metadata_size: info.len() as u64,
+                ..Default::default()
+            };
+            let payload = handshake.to_bytes().unwrap();
+            conn.send(&Message::Extended {
+                id: HANDSHAKE_ID,
+                payload,
+            })
+            .await
+            .unwrap();
+            while let Ok(msg) = conn.recv().await {
+                let payload = match msg {
+                    Message::Extended { id: 3, payload } => payload,
+                    _ => continue,
+                };
+                if let Ok(MetadataMessage::Request { piece }) =
+                    MetadataMessage::from_bytes(&payload)
+                {
+                    let payload = MetadataMessage::respond(info, piece).to_bytes().unwrap();
+                    let msg = Message::Extended {
+                        id: UT_METADATA_ID,
+                        payload,
+                    };
+                    if conn.send(&msg).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        addr
+    }
+
+    async fn fetch(info: &'static [u8]) -> Result<Vec<u8>, MagnetError> {
+        let magnet = Magnet {
+            peers: vec![stub_peer(info).await.to_string()],
+            ..Magnet::new(InfoHash(Sha1::digest(info).into()))
+        };
+
+        MetadataFetcher::new(magnet)
+            .timeout(Duration::from_secs(10))
+            .fetch()
+            .await
+    }
+
+    #[tokio::test]
+    async fn fetch_returns_the_torrent() {
+        let info =
+            b"d6:lengthi5e4:name4:test12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        let torrent = fetch(info).await.unwrap();
+
+        let parsed = Torrent::parse_bytes(&torrent).unwrap();
+        assert_eq!(parsed.info_bytes().unwrap(), info);
+    }
+
+    #[tokio::test]
+    async fn fetch_rejects_invalid_info() {
+        let info = b"d6:lengthi5e4:name4:test12:piece lengthi0e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+
+        assert!(matches!(fetch(info).await, Err(MagnetError::Torrent(_))));
+    }
+}
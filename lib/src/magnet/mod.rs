@@ -0,0 +1,352 @@
+//! Magnet links identifying a torrent by its info hash
+//! ([BEP 0009](https://www.bittorrent.org/beps/bep_0009.html)).
+
+mod errors;
+mod fetch;
+
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    extension::ExtensionError,
+    torrent::{urlencode, InfoHash},
+};
+
+pub use errors::MagnetError;
+pub use fetch::MetadataFetcher;
+
+const SCHEME: &str = "magnet:?";
+const BTIH: &str = "urn:btih:";
+const BTMH: &str = "urn:btmh:";
+/// Multihash prefix of a SHA-256 digest: hash function code and digest length
+const SHA256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+
+/// Magnet is a parsed `magnet:?` URI.
+///
+/// The content is fetched from peers once the info dictionary was retrieved from the swarm,
+/// so only the info hash is required. Every other parameter is a hint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Magnet {
+    /// SHA1 info hash of a v1 or hybrid torrent (`xt=urn:btih:`)
+    pub info_hash: Option<InfoHash>,
+    /// SHA-256 info hash of a v2 or hybrid torrent (`xt=urn:btmh:`)
+    pub info_hash_v2: Option<[u8; 32]>,
+    /// Display name (`dn`)
+    pub name: Option<String>,
+    /// Content length in bytes (`xl`)
+    pub length: Option<u64>,
+    /// Tracker URLs (`tr`)
+    pub trackers: Vec<String>,
+    /// Web seed URLs (`ws`)
+    pub web_seeds: Vec<String>,
+    /// Peer addresses, as `host:port` (`x.pe`)
+    pub peers: Vec<String>,
+    /// Indexes of the files to download, every file when empty
+    /// ([BEP 0053](https://www.bittorrent.org/beps/bep_0053.html), `so`)
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+impl Magnet {
+    pub fn new(info_hash: InfoHash) -> Self {
+        Self {
+            info_hash: Some(info_hash),
+            ..Default::default()
+        }
+    }
+
+    pub fn parse(uri: &str) -> Result<Self, MagnetError> {
+        let query = match uri.get(..SCHEME.len()) {
+            Some(s) if s.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+            _ => return Err(MagnetError::InvalidScheme(uri.to_string())),
+        };
+
+        let mut magnet = Self::default();
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let invalid = || MagnetError::InvalidParameter {
+                key: key.to_string(),
+                value: value.to_string(),
+            };
+            let value = urldecode(value, key == "dn").ok_or_else(invalid)?;
+
+            // Repeated parameters may be numbered, e.g. `xt.1` and `xt.2`
+            let name = match key.rsplit_once('.') {
+                Some((name, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => name,
+                _ => key,
+            };
+            match name {
+                "xt" => magnet.parse_topic(&value)?,
+                "dn" => magnet.name = Some(value),
+                "xl" => magnet.length = Some(value.parse().map_err(|_| invalid())?),
+                "tr" => magnet.trackers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "so" => magnet.select_only = parse_selection(&value).ok_or_else(invalid)?,
+                _ => {}
+            }
+        }
+
+        if magnet.info_hash.is_none() && magnet.info_hash_v2.is_none() {
+            return Err(MagnetError::MissingInfoHash);
+        }
+
+        Ok(magnet)
+    }
+
+    /// Parse an exact topic. Topics other than BitTorrent info hashes are ignored.
+    fn parse_topic(&mut self, topic: &str) -> Result<(), MagnetError> {
+        let invalid = || MagnetError::InvalidInfoHash(topic.to_string());
+        let urn = |prefix: &str| {
+            topic
+                .get(..prefix.len())
+                .filter(|s| s.eq_ignore_ascii_case(prefix))
+                .map(|_| &topic[prefix.len()..])
+        };
+
+        if let Some(hash) = urn(BTIH) {
+            self.info_hash = Some(hash.parse().map_err(|_| invalid())?);
+        } else if let Some(multihash) = urn(BTMH) {
+            let bytes = hex::decode(multihash).map_err(|_| invalid())?;
+            let digest = bytes
+                .strip_prefix(&SHA256_MULTIHASH)
+                .and_then(|d| d.try_into().ok())
+                .ok_or_else(invalid)?;
+            self.info_hash_v2 = Some(digest);
+        }
+
+        Ok(())
+    }
+
+    /// Build a `.torrent` file from the `info` dictionary fetched from peers, with the
+    /// trackers and web seeds of the link. `info` is kept as is so the info hash is preserved.
+    pub fn to_torrent(&self, info: &[u8]) -> Result<Vec<u8>, MagnetError> {
+        let info_hash = self.info_hash.ok_or(MagnetError::V2Only)?;
+        if Sha1::digest(info).as_slice() != info_hash.as_bytes() {
+            return Err(ExtensionError::MetadataHashMismatch.into());
+        }
+
+        let mut out = vec![b'd'];
+        if let Some(tracker) = self.trackers.first() {
+            push_str(&mut out, "announce");
+            push_str(&mut out, tracker);
+        }
+        if self.trackers.len() > 1 {
+            push_str(&mut out, "announce-list");
+            out.push(b'l');
+            for tracker in &self.trackers {
+                out.push(b'l');
+                push_str(&mut out, tracker);
+                out.push(b'e');
+            }
+            out.push(b'e');
+        }
+        push_str(&mut out, "info");
+        out.extend_from_slice(info);
+        if !self.web_seeds.is_empty() {
+            push_str(&mut out, "url-list");
+            out.push(b'l');
+            for url in &self.web_seeds {
+                push_str(&mut out, url);
+            }
+            out.push(b'e');
+        }
+        out.push(b'e');
+
+        Ok(out)
+    }
+
+    /// Whether the file at `index` in the torrent should be downloaded
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.select_only.is_empty() || self.select_only.iter().any(|r| r.contains(&index))
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = MagnetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Magnet {
+    /// Format the magnet URI, every value being percent-encoded
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params = vec![];
+        if let Some(hash) = &self.info_hash {
+            params.push(format!("xt={BTIH}{}", hash.to_hex()));
+        }
+        if let Some(hash) = &self.info_hash_v2 {
+            let multihash = [&SHA256_MULTIHASH[..], hash].concat();
+            params.push(format!("xt={BTMH}{}", hex::encode(multihash)));
+        }
+        if let Some(name) = &self.name {
+            params.push(format!("dn={}", urlencode(name.as_bytes())));
+        }
+        if let Some(length) = self.length {
+            params.push(format!("xl={length}"));
+        }
+        let lists = [
+            ("tr", &self.trackers),
+            ("ws", &self.web_seeds),
+            ("x.pe", &self.peers),
+        ];
+        for (key, values) in lists {
+            for v in values {
+                params.push(format!("{key}={}", urlencode(v.as_bytes())));
+            }
+        }
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|r| match r.start() == r.end() {
+                    true => r.start().to_string(),
+                    false => format!("{}-{}", r.start(), r.end()),
+                })
+                .collect();
+            params.push(format!("so={}", ranges.join(",")));
+        }
+
+        write!(f, "{SCHEME}{}", params.join("&"))
+    }
+}
+
+/// Append a bencoded string
+fn push_str(out: &mut Vec<u8>, v: &str) {
+    out.extend_from_slice(format!("{}:", v.len()).as_bytes());
+    out.extend_from_slice(v.as_bytes());
+}
+
+/// Parse a file selection such as `0,2,4-6`
+fn parse_selection(v: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    v.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(start..=end)
+            }
+            None => s.parse().ok().map(|i| i..=i),
+        })
+        .collect()
+}
+
+/// Decode a percent-encoded value. `+` is only decoded as a space when `plus_as_space` is
+/// set, as URLs found in other parameters may contain it.
+fn urldecode(v: &str, plus_as_space: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(v.len());
+    let mut input = v.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+
+    #[test]
+    fn parse_every_parameter() {
+        let uri = format!(
+            "magnet:?xt=urn:btih:{HASH}&dn=Some+name%21&xl=1024\
+             &tr=udp%3A%2F%2Ftracker%3A80&tr.2=http://b/a+b&ws=http://seed&x.pe=1.2.3.4:5\
+             &so=0,2,4-6&unknown=1"
+        );
+        let magnet = Magnet::parse(&uri).unwrap();
+
+        assert_eq!(magnet.info_hash, Some(InfoHash::from_hex(HASH).unwrap()));
+        assert_eq!(magnet.name.as_deref(), Some("Some name!"));
+        assert_eq!(magnet.length, Some(1024));
+        assert_eq!(magnet.trackers, ["udp://tracker:80", "http://b/a+b"]);
+        assert_eq!(magnet.web_seeds, ["http://seed"]);
+        assert_eq!(magnet.peers, ["1.2.3.4:5"]);
+        assert_eq!(magnet.select_only, [0..=0, 2..=2, 4..=6]);
+        assert!(magnet.is_selected(5));
+        assert!(!magnet.is_selected(3));
+    }
+
+    #[test]
+    fn parse_base32_and_v2_hashes() {
+        let v2 = "1220".to_string() + &"ab".repeat(32);
+        let uri =
+            format!("MAGNET:?xt.1=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK&xt.2=urn:btmh:{v2}");
+        let magnet = Magnet::parse(&uri).unwrap();
+
+        assert_eq!(magnet.info_hash, Some(InfoHash::from_hex(HASH).unwrap()));
+        assert_eq!(magnet.info_hash_v2, Some([0xab; 32]));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_links() {
+        assert!(matches!(
+            Magnet::parse("http://example.com"),
+            Err(MagnetError::InvalidScheme(_))
+        ));
+        assert!(matches!(
+            Magnet::parse("magnet:?dn=name&xt=urn:sha1:abc"),
+            Err(MagnetError::MissingInfoHash)
+        ));
+        assert!(matches!(
+            Magnet::parse("magnet:?xt=urn:btih:1234"),
+            Err(MagnetError::InvalidInfoHash(_))
+        ));
+        assert!(matches!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{HASH}&so=3-1")),
+            Err(MagnetError::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{HASH}&dn=%4")),
+            Err(MagnetError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let magnet = Magnet {
+            name: Some("a name & more".to_string()),
+            length: Some(7),
+            trackers: vec!["http://tracker/announce?a=1".to_string()],
+            select_only: vec![1..=1, 3..=4],
+            ..Magnet::new(InfoHash::from_hex(HASH).unwrap())
+        };
+
+        assert_eq!(Magnet::parse(&magnet.to_string()).unwrap(), magnet);
+    }
+
+    #[test]
+    fn to_torrent_checks_the_info_hash() {
+        let info = b"d6:lengthi5e4:name4:test12:piece lengthi16384e6:pieces0:e";
+        let magnet = Magnet {
+            trackers: vec!["http://a".to_string(), "http://b".to_string()],
+            ..Magnet::new(InfoHash(Sha1::digest(info).into()))
+        };
+
+        let torrent = magnet.to_torrent(info).unwrap();
+        assert_eq!(
+            torrent,
+            [
+                &b"d8:announce8:http://a13:announce-listll8:http://ael8:http://bee4:info"[..],
+                info,
+                b"e",
+            ]
+            .concat()
+        );
+        assert!(matches!(
+            Magnet::new(InfoHash([0; 20])).to_torrent(info),
+            Err(MagnetError::Metadata(_))
+        ));
+    }
+}
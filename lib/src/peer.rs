@@ -1,5 +1,9 @@
+use std::fmt;
+
 use rand::{distributions::Alphanumeric, Rng};
 
+use crate::torrent::urlencode;
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Generate a peer ID matching the specification `BEP 0020`
@@ -29,3 +33,46 @@ pub fn gen_peer_id(prefix: Option<String>) -> String {
 
     format!("{prefix}{random_alphanum}")
 }
+
+/// PeerId is the 20 bytes identifier of a client in a swarm.
+/// Any byte value is allowed, even if most clients only use printable characters.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PeerId(pub [u8; 20]);
+
+impl PeerId {
+    /// Generate a new random peer ID with [`gen_peer_id`]
+    pub fn generate(prefix: Option<String>) -> Self {
+        let mut id = [0u8; 20];
+        id.copy_from_slice(&gen_peer_id(prefix).as_bytes()[..20]);
+
+        Self(id)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Percent-encoded form, byte by byte, as expected by HTTP trackers
+    pub fn urlencode(&self) -> String {
+        urlencode(&self.0)
+    }
+}
+
+impl From<[u8; 20]> for PeerId {
+    fn from(v: [u8; 20]) -> Self {
+        Self(v)
+    }
+}
+
+impl fmt::Display for PeerId {
+    /// Printable bytes are written as is, others are escaped
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.escape_ascii())
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PeerId({self})")
+    }
+}
@@ -0,0 +1,27 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::{dht::DhtError, engine::EngineError, torrent::errors::TorrentError, torrent::InfoHash};
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("Failed to listen for incoming peers: {0}")]
+    Listen(io::Error),
+    #[error("Failed to access session state at {0}: {1}")]
+    State(PathBuf, io::Error),
+    #[error("Failed to parse session state: {0}")]
+    ParseState(bendy::serde::Error),
+    #[error("Failed to encode session state: {0}")]
+    EncodeState(bendy::serde::Error),
+    #[error("Invalid torrent: {0}")]
+    Torrent(#[from] TorrentError),
+    #[error("Failed to start torrent: {0}")]
+    Engine(#[from] EngineError),
+    #[error("Failed to start the DHT node: {0}")]
+    Dht(#[from] DhtError),
+    #[error("Torrent {0} is already in the session")]
+    Duplicate(InfoHash),
+    #[error("Unknown torrent {0}")]
+    UnknownTorrent(InfoHash),
+}
@@ -0,0 +1,961 @@
+//! Session running many torrents at once, sharing a listening port, tracker clients and
+//! connection limits.
+
+mod errors;
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use bendy::serde::{from_bytes, to_bytes};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, BoolFromInt};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Semaphore},
+    task::JoinHandle,
+    time,
+};
+
+use crate::{
+    bandwidth::{Bandwidth, SpeedLimits, SpeedSchedule},
+    dht::{Dht, DhtConfig},
+    engine::{self, Engine, EngineError, EngineProgress, ResumeData, SeedTarget},
+    peer::PeerId,
+    torrent::{
+        v1::{CancelHandle, Handshake, PeerConnection, Torrent},
+        InfoHash,
+    },
+};
+
+pub use errors::SessionError;
+
+/// File listing the torrents of the session, in the state directory
+const STATE_FILE: &str = "session.state";
+/// File keeping the DHT routing table, in the state directory
+const DHT_STATE_FILE: &str = "dht.dat";
+/// Default maximum number of connected peers over every torrent
+const DEFAULT_MAX_CONNECTIONS: usize = 200;
+const DEFAULT_MAX_ACTIVE_DOWNLOADS: usize = 3;
+const DEFAULT_MAX_ACTIVE_SEEDS: usize = 5;
+/// Delay before accepting connections again after the listener failed
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+/// Interval between two checks of the speed schedule
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Settings of a [`Session`], shared by every torrent
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Directory keeping the torrent list, the torrent files and their resume data
+    pub state_dir: PathBuf,
+    /// Port listened on and announced for every torrent
+    pub port: u16,
+    pub peer_id: PeerId,
+    /// Maximum number of connected peers over every torrent
+    pub max_connections: usize,
+    /// Maximum number of connected peers per torrent
+    pub max_peers: usize,
+    /// Maximum number of torrents downloading at the same time
+    pub max_active_downloads: usize,
+    /// Maximum number of completed torrents started to seed at the same time
+    pub max_active_seeds: usize,
+    /// Keep seeding completed torrents until the target is reached.
+    /// When `None`, torrents stop as soon as they are complete.
+    pub seed: Option<SeedTarget>,
+    /// Rate limits over every torrent
+    pub limits: SpeedLimits,
+    /// Rate limits of each peer
+    pub peer_limits: SpeedLimits,
+    /// Switch the session limits to alternative ones during part of the day
+    pub schedule: Option<SpeedSchedule>,
+    /// Run a DHT node finding peers for every public torrent. Its state is kept in the
+    /// state directory unless [`DhtConfig::state_file`] is set.
+    pub dht: Option<DhtConfig>,
+}
+
+impl SessionConfig {
+    pub fn new(state_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            state_dir: state_dir.into(),
+            port: engine::DEFAULT_PORT,
+            peer_id: PeerId::generate(None),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_peers: engine::DEFAULT_MAX_PEERS,
+            max_active_downloads: DEFAULT_MAX_ACTIVE_DOWNLOADS,
+            max_active_seeds: DEFAULT_MAX_ACTIVE_SEEDS,
+            seed: None,
+            limits: SpeedLimits::default(),
+            peer_limits: SpeedLimits::default(),
+            schedule: None,
+            dht: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TorrentState {
+    /// Waiting for a download or seed slot
+    Queued,
+    Downloading,
+    Seeding,
+    Paused,
+    /// Complete and not seeding anymore
+    Finished,
+    /// The engine stopped on an error, see [`Session::resume`] to try again
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TorrentStatus {
+    pub info_hash: InfoHash,
+    pub name: String,
+    /// Directory containing the torrent content
+    pub output: PathBuf,
+    pub state: TorrentState,
+    /// Last progress reported by the engine, `None` if it never ran
+    pub progress: Option<EngineProgress>,
+}
+
+/// Session owns many torrents and runs them with a download and seed queue.
+///
+/// Every torrent shares the same peer ID, listening port, HTTP client, connection limit and
+/// rate limits.
+/// Incoming peers are dispatched to the torrent matching their handshake. The torrent list
+/// is kept in the state directory along with the resume data of each torrent, so a new
+/// session started on the same directory picks up where the last one stopped.
+///
+/// Methods spawn tasks, they must be called from a Tokio runtime.
+#[derive(Debug)]
+pub struct Session {
+    shared: Arc<Shared>,
+    listener: JoinHandle<()>,
+    scheduler: Option<JoinHandle<()>>,
+    /// Joining the DHT network in the background
+    bootstrap: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: SessionConfig,
+    connections: Arc<Semaphore>,
+    http: reqwest::Client,
+    bandwidth: Bandwidth,
+    peer_bandwidth: Bandwidth,
+    dht: Option<Arc<Dht>>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    torrents: HashMap<InfoHash, Entry>,
+    /// Torrents in queue order
+    order: Vec<InfoHash>,
+    shutdown: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    output: PathBuf,
+    state: TorrentState,
+    paused: bool,
+    /// The torrent was seen complete, it takes a seed slot instead of a download slot
+    complete: bool,
+    /// The engine is stopped to wait for a seed slot, the torrent is queued again once done
+    requeue: bool,
+    progress: Option<EngineProgress>,
+    /// Limits of this torrent only, on top of the session ones
+    bandwidth: Bandwidth,
+    running: Option<Running>,
+}
+
+/// Handles of a running engine
+#[derive(Debug)]
+struct Running {
+    cancel: CancelHandle,
+    incoming: mpsc::UnboundedSender<PeerConnection>,
+    task: JoinHandle<()>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, Serialize)]
+struct SavedTorrent {
+    #[serde(rename = "info-hash")]
+    info_hash: InfoHash,
+    output: PathBuf,
+    #[serde_as(as = "BoolFromInt")]
+    paused: bool,
+    #[serde_as(as = "BoolFromInt")]
+    #[serde(default)]
+    complete: bool,
+    #[serde(
+        rename = "upload limit",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    upload_limit: Option<u64>,
+    #[serde(
+        rename = "download limit",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    download_limit: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SavedSession {
+    torrents: Vec<SavedTorrent>,
+}
+
+impl Session {
+    /// Start listening for peers and restore the torrents saved in the state directory
+    pub async fn start(config: SessionConfig) -> Result<Self, SessionError> {
+        fs::create_dir_all(&config.state_dir)
+            .map_err(|e| SessionError::State(config.state_dir.clone(), e))?;
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, config.port))
+            .await
+            .map_err(SessionError::Listen)?;
+        let dht = match config.dht.clone() {
+            Some(mut dht) => {
+                let path = config.state_dir.join(DHT_STATE_FILE);
+                dht.state_file = dht.state_file.or(Some(path));
+                Some(Arc::new(Dht::bind(dht).await?))
+            }
+            None => None,
+        };
+
+        let shared = Arc::new(Shared {
+            connections: Arc::new(Semaphore::new(config.max_connections)),
+            http: reqwest::Client::new(),
+            bandwidth: Bandwidth::new(config.limits),
+            peer_bandwidth: Bandwidth::new(config.peer_limits),
+            dht,
+            state: Mutex::new(State::default()),
+            config,
+        });
+        let saved = shared.load()?;
+        {
+            let mut state = shared.lock();
+            for t in saved.torrents {
+                let name = shared
+                    .read_torrent(&t.info_hash)
+                    .and_then(|b| Ok(Torrent::parse_bytes(&b)?.info.name));
+                let (name, torrent_state) = match name {
+                    Ok(v) if t.paused => (v, TorrentState::Paused),
+                    Ok(v) => (v, TorrentState::Queued),
+                    Err(e) => (t.info_hash.to_hex(), TorrentState::Failed(e.to_string())),
+                };
+                let mut entry = Entry::new(name, t.output, torrent_state, t.paused);
+                entry.complete = t.complete;
+                entry.bandwidth.set_limits(SpeedLimits {
+                    upload: t.upload_limit,
+                    download: t.download_limit,
+                });
+                state.order.push(t.info_hash);
+                state.torrents.insert(t.info_hash, entry);
+            }
+            shared.schedule(&mut state);
+        }
+
+        let listener = tokio::spawn(Arc::clone(&shared).accept_peers(listener));
+        let scheduler = shared
+            .config
+            .schedule
+            .map(|s| tokio::spawn(Arc::clone(&shared).follow_schedule(s)));
+        // Engines started before the end retry their DHT lookups later
+        let bootstrap = shared.dht.clone().map(|dht| {
+            tokio::spawn(async move {
+                let _ = dht.bootstrap().await;
+            })
+        });
+        Ok(Self {
+            shared,
+            listener,
+            scheduler,
+            bootstrap,
+        })
+    }
+
+    /// Add a torrent downloading into `output`, the directory containing its content.
+    /// It starts once a download slot is available.
+    pub fn add_torrent(&self, torrent: &[u8], output: &Path) -> Result<InfoHash, SessionError> {
+        let parsed = Torrent::parse_bytes(torrent)?;
+        // Fail early on torrents the engine would refuse
+        let info_hash = Engine::new(&parsed, output)?.info_hash();
+
+        let mut state = self.shared.lock();
+        if state.torrents.contains_key(&info_hash) {
+            return Err(SessionError::Duplicate(info_hash));
+        }
+        let path = self.shared.torrent_path(&info_hash);
+        fs::write(&path, torrent).map_err(|e| SessionError::State(path, e))?;
+        state.order.push(info_hash);
+        state.torrents.insert(
+            info_hash,
+            Entry::new(
+                parsed.info.name.clone(),
+                output.to_path_buf(),
+                TorrentState::Queued,
+                false,
+            ),
+        );
+        self.shared.save(&state)?;
+        self.shared.schedule(&mut state);
+
+        Ok(info_hash)
+    }
+
+    /// Stop and forget a torrent. Its content is kept on disk.
+    pub fn remove(&self, info_hash: InfoHash) -> Result<(), SessionError> {
+        let mut state = self.shared.lock();
+        let entry = state
+            .torrents
+            .remove(&info_hash)
+            .ok_or(SessionError::UnknownTorrent(info_hash))?;
+        state.order.retain(|h| *h != info_hash);
+        if let Some(running) = entry.running {
+            running.cancel.cancel();
+        }
+        let _ = fs::remove_file(self.shared.torrent_path(&info_hash));
+        let _ = fs::remove_file(self.shared.resume_path(&info_hash));
+        self.shared.save(&state)?;
+        self.shared.schedule(&mut state);
+
+        Ok(())
+    }
+
+    /// Stop a torrent until [`Session::resume`] is called, freeing its slot
+    pub fn pause(&self, info_hash: InfoHash) -> Result<(), SessionError> {
+        let mut state = self.shared.lock();
+        let entry = state
+            .torrents
+            .get_mut(&info_hash)
+            .ok_or(SessionError::UnknownTorrent(info_hash))?;
+        entry.paused = true;
+        match &entry.running {
+            Some(running) => running.cancel.cancel(),
+            None => entry.state = TorrentState::Paused,
+        }
+        self.shared.save(&state)?;
+        self.shared.schedule(&mut state);
+
+        Ok(())
+    }
+
+    /// Queue a paused, finished or failed torrent again
+    pub fn resume(&self, info_hash: InfoHash) -> Result<(), SessionError> {
+        let mut state = self.shared.lock();
+        let entry = state
+            .torrents
+            .get_mut(&info_hash)
+            .ok_or(SessionError::UnknownTorrent(info_hash))?;
+        entry.paused = false;
+        if entry.running.is_none() {
+            entry.state = TorrentState::Queued;
+        }
+        self.shared.save(&state)?;
+        self.shared.schedule(&mut state);
+
+        Ok(())
+    }
+
+    /// Status of every torrent, in queue order
+    pub fn torrents(&self) -> Vec<TorrentStatus> {
+        let state = self.shared.lock();
+        state
+            .order
+            .iter()
+            .map(|h| {
+                let entry = &state.torrents[h];
+                TorrentStatus {
+                    info_hash: *h,
+                    name: entry.name.clone(),
+                    output: entry.output.clone(),
+                    state: entry.state.clone(),
+                    progress: entry.progress,
+                }
+            })
+            .collect()
+    }
+
+    /// Rate limits over every torrent
+    pub fn limits(&self) -> SpeedLimits {
+        self.shared.bandwidth.limits()
+    }
+
+    /// Change the rate limits over every torrent, taking effect immediately.
+    /// When a schedule is set, they are replaced at the next start or end of its window.
+    pub fn set_limits(&self, limits: SpeedLimits) {
+        self.shared.bandwidth.set_limits(limits);
+    }
+
+    /// Change the rate limits of each peer, taking effect immediately
+    pub fn set_peer_limits(&self, limits: SpeedLimits) {
+        self.shared.peer_bandwidth.set_limits(limits);
+    }
+
+    pub fn torrent_limits(&self, info_hash: InfoHash) -> Result<SpeedLimits, SessionError> {
+        let state = self.shared.lock();
+        let entry = state
+            .torrents
+            .get(&info_hash)
+            .ok_or(SessionError::UnknownTorrent(info_hash))?;
+
+        Ok(entry.bandwidth.limits())
+    }
+
+    /// Change the rate limits of a torrent, taking effect immediately
+    pub fn set_torrent_limits(
+        &self,
+        info_hash: InfoHash,
+        limits: SpeedLimits,
+    ) -> Result<(), SessionError> {
+        let state = self.shared.lock();
+        state
+            .torrents
+            .get(&info_hash)
+            .ok_or(SessionError::UnknownTorrent(info_hash))?
+            .bandwidth
+            .set_limits(limits);
+
+        self.shared.save(&state)
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.shared.config.peer_id
+    }
+
+    /// Stop every torrent, waiting for their resume data and final announces, and save the
+    /// torrent list.
+    pub async fn shutdown(self) -> Result<(), SessionError> {
+        self.listener.abort();
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.abort();
+        }
+        if let Some(bootstrap) = &self.bootstrap {
+            bootstrap.abort();
+        }
+        let tasks: Vec<JoinHandle<()>> = {
+            let mut state = self.shared.lock();
+            state.shutdown = true;
+            state
+                .torrents
+                .values_mut()
+                .filter_map(|e| e.running.take())
+                .map(|r| {
+                    r.cancel.cancel();
+                    r.task
+                })
+                .collect()
+        };
+        for task in tasks {
+            let _ = task.await;
+        }
+
+        if let Some(dht) = &self.shared.dht {
+            let _ = dht.save_state();
+        }
+        let state = self.shared.lock();
+        self.shared.save(&state)
+    }
+}
+
+impl State {
+    /// Number of running torrents, either seeding or downloading
+    fn running(&self, complete: bool) -> usize {
+        self.torrents
+            .values()
+            .filter(|e| e.running.is_some() && e.complete == complete)
+            .count()
+    }
+
+    /// Queued torrents to start, in queue order, given the maximum number of active
+    /// downloads and seeds
+    fn startable(&self, max_downloads: usize, max_seeds: usize) -> Vec<InfoHash> {
+        let (mut downloads, mut seeds) = (self.running(false), self.running(true));
+        let mut startable = vec![];
+        for hash in &self.order {
+            let entry = &self.torrents[hash];
+            if entry.paused || entry.running.is_some() || entry.state != TorrentState::Queued {
+                continue;
+            }
+            let (active, max) = match entry.complete {
+                true => (&mut seeds, max_seeds),
+                false => (&mut downloads, max_downloads),
+            };
+            if *active >= max {
+                continue;
+            }
+            *active += 1;
+            startable.push(*hash);
+        }
+
+        startable
+    }
+}
+
+impl Entry {
+    fn new(name: String, output: PathBuf, state: TorrentState, paused: bool) -> Self {
+        Self {
+            name,
+            output,
+            state,
+            paused,
+            complete: false,
+            requeue: false,
+            progress: None,
+            bandwidth: Bandwidth::default(),
+            running: None,
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("session state poisoned")
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.config.state_dir.join(STATE_FILE)
+    }
+
+    fn torrent_path(&self, info_hash: &InfoHash) -> PathBuf {
+        self.config
+            .state_dir
+            .join(format!("{}.torrent", info_hash.to_hex()))
+    }
+
+    fn resume_path(&self, info_hash: &InfoHash) -> PathBuf {
+        ResumeData::path_for(&self.torrent_path(info_hash))
+    }
+
+    fn read_torrent(&self, info_hash: &InfoHash) -> Result<Vec<u8>, SessionError> {
+        let path = self.torrent_path(info_hash);
+        fs::read(&path).map_err(|e| SessionError::State(path, e))
+    }
+
+    fn load(&self) -> Result<SavedSession, SessionError> {
+        let path = self.state_path();
+        match fs::read(&path) {
+            Ok(bytes) => from_bytes(&bytes).map_err(SessionError::ParseState),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(SavedSession::default()),
+            Err(e) => Err(SessionError::State(path, e)),
+        }
+    }
+
+    /// Write the torrent list. A temporary file is renamed over the previous one.
+    fn save(&self, state: &State) -> Result<(), SessionError> {
+        let saved = SavedSession {
+            torrents: state
+                .order
+                .iter()
+                .map(|h| {
+                    let entry = &state.torrents[h];
+                    let limits = entry.bandwidth.limits();
+                    SavedTorrent {
+                        info_hash: *h,
+                        output: entry.output.clone(),
+                        paused: entry.paused,
+                        complete: entry.complete,
+                        upload_limit: limits.upload,
+                        download_limit: limits.download,
+                    }
+                })
+                .collect(),
+        };
+        let bytes = to_bytes(&saved).map_err(SessionError::EncodeState)?;
+        let path = self.state_path();
+        let tmp = path.with_extension("tmp");
+
+        fs::write(&tmp, bytes)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| SessionError::State(path, e))
+    }
+
+    /// Start queued torrents while download and seed slots are available
+    fn schedule(self: &Arc<Self>, state: &mut State) {
+        if state.shutdown {
+            return;
+        }
+        let startable = state.startable(
+            self.config.max_active_downloads,
+            self.config.max_active_seeds,
+        );
+
+        for hash in startable {
+            if let Err(e) = self.start(state, hash) {
+                let entry = state.torrents.get_mut(&hash).expect("queued torrent");
+                entry.state = TorrentState::Failed(e.to_string());
+            }
+        }
+    }
+
+    /// Spawn the engine of a torrent
+    fn start(self: &Arc<Self>, state: &mut State, info_hash: InfoHash) -> Result<(), SessionError> {
+        let bytes = self.read_torrent(&info_hash)?;
+        let torrent = Torrent::parse_bytes(&bytes)?;
+        let entry = state
+            .torrents
+            .get_mut(&info_hash)
+            .expect("started torrent should exist");
+
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let mut engine = Engine::new(&torrent, &entry.output)?
+            .peer_id(self.config.peer_id)
+            .port(self.config.port)
+            .max_peers(self.config.max_peers)
+            .connection_limit(Arc::clone(&self.connections))
+            .http_client(self.http.clone())
+            .incoming(incoming_rx)
+            .resume_file(self.resume_path(&info_hash))
+            .rate_limit(entry.bandwidth.clone())
+            .shared_rate_limit(self.bandwidth.clone())
+            .peer_rate_limit(self.peer_bandwidth.clone());
+        if let Some(target) = self.config.seed {
+            engine = engine.seed(target);
+        }
+        if let Some(dht) = &self.dht {
+            engine = engine.dht(Arc::clone(dht));
+        }
+        let cancel = engine.cancel_handle();
+
+        let shared = Arc::clone(self);
+        let task = tokio::spawn(async move {
+            let res = engine.run(|p| shared.update_progress(&info_hash, p)).await;
+            shared.stopped(&info_hash, res);
+        });
+        entry.state = match entry.complete {
+            true => TorrentState::Seeding,
+            false => TorrentState::Downloading,
+        };
+        entry.running = Some(Running {
+            cancel,
+            incoming: incoming_tx,
+            task,
+        });
+
+        Ok(())
+    }
+
+    /// Record the progress of an engine. A completed download frees its download slot,
+    /// and keeps seeding only if a seed slot is available.
+    fn update_progress(self: &Arc<Self>, info_hash: &InfoHash, progress: EngineProgress) {
+        let mut state = self.lock();
+        let seeds = state.running(true);
+        let entry = match state.torrents.get_mut(info_hash) {
+            Some(v) => v,
+            None => return,
+        };
+        let completed = !entry.complete && progress.downloaded == progress.total;
+        entry.complete = progress.downloaded == progress.total;
+        entry.progress = Some(progress);
+        let running = match &entry.running {
+            Some(v) => v,
+            None => return,
+        };
+        entry.state = match entry.complete {
+            true => TorrentState::Seeding,
+            false => TorrentState::Downloading,
+        };
+        if !completed {
+            return;
+        }
+
+        if self.config.seed.is_some() && seeds >= self.config.max_active_seeds {
+            running.cancel.cancel();
+            entry.requeue = true;
+        }
+        self.schedule(&mut state);
+    }
+
+    /// Record the end of an engine and give its slot to the next queued torrent
+    fn stopped(self: &Arc<Self>, info_hash: &InfoHash, res: Result<(), EngineError>) {
+        let mut state = self.lock();
+        match state.torrents.get_mut(info_hash) {
+            Some(entry) => {
+                entry.running = None;
+                let requeue = std::mem::take(&mut entry.requeue);
+                entry.state = match res {
+                    _ if entry.paused => TorrentState::Paused,
+                    _ if requeue => TorrentState::Queued,
+                    Ok(_) => TorrentState::Finished,
+                    Err(EngineError::Cancelled) => TorrentState::Queued,
+                    Err(e) => TorrentState::Failed(e.to_string()),
+                };
+            }
+            // Removed while running: the engine saved its resume data once stopped
+            None => {
+                let _ = fs::remove_file(self.resume_path(info_hash));
+            }
+        }
+        self.schedule(&mut state);
+    }
+
+    /// Apply the limits of the schedule each time its window starts or ends, leaving the
+    /// limits set in between untouched
+    async fn follow_schedule(self: Arc<Self>, schedule: SpeedSchedule) {
+        let mut interval = time::interval(SCHEDULE_INTERVAL);
+        let mut alternative = None;
+        loop {
+            interval.tick().await;
+            let now = Local::now().time();
+            if alternative != Some(schedule.is_alternative(now)) {
+                alternative = Some(schedule.is_alternative(now));
+                schedule.apply(&self.bandwidth, now);
+            }
+        }
+    }
+
+    async fn accept_peers(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(Arc::clone(&self).dispatch(stream));
+                }
+                Err(_) => time::sleep(ACCEPT_RETRY).await,
+            }
+        }
+    }
+
+    /// Handshake an incoming peer and hand it to the engine of the requested torrent
+    async fn dispatch(self: Arc<Self>, stream: TcpStream) {
+        let mut target = None;
+        let conn = PeerConnection::accept(stream, |remote| {
+            let state = self.lock();
+            let running = state.torrents.get(&remote.info_hash)?.running.as_ref()?;
+            target = Some(running.incoming.clone());
+
+            Some(Handshake::new(remote.info_hash, self.config.peer_id).with_extension_protocol())
+        })
+        .await;
+
+        if let (Ok(conn), Some(target)) = (conn, target) {
+            let _ = target.send(conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn torrent_bytes(name: &str) -> Vec<u8> {
+        format!(
+            "d8:announce8:http://a4:infod6:lengthi1e4:name{}:{name}12:piece lengthi1e\
+            6:pieces20:aaaaaaaaaaaaaaaaaaaaee",
+            name.len()
+        )
+        .into_bytes()
+    }
+
+    fn state_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("brs-session-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Configuration never starting any engine, so torrents stay queued
+    fn config(dir: &Path) -> SessionConfig {
+        SessionConfig {
+            port: 0,
+            max_active_downloads: 0,
+            max_active_seeds: 0,
+            ..SessionConfig::new(dir)
+        }
+    }
+
+    /// Handles of an engine that isn't actually running
+    fn running() -> Running {
+        let (incoming, _) = mpsc::unbounded_channel();
+        Running {
+            cancel: CancelHandle::default(),
+            incoming,
+            task: tokio::spawn(async {}),
+        }
+    }
+
+    fn progress(downloaded: u64) -> EngineProgress {
+        EngineProgress {
+            verified_pieces: downloaded as usize,
+            total_pieces: 1,
+            downloaded,
+            total: 1,
+            uploaded: 0,
+            peers: 0,
+            download_rate: 0,
+            upload_rate: 0,
+        }
+    }
+
+    fn states(session: &Session) -> Vec<TorrentState> {
+        session.torrents().into_iter().map(|t| t.state).collect()
+    }
+
+    #[tokio::test]
+    async fn startable_follows_queue_limits() {
+        let mut state = State::default();
+        let entries = [
+            ("running", false, TorrentState::Downloading, false),
+            ("download", false, TorrentState::Queued, false),
+            ("paused", false, TorrentState::Paused, true),
+            ("failed", false, TorrentState::Failed("e".into()), false),
+            ("next download", false, TorrentState::Queued, false),
+            ("seed", true, TorrentState::Queued, false),
+            ("next seed", true, TorrentState::Queued, false),
+        ];
+        let mut hashes = vec![];
+        for (i, (name, complete, torrent_state, paused)) in entries.into_iter().enumerate() {
+            let hash = InfoHash([i as u8; 20]);
+            let mut entry = Entry::new(name.into(), PathBuf::new(), torrent_state, paused);
+            entry.complete = complete;
+            if name == "running" {
+                entry.running = Some(running());
+            }
+            state.order.push(hash);
+            state.torrents.insert(hash, entry);
+            hashes.push(hash);
+        }
+
+        assert_eq!(state.startable(2, 1), vec![hashes[1], hashes[5]]);
+        assert_eq!(
+            state.startable(3, 2),
+            vec![hashes[1], hashes[4], hashes[5], hashes[6]]
+        );
+        assert_eq!(state.startable(1, 0), vec![]);
+    }
+
+    #[tokio::test]
+    async fn add_pause_resume_remove() {
+        let dir = state_dir("lifecycle");
+        let session = Session::start(config(&dir)).await.unwrap();
+        let bytes = torrent_bytes("a");
+        let hash = session.add_torrent(&bytes, &dir).unwrap();
+
+        let status = &session.torrents()[0];
+        assert_eq!((status.info_hash, status.name.as_str()), (hash, "a"));
+        assert_eq!(states(&session), vec![TorrentState::Queued]);
+        assert!(matches!(
+            session.add_torrent(&bytes, &dir),
+            Err(SessionError::Duplicate(h)) if h == hash
+        ));
+
+        session.pause(hash).unwrap();
+        assert_eq!(states(&session), vec![TorrentState::Paused]);
+        session.resume(hash).unwrap();
+        assert_eq!(states(&session), vec![TorrentState::Queued]);
+
+        session.remove(hash).unwrap();
+        assert!(session.torrents().is_empty());
+        assert!(!session.shared.torrent_path(&hash).exists());
+        assert!(matches!(
+            session.pause(hash),
+            Err(SessionError::UnknownTorrent(h)) if h == hash
+        ));
+
+        session.shutdown().await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn torrents_are_restored() {
+        let dir = state_dir("restore");
+        let session = Session::start(config(&dir)).await.unwrap();
+        let a = session.add_torrent(&torrent_bytes("a"), &dir).unwrap();
+        let b = session.add_torrent(&torrent_bytes("b"), &dir).unwrap();
+        session.pause(a).unwrap();
+        let limits = SpeedLimits {
+            upload: Some(1000),
+            download: None,
+        };
+        session.set_torrent_limits(b, limits).unwrap();
+        session.shared.update_progress(&b, progress(1));
+        session.shutdown().await.unwrap();
+
+        let session = Session::start(config(&dir)).await.unwrap();
+        let hashes: Vec<InfoHash> = session.torrents().iter().map(|t| t.info_hash).collect();
+        assert_eq!(hashes, vec![a, b]);
+        assert_eq!(
+            states(&session),
+            vec![TorrentState::Paused, TorrentState::Queued]
+        );
+        assert_eq!(session.torrent_limits(b).unwrap(), limits);
+        {
+            let state = session.shared.lock();
+            assert!(!state.torrents[&a].complete);
+            assert!(state.torrents[&b].complete);
+        }
+
+        session.shutdown().await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn completed_download_waits_for_a_seed_slot() {
+        let dir = state_dir("seed-slot");
+        let session = Session::start(SessionConfig {
+            seed: Some(SeedTarget::default()),
+            ..config(&dir)
+        })
+        .await
+        .unwrap();
+        let hash = session.add_torrent(&torrent_bytes("a"), &dir).unwrap();
+        let cancel = {
+            let mut state = session.shared.lock();
+            let entry = state.torrents.get_mut(&hash).unwrap();
+            let running = running();
+            let cancel = running.cancel.clone();
+            entry.running = Some(running);
+            cancel
+        };
+
+        session.shared.update_progress(&hash, progress(0));
+        assert!(!cancel.is_cancelled());
+        session.shared.update_progress(&hash, progress(1));
+        assert!(cancel.is_cancelled());
+
+        session.shared.stopped(&hash, Ok(()));
+        assert_eq!(states(&session), vec![TorrentState::Queued]);
+
+        session.shutdown().await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn completed_download_keeps_a_free_seed_slot() {
+        let dir = state_dir("seed-free");
+        let session = Session::start(SessionConfig {
+            seed: Some(SeedTarget::default()),
+            max_active_seeds: 1,
+            ..config(&dir)
+        })
+        .await
+        .unwrap();
+        let hash = session.add_torrent(&torrent_bytes("a"), &dir).unwrap();
+        let cancel = {
+            let mut state = session.shared.lock();
+            let entry = state.torrents.get_mut(&hash).unwrap();
+            let running = running();
+            let cancel = running.cancel.clone();
+            entry.running = Some(running);
+            cancel
+        };
+
+        session.shared.update_progress(&hash, progress(1));
+        assert!(!cancel.is_cancelled());
+        assert_eq!(states(&session), vec![TorrentState::Seeding]);
+
+        session.shared.stopped(&hash, Ok(()));
+        assert_eq!(states(&session), vec![TorrentState::Finished]);
+
+        session.shutdown().await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,19 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("Access out of bounds: {length} bytes at offset {offset}, content is {total} bytes")]
+    OutOfBounds {
+        offset: u64,
+        length: u64,
+        total: u64,
+    },
+    #[error("Invalid block: {length} bytes at offset {begin} of piece {index}")]
+    InvalidBlock { index: u32, begin: u32, length: u32 },
+    #[error("Content of skipped files is not stored: {length} bytes at offset {offset}")]
+    NotStored { offset: u64, length: u64 },
+}
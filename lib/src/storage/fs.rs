@@ -0,0 +1,424 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::torrent::v1::Torrent;
+
+use super::{spans, Storage, StorageError};
+
+/// Size of the zeroed buffer written when preallocating files
+const ZEROES_LEN: usize = 1024 * 1024;
+
+/// FileStorage stores the content in files on disk.
+/// Files are allocated sparsely by default, without writing any data.
+///
+/// Skipped files are never created. The parts of skipped files sharing a piece with a
+/// wanted file are stored one after the other in a partfile instead, the rest of their
+/// content isn't stored at all.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    files: Vec<(PathBuf, u64)>,
+    piece_length: u64,
+    preallocate: bool,
+    skipped: Vec<bool>,
+    partfile: Option<PathBuf>,
+    /// Content ranges stored in the partfile, in order, with their offset in the partfile
+    parts: Vec<(Range<u64>, u64)>,
+}
+
+impl FileStorage {
+    /// Create a storage over `files`, given as their path and length.
+    pub fn new(files: Vec<(PathBuf, u64)>, piece_length: u64) -> Self {
+        Self {
+            files,
+            piece_length,
+            preallocate: false,
+            skipped: vec![],
+            partfile: None,
+            parts: vec![],
+        }
+    }
+
+    /// Create a storage for the torrent content located under `root`.
+    /// Paths are sanitized by [`Torrent::content_files`].
+    pub fn from_torrent(torrent: &Torrent, root: &Path) -> Self {
+        let files = torrent
+            .content_files(root)
+            .into_iter()
+            .map(|(p, l)| (p, l.max(0) as u64))
+            .collect();
+
+        Self::new(files, torrent.info.piece_length.max(1) as u64)
+    }
+
+    /// Write zeroes when allocating files instead of extending them sparsely, so the disk
+    /// space is reserved up front.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Don't create the files flagged in `skipped`, given in the same order as the files.
+    /// Their parts sharing a piece with a wanted file are read from and written to
+    /// `partfile`, created on the first write.
+    pub fn skip_files(mut self, skipped: Vec<bool>, partfile: PathBuf) -> Self {
+        self.skipped = skipped;
+        self.partfile = Some(partfile);
+        self.parts = self.part_ranges();
+        self
+    }
+
+    /// Move the data kept for skipped files after their flags changed from `previous`.
+    /// Parts of files wanted again are copied into their file, which must be allocated, and
+    /// parts of newly skipped files into the partfile. As the partfile layout depends on the
+    /// skipped files, it is written again.
+    /// Parts that can't be read are left out, their pieces must be checked again.
+    pub fn migrate_parts(&self, previous: Vec<bool>) -> Result<(), StorageError> {
+        let partfile = match &self.partfile {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let old =
+            Self::new(self.files.clone(), self.piece_length).skip_files(previous, partfile.clone());
+        let mut tmp = partfile.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let new = Self {
+            partfile: Some(tmp.into()),
+            ..self.clone()
+        };
+        // Left over by an interrupted migration
+        let _ = fs::remove_file(new.partfile.as_ref().expect("partfile should be set"));
+
+        let ranges = old.parts.iter().chain(&new.parts).map(|(r, _)| r.clone());
+        for range in ranges {
+            let mut data = vec![0u8; (range.end - range.start) as usize];
+            if old.read_at(range.start, &mut data).is_err() {
+                continue;
+            }
+            for (path, file_offset, start, len) in new.locate(range.start, data.len(), true)? {
+                let in_partfile = new.partfile.as_ref() == Some(path);
+                let res = open_for_write(path, in_partfile).and_then(|mut f| {
+                    f.seek(SeekFrom::Start(file_offset))?;
+                    f.write_all(&data[start..start + len])
+                });
+                res.map_err(|e| StorageError::Io(path.clone(), e))?;
+            }
+        }
+
+        let tmp = new.partfile.expect("partfile should be set");
+        let res = match tmp.exists() {
+            true => fs::rename(&tmp, partfile),
+            false => fs::remove_file(partfile).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            }),
+        };
+        res.map_err(|e| StorageError::Io(partfile.clone(), e))
+    }
+
+    pub fn files(&self) -> &[(PathBuf, u64)] {
+        &self.files
+    }
+
+    fn is_skipped(&self, index: usize) -> bool {
+        self.partfile.is_some() && self.skipped.get(index).copied().unwrap_or_default()
+    }
+
+    /// Parts of skipped files sharing a piece with a wanted file, with their offset in the
+    /// partfile. Other pieces are never downloaded.
+    fn part_ranges(&self) -> Vec<(Range<u64>, u64)> {
+        let piece_length = self.piece_length.max(1);
+        let mut wanted = vec![false; self.piece_count().max(1)];
+        let mut offset = 0;
+        let mut ranges = vec![];
+        for (i, (_, length)) in self.files.iter().enumerate() {
+            let range = offset..offset + length;
+            offset += length;
+            if range.is_empty() {
+                continue;
+            }
+            let pieces = range.start / piece_length..=(range.end - 1) / piece_length;
+            match self.is_skipped(i) {
+                true => ranges.push((range, pieces)),
+                false => pieces.for_each(|p| wanted[p as usize] = true),
+            }
+        }
+
+        let mut parts = vec![];
+        let mut part_offset = 0;
+        for (range, pieces) in ranges {
+            let (first, last) = (*pieces.start(), *pieces.end());
+            let head = range.start..range.end.min((first + 1) * piece_length);
+            let tail = range.start.max(last * piece_length)..range.end;
+            let kept = match (wanted[first as usize], wanted[last as usize]) {
+                (true, _) if first == last => vec![range],
+                (true, true) => vec![head, tail],
+                (true, false) => vec![head],
+                (false, true) => vec![tail],
+                (false, false) => vec![],
+            };
+            for part in kept {
+                let length = part.end - part.start;
+                parts.push((part, part_offset));
+                part_offset += length;
+            }
+        }
+
+        parts
+    }
+
+    /// Where the content range `offset..offset + length` is stored, as
+    /// `(path, offset in the file, offset in the range, length)`.
+    /// Parts of skipped files missing from the partfile fail with
+    /// [`StorageError::NotStored`], unless `lenient` is set and they are left out.
+    fn locate(
+        &self,
+        offset: u64,
+        length: usize,
+        lenient: bool,
+    ) -> Result<Vec<(&PathBuf, u64, usize, usize)>, StorageError> {
+        let mut located = vec![];
+        for (i, file_offset, start, len) in self.spans(offset, length)? {
+            let partfile = match &self.partfile {
+                Some(v) if self.is_skipped(i) => v,
+                _ => {
+                    located.push((&self.files[i].0, file_offset, start, len));
+                    continue;
+                }
+            };
+
+            let (mut from, to) = (offset + start as u64, offset + (start + len) as u64);
+            for (part, part_offset) in &self.parts {
+                if part.end <= from || part.start >= to {
+                    continue;
+                }
+                if part.start > from && !lenient {
+                    break;
+                }
+                let from_part = from.max(part.start);
+                let end = part.end.min(to);
+                located.push((
+                    partfile,
+                    part_offset + from_part - part.start,
+                    (from_part - offset) as usize,
+                    (end - from_part) as usize,
+                ));
+                from = end;
+            }
+            if from < to && !lenient {
+                return Err(StorageError::NotStored {
+                    offset: from,
+                    length: to - from,
+                });
+            }
+        }
+
+        Ok(located)
+    }
+
+    fn spans(
+        &self,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<(usize, u64, usize, usize)>, StorageError> {
+        let total = self.total_length();
+        if offset + length as u64 > total {
+            return Err(StorageError::OutOfBounds {
+                offset,
+                length: length as u64,
+                total,
+            });
+        }
+
+        Ok(spans(
+            self.files.iter().map(|(_, l)| *l),
+            offset,
+            length as u64,
+        ))
+    }
+}
+
+impl Storage for FileStorage {
+    fn piece_length(&self) -> u64 {
+        self.piece_length
+    }
+
+    fn total_length(&self) -> u64 {
+        self.files.iter().map(|(_, l)| l).sum()
+    }
+
+    fn allocate(&self) -> Result<(), StorageError> {
+        for (i, (path, length)) in self.files.iter().enumerate() {
+            if self.is_skipped(i) {
+                continue;
+            }
+            allocate_file(path, *length, self.preallocate)
+                .map_err(|e| StorageError::Io(path.clone(), e))?;
+        }
+
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        for (path, file_offset, start, len) in self.locate(offset, buf.len(), false)? {
+            let res = File::open(path).and_then(|mut f| {
+                f.seek(SeekFrom::Start(file_offset))?;
+                f.read_exact(&mut buf[start..start + len])
+            });
+            res.map_err(|e| StorageError::Io(path.clone(), e))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), StorageError> {
+        for (path, file_offset, start, len) in self.locate(offset, data.len(), false)? {
+            let in_partfile = self.partfile.as_ref() == Some(path);
+            let res = open_for_write(path, in_partfile).and_then(|mut f| {
+                f.seek(SeekFrom::Start(file_offset))?;
+                f.write_all(&data[start..start + len])
+            });
+            res.map_err(|e| StorageError::Io(path.clone(), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Open a file for writing. Missing files and directories are only created when `create`
+/// is set, as content files are expected to be allocated.
+fn open_for_write(path: &Path, create: bool) -> io::Result<File> {
+    if create {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create(create)
+        .truncate(false)
+        .open(path)
+}
+
+/// Create the file and its directories, extending it to `length` if shorter
+fn allocate_file(path: &Path, length: u64, preallocate: bool) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let current = file.metadata()?.len();
+    if current >= length {
+        return Ok(());
+    }
+    if !preallocate {
+        return file.set_len(length);
+    }
+
+    file.seek(SeekFrom::Start(current))?;
+    let zeroes = vec![0u8; ZEROES_LEN];
+    let mut remaining = length - current;
+    while remaining > 0 {
+        let n = remaining.min(ZEROES_LEN as u64) as usize;
+        file.write_all(&zeroes[..n])?;
+        remaining -= n as u64;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// `a` and `c` are wanted, `b` is skipped and shares pieces `0` and `2` with them
+    fn layout(name: &str) -> (PathBuf, Vec<(PathBuf, u64)>) {
+        let root = env::temp_dir().join(format!("brs-fs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let files = vec![
+            (root.join("a"), 10),
+            (root.join("b"), 30),
+            (root.join("c"), 10),
+        ];
+        (root, files)
+    }
+
+    fn content() -> Vec<u8> {
+        (0..50).collect()
+    }
+
+    fn skipping(files: &[(PathBuf, u64)], root: &Path) -> FileStorage {
+        FileStorage::new(files.to_vec(), 16)
+            .skip_files(vec![false, true, false], root.join("parts"))
+    }
+
+    #[test]
+    fn partfile_only_keeps_shared_pieces() {
+        let (root, files) = layout("parts");
+        let storage = skipping(&files, &root);
+        let content = content();
+        storage.allocate().unwrap();
+        for index in [0, 2, 3] {
+            let start = index as usize * 16;
+            let end = (start + 16).min(content.len());
+            storage.write_block(index, 0, &content[start..end]).unwrap();
+        }
+
+        assert!(!files[1].0.exists());
+        // 6 bytes of `b` in piece 0 and 8 bytes in piece 2
+        assert_eq!(fs::metadata(root.join("parts")).unwrap().len(), 14);
+        assert_eq!(storage.read_piece(0).unwrap(), &content[0..16]);
+        assert_eq!(storage.read_piece(2).unwrap(), &content[32..48]);
+        assert!(matches!(
+            storage.read_piece(1),
+            Err(StorageError::NotStored { .. })
+        ));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn migrate_parts_to_wanted_file() {
+        let (root, files) = layout("migrate");
+        let skipping = skipping(&files, &root);
+        let content = content();
+        skipping.allocate().unwrap();
+        skipping.write_block(0, 0, &content[0..16]).unwrap();
+        skipping.write_block(2, 0, &content[32..48]).unwrap();
+
+        let wanted =
+            FileStorage::new(files.clone(), 16).skip_files(vec![false; 3], root.join("parts"));
+        wanted.allocate().unwrap();
+        wanted.migrate_parts(vec![false, true, false]).unwrap();
+
+        assert!(!root.join("parts").exists());
+        assert_eq!(wanted.read_piece(0).unwrap(), &content[0..16]);
+        assert_eq!(wanted.read_piece(2).unwrap(), &content[32..48]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn migrate_parts_from_wanted_file() {
+        let (root, files) = layout("skip");
+        let wanted = FileStorage::new(files.clone(), 16);
+        let content = content();
+        wanted.allocate().unwrap();
+        wanted.write_at(0, &content).unwrap();
+
+        let skipping = skipping(&files, &root);
+        skipping.migrate_parts(vec![false; 3]).unwrap();
+
+        assert_eq!(fs::metadata(root.join("parts")).unwrap().len(), 14);
+        assert_eq!(skipping.read_piece(0).unwrap(), &content[0..16]);
+        assert_eq!(skipping.read_piece(2).unwrap(), &content[32..48]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::RwLock;
+
+use super::{Storage, StorageError};
+
+/// MemoryStorage keeps the whole content in memory.
+/// Useful for tests, or to serve small torrents without touching the disk.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    data: RwLock<Vec<u8>>,
+    piece_length: u64,
+}
+
+impl MemoryStorage {
+    /// Create an empty content of `total_length` bytes, filled with zeroes
+    pub fn new(total_length: u64, piece_length: u64) -> Self {
+        Self::with_data(vec![0; total_length as usize], piece_length)
+    }
+
+    /// Create a storage holding `data`
+    pub fn with_data(data: Vec<u8>, piece_length: u64) -> Self {
+        Self {
+            data: RwLock::new(data),
+            piece_length,
+        }
+    }
+
+    /// Copy of the whole content
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.read().expect("storage poisoned").clone()
+    }
+
+    fn check(&self, offset: u64, length: usize) -> Result<(), StorageError> {
+        let total = self.total_length();
+        if offset + length as u64 > total {
+            return Err(StorageError::OutOfBounds {
+                offset,
+                length: length as u64,
+                total,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn piece_length(&self) -> u64 {
+        self.piece_length
+    }
+
+    fn total_length(&self) -> u64 {
+        self.data.read().expect("storage poisoned").len() as u64
+    }
+
+    fn allocate(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.check(offset, buf.len())?;
+        let data = self.data.read().expect("storage poisoned");
+        buf.copy_from_slice(&data[offset as usize..offset as usize + buf.len()]);
+
+        Ok(())
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), StorageError> {
+        self.check(offset, data.len())?;
+        let mut content = self.data.write().expect("storage poisoned");
+        content[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Storage of the torrent content.
+//!
+//! Pieces are laid out as one continuous byte range over the files, in the order of
+//! `TorrentInfo.files`. A [`Storage`] maps this range onto its backend, so the download
+//! engine, seeding and verification all read and write through the same mapping.
+
+mod errors;
+mod fs;
+mod memory;
+
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+pub use errors::StorageError;
+pub use fs::FileStorage;
+pub use memory::MemoryStorage;
+
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Size of each piece, the last one being usually shorter
+    fn piece_length(&self) -> u64;
+
+    /// Size of the whole content
+    fn total_length(&self) -> u64;
+
+    /// Create the content so it can be written. Existing data is kept.
+    fn allocate(&self) -> Result<(), StorageError>;
+
+    /// Fill `buf` with the content found at `offset`
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError>;
+
+    /// Write `data` at `offset` of the content
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), StorageError>;
+
+    /// Number of pieces, `0` when the piece length is `0`
+    fn piece_count(&self) -> usize {
+        match self.piece_length() {
+            0 => 0,
+            n => self.total_length().div_ceil(n) as usize,
+        }
+    }
+
+    /// Size of the piece at `index`, `0` if it doesn't exist
+    fn piece_size(&self, index: u32) -> u32 {
+        let start = index as u64 * self.piece_length();
+        self.total_length()
+            .saturating_sub(start)
+            .min(self.piece_length()) as u32
+    }
+
+    /// Read `length` bytes at `begin` of the piece at `index`
+    fn read_block(&self, index: u32, begin: u32, length: u32) -> Result<Vec<u8>, StorageError> {
+        self.check_block(index, begin, length)?;
+        let mut data = vec![0u8; length as usize];
+        self.read_at(index as u64 * self.piece_length() + begin as u64, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// Write `data` at `begin` of the piece at `index`
+    fn write_block(&self, index: u32, begin: u32, data: &[u8]) -> Result<(), StorageError> {
+        self.check_block(index, begin, data.len() as u32)?;
+        self.write_at(index as u64 * self.piece_length() + begin as u64, data)
+    }
+
+    fn read_piece(&self, index: u32) -> Result<Vec<u8>, StorageError> {
+        self.read_block(index, 0, self.piece_size(index))
+    }
+
+    fn check_block(&self, index: u32, begin: u32, length: u32) -> Result<(), StorageError> {
+        if begin as u64 + length as u64 > self.piece_size(index) as u64 {
+            return Err(StorageError::InvalidBlock {
+                index,
+                begin,
+                length,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Turn a `/` separated torrent path into a relative path safe to join to a directory.
+/// Empty, `.` and `..` components are dropped, as well as root and drive prefixes.
+pub fn sanitize_path(path: &str) -> PathBuf {
+    path.split(['/', '\\'])
+        .filter(|c| !c.is_empty())
+        .flat_map(|c| Path::new(c).components())
+        .filter_map(|c| match c {
+            Component::Normal(v) => Some(v),
+            Component::Prefix(_)
+            | Component::RootDir
+            | Component::CurDir
+            | Component::ParentDir => None,
+        })
+        .collect()
+}
+
+/// Spans of the content range `offset..offset + length` over files of the given lengths,
+/// as `(file index, offset in the file, offset in the range, span length)`.
+pub(crate) fn spans(
+    lengths: impl IntoIterator<Item = u64>,
+    offset: u64,
+    length: u64,
+) -> Vec<(usize, u64, usize, usize)> {
+    let end = offset + length;
+    let mut spans = vec![];
+    let mut start = 0;
+    for (i, file_length) in lengths.into_iter().enumerate() {
+        let file_end = start + file_length;
+        if file_end > offset && start < end {
+            let from = offset.max(start);
+            let to = end.min(file_end);
+            spans.push((
+                i,
+                from - start,
+                (from - offset) as usize,
+                (to - from) as usize,
+            ));
+        }
+        if file_end >= end {
+            break;
+        }
+        start = file_end;
+    }
+
+    spans
+}
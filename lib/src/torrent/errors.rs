@@ -1,18 +1,59 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
+use crate::storage::StorageError;
+
+use super::InfoHash;
+
 #[derive(Error, Debug)]
 pub enum TorrentError {
     #[error("Failed to parse torrent file: {0}")]
     ParseTorrent(bendy::serde::Error),
     #[error("Failed to encode info dictionnary: {0}")]
     EncodeInfo(bendy::serde::Error),
+    #[error("Failed to encode torrent file: {0}")]
+    EncodeTorrent(bendy::serde::Error),
     #[error("Failed to read torrent file: {0}")]
     ReadTorrent(#[from] io::Error),
+    #[error("Failed to read torrent content at {0}: {1}")]
+    ReadContent(PathBuf, io::Error),
+    #[error("Failed to read torrent content: {0}")]
+    Storage(#[from] StorageError),
+    #[error("Torrent content path is not valid UTF-8: {0}")]
+    InvalidPath(PathBuf),
+    #[error("Torrent content is empty: {0}")]
+    EmptyContent(PathBuf),
+    #[error("Invalid piece length {0}: must be a power of two of at least 16 KiB")]
+    InvalidPieceLength(i64),
+    #[error("Hashing was cancelled")]
+    Cancelled,
+    #[error("Invalid pieces length {0}: must be a multiple of 20 bytes")]
+    InvalidPieces(usize),
+    #[error("Invalid info hash: {0}")]
+    InvalidInfoHash(String),
 }
 
 #[derive(Error, Debug)]
 pub enum TcpError {
-
+    #[error("Peer connection error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Peer did not respond in time")]
+    Timeout,
+    #[error("Peer closed the connection")]
+    ConnectionClosed,
+    #[error("Peer speaks an unsupported protocol: {0}")]
+    InvalidProtocol(String),
+    #[error("Peer serves another torrent: expected info hash {expected}, found {found}")]
+    InfoHashMismatch { expected: InfoHash, found: InfoHash },
+    #[error("Peer asked for an unknown torrent: {0}")]
+    UnknownInfoHash(InfoHash),
+    #[error("Peer message is too large: {0} bytes")]
+    MessageTooLarge(u32),
+    #[error("Invalid length {len} for peer message {id}")]
+    InvalidMessageLength { id: u8, len: usize },
+    #[error("Unknown peer message {0}")]
+    UnknownMessage(u8),
+    #[error("Requested block is too large: {0} bytes")]
+    BlockTooLarge(u32),
 }
@@ -0,0 +1,142 @@
+use std::{fmt, str::FromStr};
+
+use data_encoding::BASE32;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::errors::TorrentError;
+
+/// InfoHash is the SHA1 hash of the bencoded `info` dictionary of a torrent.
+/// It uniquely identifies a torrent in a swarm.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoHash(pub [u8; 20]);
+
+impl InfoHash {
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Lowercase hexadecimal form (40 characters)
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Uppercase base32 form (32 characters), as found in some magnet links
+    pub fn to_base32(&self) -> String {
+        BASE32.encode(&self.0)
+    }
+
+    /// Percent-encoded form, byte by byte, as expected by HTTP trackers
+    pub fn urlencode(&self) -> String {
+        urlencode(&self.0)
+    }
+
+    pub fn from_hex(v: &str) -> Result<Self, TorrentError> {
+        let mut hash = [0u8; 20];
+        hex::decode_to_slice(v, &mut hash)
+            .map_err(|_| TorrentError::InvalidInfoHash(v.to_string()))?;
+
+        Ok(Self(hash))
+    }
+
+    pub fn from_base32(v: &str) -> Result<Self, TorrentError> {
+        let bytes = BASE32
+            .decode(v.to_ascii_uppercase().as_bytes())
+            .map_err(|_| TorrentError::InvalidInfoHash(v.to_string()))?;
+
+        Self::try_from(bytes.as_slice())
+    }
+}
+
+impl From<[u8; 20]> for InfoHash {
+    fn from(v: [u8; 20]) -> Self {
+        Self(v)
+    }
+}
+
+impl TryFrom<&[u8]> for InfoHash {
+    type Error = TorrentError;
+
+    fn try_from(v: &[u8]) -> Result<Self, Self::Error> {
+        Ok(Self(v.try_into().map_err(|_| {
+            TorrentError::InvalidInfoHash(hex::encode(v))
+        })?))
+    }
+}
+
+impl FromStr for InfoHash {
+    type Err = TorrentError;
+
+    /// Parse either the hexadecimal (40 characters) or the base32 (32 characters) form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.len() {
+            40 => Self::from_hex(s),
+            32 => Self::from_base32(s),
+            _ => Err(TorrentError::InvalidInfoHash(s.to_string())),
+        }
+    }
+}
+
+impl AsRef<[u8]> for InfoHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl fmt::Debug for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InfoHash({})", self.to_hex())
+    }
+}
+
+impl Serialize for InfoHash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for InfoHash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = InfoHash;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a 20 bytes SHA1 hash")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                InfoHash::try_from(v).map_err(|_| E::invalid_length(v.len(), &self))
+            }
+        }
+
+        deserializer.deserialize_bytes(Visitor)
+    }
+}
+
+/// Percent-encode every byte outside of the URL unreserved set (`RFC 3986`).
+pub(crate) fn urlencode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(*b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    out
+}
@@ -1,3 +1,7 @@
 pub mod errors;
+mod info_hash;
 pub mod v1;
 pub mod v2;
+
+pub use info_hash::InfoHash;
+pub(crate) use info_hash::urlencode;
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// AnnounceList holds the trackers tiers defined by
+/// [BEP 0012](https://www.bittorrent.org/beps/bep_0012.html).
+/// Each tier is a list of tracker URLs. Tiers are tried in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct AnnounceList(pub Vec<Vec<String>>);
+
+impl AnnounceList {
+    /// Put each tracker in its own tier
+    pub fn from_trackers(trackers: Vec<String>) -> Self {
+        Self(trackers.into_iter().map(|t| vec![t]).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(Vec::is_empty)
+    }
+
+    pub fn tiers(&self) -> &[Vec<String>] {
+        &self.0
+    }
+
+    /// Every tracker URL, tier after tier
+    pub fn trackers(&self) -> impl Iterator<Item = &String> {
+        self.0.iter().flatten()
+    }
+}
+
+impl<'de> Deserialize<'de> for AnnounceList {
+    /// Some torrents in the wild use a flat list of URLs instead of a list of tiers.
+    /// In that case, every URL is considered as its own tier.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Tier {
+            List(Vec<String>),
+            Single(String),
+        }
+
+        let tiers = Vec::<Tier>::deserialize(deserializer)?
+            .into_iter()
+            .map(|t| match t {
+                Tier::List(v) => v,
+                Tier::Single(v) => vec![v],
+            })
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        Ok(Self(tiers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiers(list: &[&[&str]]) -> AnnounceList {
+        AnnounceList(
+            list.iter()
+                .map(|t| t.iter().map(|u| u.to_string()).collect())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn decode_tiers() {
+        let list: AnnounceList = bendy::serde::from_bytes(b"ll1:a1:bel1:cee").unwrap();
+        assert_eq!(list, tiers(&[&["a", "b"], &["c"]]));
+    }
+
+    #[test]
+    fn decode_flat_list() {
+        let list: AnnounceList = bendy::serde::from_bytes(b"l1:a1:be").unwrap();
+        assert_eq!(list, tiers(&[&["a"], &["b"]]));
+
+        let mixed: AnnounceList = bendy::serde::from_bytes(b"l1:al1:b1:cee").unwrap();
+        assert_eq!(mixed, tiers(&[&["a"], &["b", "c"]]));
+    }
+
+    #[test]
+    fn drop_empty_tiers() {
+        let list: AnnounceList = bendy::serde::from_bytes(b"llel1:aelee").unwrap();
+        assert_eq!(list, tiers(&[&["a"]]));
+        assert!(AnnounceList::default().is_empty());
+        assert!(tiers(&[&[]]).is_empty());
+    }
+
+    #[test]
+    fn trackers_in_tier_order() {
+        let list = AnnounceList::from_trackers(vec!["a".into(), "b".into()]);
+        assert_eq!(list, tiers(&[&["a"], &["b"]]));
+
+        let list = tiers(&[&["a", "b"], &["c"]]);
+        assert_eq!(list.trackers().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(bendy::serde::to_bytes(&list).unwrap(), b"ll1:a1:bel1:cee");
+    }
+}
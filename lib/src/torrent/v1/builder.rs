@@ -0,0 +1,270 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::torrent::errors::TorrentError;
+
+use super::{
+    hasher::{HashProgress, PieceHasher},
+    AnnounceList, RootAdditionalFields, Torrent, TorrentFile, TorrentInfo, TorrentInfoAdditionalFields,
+};
+
+/// Smallest piece length accepted by most clients (16 KiB).
+const MIN_PIECE_LENGTH: i64 = 16 * 1024;
+/// Biggest piece length picked automatically (16 MiB).
+const MAX_PIECE_LENGTH: i64 = 16 * 1024 * 1024;
+/// Number of pieces aimed for when the piece length is picked automatically.
+const TARGET_PIECE_COUNT: i64 = 1500;
+
+/// TorrentBuilder creates a [`Torrent`] from a file or a directory available on disk.
+///
+/// ```no_run
+/// use brs::torrent::v1::TorrentBuilder;
+///
+/// let torrent = TorrentBuilder::new("./dataset")
+///     .trackers(vec!["http://tracker.example.org/announce".to_string()])
+///     .private(true)
+///     .build()
+///     .unwrap();
+/// std::fs::write("dataset.torrent", torrent.to_bytes().unwrap()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TorrentBuilder {
+    path: PathBuf,
+    name: Option<String>,
+    trackers: Vec<Vec<String>>,
+    piece_length: Option<i64>,
+    private: bool,
+    comment: String,
+    created_by: String,
+    creation_date: Option<DateTime<Utc>>,
+    url_list: Vec<String>,
+    threads: Option<usize>,
+}
+
+impl TorrentBuilder {
+    /// Create a new builder for the file or directory located at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            name: None,
+            trackers: vec![],
+            piece_length: None,
+            private: false,
+            comment: String::new(),
+            created_by: format!("brs/{}", env!("CARGO_PKG_VERSION")),
+            creation_date: None,
+            url_list: vec![],
+            threads: None,
+        }
+    }
+
+    /// Override the torrent name. Defaults to the file or directory name.
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Trackers announced in the torrent, each one in its own tier.
+    /// The first one is used as the main tracker.
+    pub fn trackers(mut self, trackers: Vec<String>) -> Self {
+        self.trackers = AnnounceList::from_trackers(trackers).0;
+        self
+    }
+
+    /// Trackers announced in the torrent, grouped by tier (`BEP 0012`).
+    /// The first tracker of the first tier is used as the main tracker.
+    pub fn tracker_tiers(mut self, tiers: Vec<Vec<String>>) -> Self {
+        self.trackers = tiers.into_iter().filter(|t| !t.is_empty()).collect();
+        self
+    }
+
+    /// Size of each piece in bytes. Picked from the content size when not set.
+    pub fn piece_length(mut self, piece_length: i64) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    pub fn comment(mut self, comment: String) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    pub fn created_by(mut self, created_by: String) -> Self {
+        self.created_by = created_by;
+        self
+    }
+
+    /// Creation date written in the torrent. Defaults to the build time.
+    pub fn creation_date(mut self, creation_date: DateTime<Utc>) -> Self {
+        self.creation_date = Some(creation_date);
+        self
+    }
+
+    /// Web seeds (`BEP 0019`) serving the torrent content.
+    pub fn url_list(mut self, url_list: Vec<String>) -> Self {
+        self.url_list = url_list;
+        self
+    }
+
+    /// Number of threads hashing pieces. Defaults to the available parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Walk the content, hash every pieces and create the torrent.
+    pub fn build(self) -> Result<Torrent<'static>, TorrentError> {
+        self.build_with_progress(|_| {})
+    }
+
+    /// Same as [`TorrentBuilder::build`], calling `progress` after each hashed piece.
+    pub fn build_with_progress<F>(self, progress: F) -> Result<Torrent<'static>, TorrentError>
+    where
+        F: FnMut(HashProgress),
+    {
+        let metadata = fs::metadata(&self.path)
+            .map_err(|e| TorrentError::ReadContent(self.path.clone(), e))?;
+        let name = match self.name {
+            Some(v) => v,
+            None => file_name(&self.path)?,
+        };
+
+        let (length, files, sources) = if metadata.is_dir() {
+            let mut sources = vec![];
+            walk_dir(&self.path, &mut sources)?;
+            let files = sources
+                .iter()
+                .map(|(p, l)| {
+                    Ok(TorrentFile {
+                        path: relative_path(&self.path, p)?,
+                        length: *l,
+                    })
+                })
+                .collect::<Result<Vec<_>, TorrentError>>()?;
+            (0, files, sources)
+        } else {
+            let length = metadata.len() as i64;
+            (length, vec![], vec![(self.path.clone(), length)])
+        };
+
+        let total: i64 = sources.iter().map(|(_, l)| l).sum();
+        if total == 0 {
+            return Err(TorrentError::EmptyContent(self.path));
+        }
+
+        let piece_length = match self.piece_length {
+            Some(v) if v < MIN_PIECE_LENGTH || !(v as u64).is_power_of_two() => {
+                return Err(TorrentError::InvalidPieceLength(v))
+            }
+            Some(v) => v,
+            None => auto_piece_length(total),
+        };
+        let sources = sources.into_iter().map(|(p, l)| (p, l as u64)).collect();
+        let mut hasher = PieceHasher::new(sources, piece_length as u64);
+        if let Some(v) = self.threads {
+            hasher = hasher.threads(v);
+        }
+        let pieces = hasher
+            .hash(progress)?
+            .into_iter()
+            .map(|h| h.expect("pieces are complete when missing files are rejected"))
+            .collect();
+
+        let announce_list = AnnounceList(self.trackers);
+        let announce = announce_list.trackers().next().cloned().unwrap_or_default();
+        Ok(Torrent {
+            announce,
+            announce_list: if announce_list.trackers().count() > 1 {
+                announce_list
+            } else {
+                AnnounceList::default()
+            },
+            info: TorrentInfo {
+                name,
+                piece_length,
+                pieces,
+                length,
+                files,
+                additional_fields: TorrentInfoAdditionalFields {
+                    private: self.private,
+                    ..Default::default()
+                },
+            },
+            additional_fields: RootAdditionalFields {
+                created_by: self.created_by,
+                creation_date: self.creation_date.unwrap_or_else(Utc::now),
+                comment: self.comment,
+                url_list: self.url_list,
+                ..Default::default()
+            },
+            raw_info: None,
+        })
+    }
+}
+
+/// Pick a power of two piece length giving roughly `TARGET_PIECE_COUNT` pieces.
+fn auto_piece_length(total_length: i64) -> i64 {
+    let mut piece_length = MIN_PIECE_LENGTH;
+    while piece_length < MAX_PIECE_LENGTH && total_length / piece_length > TARGET_PIECE_COUNT {
+        piece_length *= 2;
+    }
+
+    piece_length
+}
+
+fn file_name(path: &Path) -> Result<String, TorrentError> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| TorrentError::ReadContent(path.to_path_buf(), e))?;
+    canonical
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(String::from)
+        .ok_or_else(|| TorrentError::InvalidPath(path.to_path_buf()))
+}
+
+fn relative_path(root: &Path, path: &Path) -> Result<String, TorrentError> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut parts = vec![];
+    for c in relative.components() {
+        parts.push(
+            c.as_os_str()
+                .to_str()
+                .ok_or_else(|| TorrentError::InvalidPath(path.to_path_buf()))?,
+        );
+    }
+
+    Ok(parts.join("/"))
+}
+
+/// Recursively list files inside `dir`, sorted by path so the piece layout is reproducible.
+/// Symbolic links are skipped, so a link cycle can't make the walk recurse forever.
+fn walk_dir(dir: &Path, files: &mut Vec<(PathBuf, i64)>) -> Result<(), TorrentError> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| TorrentError::ReadContent(dir.to_path_buf(), e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TorrentError::ReadContent(dir.to_path_buf(), e))?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| TorrentError::ReadContent(path.clone(), e))?;
+        if metadata.is_dir() {
+            walk_dir(&path, files)?;
+        } else if metadata.is_file() {
+            files.push((path, metadata.len() as i64));
+        }
+    }
+
+    Ok(())
+}
@@ -10,8 +10,11 @@ impl std::fmt::Display for Torrent<'_> {
         writeln!(f, "  Tracker: {}", self.announce)?;
         if !self.announce_list.is_empty() {
             writeln!(f, "  Additional trackers:")?;
-            for i in &self.announce_list {
-                writeln!(f, "  - {i}")?;
+            for (i, tier) in self.announce_list.tiers().iter().enumerate() {
+                writeln!(f, "    Tier {}:", i + 1)?;
+                for t in tier {
+                    writeln!(f, "    - {t}")?;
+                }
             }
         }
         write_optional!(
@@ -53,7 +56,7 @@ impl std::fmt::Display for Torrent<'_> {
         writeln!(f, "\nTORRENT INFORMATION\n")?;
         writeln!(f, "  Name: {}", self.info.name)?;
         let hash = match self.calc_hash() {
-            Ok(v) => hex::encode(v),
+            Ok(v) => v.to_hex(),
             Err(e) => format!("Failed to calculate hash for torrent: {e}"),
         };
         writeln!(f, "  Hash: {hash}",)?;
@@ -0,0 +1,327 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    storage::{FileStorage, Storage},
+    torrent::errors::TorrentError,
+};
+
+/// Number of pieces waiting to be hashed per worker. Bounds the memory used by the reader.
+const QUEUE_DEPTH_PER_WORKER: usize = 2;
+
+/// Progress reported after each hashed piece.
+#[derive(Debug, Clone, Copy)]
+pub struct HashProgress {
+    /// Index of the piece that was just hashed
+    pub piece: usize,
+    /// Number of pieces hashed so far
+    pub hashed_pieces: usize,
+    /// Total number of pieces
+    pub total_pieces: usize,
+    /// Number of bytes hashed so far
+    pub hashed_bytes: u64,
+    /// Total number of bytes to hash
+    pub total_bytes: u64,
+}
+
+/// Handle used to stop a running [`PieceHasher`] from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// PieceHasher computes the SHA1 hash of every piece of a [`Storage`].
+///
+/// Pieces are read sequentially, while they are hashed in parallel on a pool of worker
+/// threads.
+#[derive(Debug, Clone)]
+pub struct PieceHasher {
+    storage: Arc<dyn Storage>,
+    threads: usize,
+    allow_missing: bool,
+    cancel: CancelHandle,
+}
+
+struct PieceData {
+    index: usize,
+    data: Vec<u8>,
+    /// Part of the piece could not be read from disk
+    incomplete: bool,
+}
+
+impl PieceHasher {
+    /// Create a hasher over `files`, given as their path and expected length.
+    pub fn new(files: Vec<(PathBuf, u64)>, piece_length: u64) -> Self {
+        Self::from_storage(Arc::new(FileStorage::new(files, piece_length)))
+    }
+
+    /// Create a hasher over the content of `storage`
+    pub fn from_storage(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            allow_missing: false,
+            cancel: CancelHandle::default(),
+        }
+    }
+
+    /// Number of worker threads hashing pieces. Defaults to the available parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// When set, missing or truncated files don't abort the hashing. Pieces that can't be
+    /// read are reported as `None` instead. Used when checking existing data.
+    pub fn allow_missing(mut self, allow_missing: bool) -> Self {
+        self.allow_missing = allow_missing;
+        self
+    }
+
+    pub fn with_cancel(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    pub fn total_length(&self) -> u64 {
+        self.storage.total_length()
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.storage.piece_count()
+    }
+
+    /// Hash every pieces. `progress` is called from the current thread after each piece.
+    pub fn hash<F>(&self, mut progress: F) -> Result<Vec<Option<[u8; 20]>>, TorrentError>
+    where
+        F: FnMut(HashProgress),
+    {
+        let total_pieces = self.piece_count();
+        let total_bytes = self.total_length();
+        let mut hashes = vec![None; total_pieces];
+        if total_pieces == 0 {
+            return Ok(hashes);
+        }
+
+        thread::scope(|s| {
+            let (piece_tx, piece_rx) =
+                mpsc::sync_channel::<PieceData>(self.threads * QUEUE_DEPTH_PER_WORKER);
+            let piece_rx = Arc::new(Mutex::new(piece_rx));
+            let (hash_tx, hash_rx) = mpsc::channel::<(usize, usize, Option<[u8; 20]>)>();
+
+            let reader = s.spawn(move || self.read_pieces(piece_tx));
+            for _ in 0..self.threads {
+                let piece_rx = Arc::clone(&piece_rx);
+                let hash_tx = hash_tx.clone();
+                s.spawn(move || loop {
+                    let piece = match piece_rx.lock().expect("reader poisoned").recv() {
+                        Ok(v) => v,
+                        Err(_) => break,
+                    };
+                    let hash = (!piece.incomplete).then(|| Sha1::digest(&piece.data).into());
+                    if hash_tx.send((piece.index, piece.data.len(), hash)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(hash_tx);
+
+            let mut hashed_bytes = 0;
+            for (hashed, (index, length, hash)) in hash_rx.into_iter().enumerate() {
+                hashes[index] = hash;
+                hashed_bytes += length as u64;
+                progress(HashProgress {
+                    piece: index,
+                    hashed_pieces: hashed + 1,
+                    total_pieces,
+                    hashed_bytes,
+                    total_bytes,
+                });
+            }
+
+            reader.join().expect("piece reader panicked")
+        })?;
+
+        if self.cancel.is_cancelled() {
+            return Err(TorrentError::Cancelled);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Read pieces one after the other from the storage.
+    fn read_pieces(&self, tx: mpsc::SyncSender<PieceData>) -> Result<(), TorrentError> {
+        for index in 0..self.piece_count() {
+            if self.cancel.is_cancelled() {
+                return Ok(());
+            }
+
+            let (data, incomplete) = match self.storage.read_piece(index as u32) {
+                Ok(v) => (v, false),
+                Err(_) if self.allow_missing => (
+                    vec![0; self.storage.piece_size(index as u32) as usize],
+                    true,
+                ),
+                Err(e) => return Err(e.into()),
+            };
+            let piece = PieceData {
+                index,
+                data,
+                incomplete,
+            };
+            if tx.send(piece).is_err() {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    const PIECE_LEN: u64 = 16;
+
+    fn content(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Hashes of `data` computed one piece after the other
+    fn expected(data: &[u8]) -> Vec<Option<[u8; 20]>> {
+        data.chunks(PIECE_LEN as usize)
+            .map(|c| Some(Sha1::digest(c).into()))
+            .collect()
+    }
+
+    /// Write `data` split in files of the given lengths
+    fn write_files(dir: &Path, data: &[u8], lengths: &[u64]) -> Vec<(PathBuf, u64)> {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let mut offset = 0;
+        lengths
+            .iter()
+            .enumerate()
+            .map(|(i, length)| {
+                let path = dir.join(i.to_string());
+                fs::write(&path, &data[offset..offset + *length as usize]).unwrap();
+                offset += *length as usize;
+                (path, *length)
+            })
+            .collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("brs-hasher-{name}-{}", std::process::id()))
+    }
+
+    fn memory_hasher(data: &[u8]) -> PieceHasher {
+        PieceHasher::from_storage(Arc::new(MemoryStorage::with_data(data.to_vec(), PIECE_LEN)))
+    }
+
+    #[test]
+    fn pieces_span_files() {
+        let dir = temp_dir("span");
+        let lengths = [5, 40, 0, 23];
+        let data = content(68);
+        let files = write_files(&dir, &data, &lengths);
+
+        for threads in [1, 4] {
+            let hashes = PieceHasher::new(files.clone(), PIECE_LEN)
+                .threads(threads)
+                .hash(|_| {})
+                .unwrap();
+            assert_eq!(hashes, expected(&data));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn last_piece_is_shorter() {
+        let data = content(PIECE_LEN as usize * 3 + 1);
+        let hasher = memory_hasher(&data);
+
+        assert_eq!(hasher.piece_count(), 4);
+        assert_eq!(hasher.hash(|_| {}).unwrap(), expected(&data));
+    }
+
+    #[test]
+    fn progress_is_reported_for_every_piece() {
+        let data = content(100);
+        let mut seen = vec![];
+        memory_hasher(&data)
+            .threads(3)
+            .hash(|p| seen.push(p))
+            .unwrap();
+
+        assert_eq!(seen.len(), 7);
+        let mut pieces: Vec<_> = seen.iter().map(|p| p.piece).collect();
+        pieces.sort();
+        assert_eq!(pieces, (0..7).collect::<Vec<_>>());
+        for (i, p) in seen.iter().enumerate() {
+            assert_eq!(p.hashed_pieces, i + 1);
+            assert_eq!((p.total_pieces, p.total_bytes), (7, 100));
+        }
+        assert_eq!(seen.last().unwrap().hashed_bytes, 100);
+    }
+
+    #[test]
+    fn cancel_stops_hashing() {
+        let hasher = memory_hasher(&content(1 << 20)).threads(2);
+        let cancel = hasher.cancel_handle();
+
+        let mut hashed = 0;
+        let res = hasher.hash(|_| {
+            hashed += 1;
+            cancel.cancel();
+        });
+        assert!(matches!(res, Err(TorrentError::Cancelled)));
+        assert!(hashed < hasher.piece_count());
+
+        // Already cancelled
+        assert!(matches!(hasher.hash(|_| {}), Err(TorrentError::Cancelled)));
+    }
+
+    #[test]
+    fn missing_files_are_reported_as_none() {
+        let dir = temp_dir("missing");
+        let data = content(48);
+        let mut files = write_files(&dir, &data, &[20, 28]);
+        files[1].0 = dir.join("missing");
+
+        assert!(PieceHasher::new(files.clone(), PIECE_LEN)
+            .hash(|_| {})
+            .is_err());
+        let hashes = PieceHasher::new(files, PIECE_LEN)
+            .allow_missing(true)
+            .hash(|_| {})
+            .unwrap();
+        assert_eq!(hashes, [expected(&data)[0], None, None]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,13 +1,36 @@
+use std::path::{Path, PathBuf};
+
 use bendy::serde::{from_bytes, to_bytes};
 use sha1::{Digest, Sha1};
 
-use crate::{extension_parsing, torrent::errors::TorrentError};
+use crate::{
+    extension_parsing,
+    magnet::Magnet,
+    storage::sanitize_path,
+    torrent::{errors::TorrentError, InfoHash},
+};
 
-use super::Torrent;
+use super::{parsing_modules, Torrent};
 
 impl Torrent<'_> {
-    pub fn parse_bytes<'a>(bytes: &'a [u8]) -> Result<Torrent<'_>, TorrentError> {
-        from_bytes::<'_>(bytes).map_err(|e| TorrentError::ParseTorrent(e))
+    pub fn parse_bytes(bytes: &[u8]) -> Result<Torrent<'_>, TorrentError> {
+        let mut torrent: Torrent = from_bytes::<'_>(bytes).map_err(TorrentError::ParseTorrent)?;
+        torrent.raw_info = parsing_modules::raw_info(bytes);
+
+        Ok(torrent)
+    }
+
+    /// Encode the torrent into its bencoded form, ready to be written as a `.torrent` file.
+    /// Parsed torrents keep their original `info` bytes so the info hash doesn't change.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TorrentError> {
+        let mut bytes = to_bytes(self).map_err(TorrentError::EncodeTorrent)?;
+        if let Some(raw) = self.raw_info {
+            if let Some(span) = parsing_modules::raw_info_span(&bytes) {
+                bytes.splice(span, raw.iter().copied());
+            }
+        }
+
+        Ok(bytes)
     }
 
     pub fn calc_download_lenght(&self) -> i64 {
@@ -18,12 +41,128 @@ impl Torrent<'_> {
         self.info.files.iter().map(|f| f.length).sum()
     }
 
-    pub fn calc_hash(&self) -> Result<Vec<u8>, TorrentError> {
-        let mut hasher = Sha1::new();
-        let encoded = to_bytes(&self.info).map_err(|e| TorrentError::EncodeInfo(e))?;
-        hasher.update(&encoded);
-        let hash = hasher.finalize();
+    /// List the files as found in the torrent, relative to the torrent root, with their
+    /// length. Single file torrents list their name.
+    pub fn file_paths(&self) -> Vec<(String, i64)> {
+        if self.info.files.is_empty() {
+            return vec![(self.info.name.clone(), self.info.length)];
+        }
+
+        self.info
+            .files
+            .iter()
+            .map(|f| (f.path.clone(), f.length))
+            .collect()
+    }
+
+    /// List the content files located under `root` with their expected length, in the order
+    /// they are concatenated to form pieces.
+    /// Paths are sanitized so a malicious torrent can't reach files outside of `root`.
+    pub fn content_files(&self, root: &Path) -> Vec<(PathBuf, i64)> {
+        let mut base = root.join(sanitize_path(&self.info.name));
+        if base == root {
+            base.push("_");
+        }
+        if self.info.files.is_empty() {
+            return vec![(base, self.info.length)];
+        }
+
+        self.info
+            .files
+            .iter()
+            .map(|f| (base.join(sanitize_path(&f.path)), f.length))
+            .collect()
+    }
+
+    /// Compute the torrent info hash.
+    /// For parsed torrents, the original `info` bytes are hashed so unknown or
+    /// non-canonical content doesn't change the result.
+    pub fn calc_hash(&self) -> Result<InfoHash, TorrentError> {
+        Ok(InfoHash(Sha1::digest(self.info_bytes()?).into()))
+    }
+
+    /// Bencoded `info` dictionary, as found in the parsed file or encoded for built torrents.
+    /// This is the metadata exchanged with peers (`BEP 0009`).
+    pub fn info_bytes(&self) -> Result<Vec<u8>, TorrentError> {
+        match self.raw_info {
+            Some(raw) => Ok(raw.to_vec()),
+            None => to_bytes(&self.info).map_err(TorrentError::EncodeInfo),
+        }
+    }
+
+    /// Build a magnet link to this torrent, with its name, length, trackers and web seeds
+    pub fn to_magnet(&self) -> Result<Magnet, TorrentError> {
+        let mut trackers: Vec<String> = vec![];
+        let announces = [&self.announce]
+            .into_iter()
+            .chain(self.announce_list.trackers());
+        for tracker in announces {
+            if !tracker.is_empty() && !trackers.contains(tracker) {
+                trackers.push(tracker.clone());
+            }
+        }
+
+        Ok(Magnet {
+            name: Some(self.info.name.clone()),
+            length: Some(self.calc_download_lenght() as u64),
+            trackers,
+            web_seeds: self.additional_fields.url_list.clone(),
+            ..Magnet::new(self.calc_hash()?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `private` is dropped and `x-source` unknown when the info is encoded again, both
+    /// must still be part of the hashed bytes
+    const TORRENT: &[u8] = b"d8:announce14:http://tracker4:infod6:lengthi5e4:name4:test\
+        12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:privatei0e\
+        8:x-sourced3:urli7eeee";
+
+    #[test]
+    fn raw_info_spans_the_info_value() {
+        let torrent = Torrent::parse_bytes(TORRENT).unwrap();
+
+        assert_eq!(torrent.raw_info, Some(&TORRENT[34..TORRENT.len() - 1]));
+    }
+
+    #[test]
+    fn calc_hash_uses_raw_info_bytes() {
+        let torrent = Torrent::parse_bytes(TORRENT).unwrap();
+        let hash = torrent.calc_hash().unwrap();
+
+        assert_eq!(hash.to_hex(), "9623d3bf583e9e6eec6a3116c17078398dff1651");
+    }
+
+    #[test]
+    fn to_bytes_keeps_raw_info_bytes() {
+        let torrent = Torrent::parse_bytes(TORRENT).unwrap();
+        let bytes = torrent.to_bytes().unwrap();
+
+        assert_eq!(bytes, TORRENT);
+        assert_eq!(
+            Torrent::parse_bytes(&bytes).unwrap().calc_hash().unwrap(),
+            torrent.calc_hash().unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_rejects_non_utf8_paths() {
+        let torrent = b"d8:announce14:http://tracker4:infod5:filesld6:lengthi5e\
+            4:pathl2:\xff\xfeeee4:name4:test12:piece lengthi16384e\
+            6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+        assert!(Torrent::parse_bytes(torrent).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_zero_piece_length() {
+        let torrent = b"d8:announce14:http://tracker4:infod6:lengthi5e4:name4:test\
+            12:piece lengthi0e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
 
-        Ok(hash.to_vec())
+        assert!(Torrent::parse_bytes(torrent).is_err());
     }
 }
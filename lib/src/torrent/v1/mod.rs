@@ -1,7 +1,12 @@
+mod announce_list;
+mod builder;
 mod display;
+mod hasher;
 mod main;
 mod parsing_modules;
+mod pieces;
 mod tcp;
+mod verify;
 
 use std::collections::HashMap;
 
@@ -11,22 +16,36 @@ use serde_with::{serde_as, BoolFromInt, TimestampSeconds};
 
 use crate::extension_parsing;
 
+pub use announce_list::AnnounceList;
+pub use builder::TorrentBuilder;
+pub use hasher::{CancelHandle, HashProgress, PieceHasher};
+pub use pieces::{Pieces, PIECE_HASH_LEN};
+pub use tcp::{Handshake, Message, PeerConnection, PeerReader, PeerWriter};
+pub use verify::{FileReport, FileStatus, VerifyReport};
+
+pub(crate) use parsing_modules::raw_value_end;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Torrent<'a> {
-    /// Announcer URL
+    /// Announcer URL. Missing from trackerless torrents.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub announce: String,
     /// Torrent information
     pub info: TorrentInfo<'a>,
-    /// List of additional trackers
+    /// Tiers of additional trackers (`BEP 0012`)
     #[serde(
         default,
         rename = "announce-list",
-        skip_serializing_if = "Vec::is_empty"
+        skip_serializing_if = "AnnounceList::is_empty"
     )]
-    pub announce_list: Vec<String>,
+    pub announce_list: AnnounceList,
     /// Non official fields
     #[serde(flatten, borrow)]
     pub additional_fields: RootAdditionalFields<'a>,
+    /// Bencoded `info` dictionary as found in the parsed file.
+    /// Used to compute the info hash without relying on a re-encoding round-trip.
+    #[serde(skip)]
+    raw_info: Option<&'a [u8]>,
 }
 
 /// TorrentInfo is a struct that contains all the information about the torrent file.
@@ -38,12 +57,15 @@ pub struct TorrentInfo<'a> {
     pub name: String,
     /// Size of each data piece.
     /// REQUIRED
-    #[serde(rename = "piece length")]
+    #[serde(
+        rename = "piece length",
+        deserialize_with = "parsing_modules::deserialize_piece_length"
+    )]
     pub piece_length: i64,
     /// SHA1 hashes of each pieces concatenated. Each hash is 20 bytes long.
     /// REQUIRED
-    #[serde(with = "parsing_modules::pieces")]
-    pub pieces: Vec<String>,
+    #[serde(borrow)]
+    pub pieces: Pieces<'a>,
     /// In case of a single file, represents the file size.
     /// REQUIRED - If `TorrentInfo.files` is empty
     #[serde(default, skip_serializing_if = "extension_parsing::skip_empty::i64")]
@@ -75,7 +97,7 @@ pub struct TorrentFile {
 /// original [BitTorrent](https://www.bittorrent.org/beps/bep_0003.html) specification.
 /// Those who are well known are mapped directly with default values.
 #[serde_as]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Default, Debug, Deserialize, Serialize)]
 pub struct RootAdditionalFields<'a> {
     /// Torrent creator or software name
     #[serde(
@@ -98,7 +120,7 @@ pub struct RootAdditionalFields<'a> {
     /// List of resources available
     #[serde(default, rename = "url-list", skip_serializing_if = "Vec::is_empty")]
     pub url_list: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub encoding: String,
     /// Extra fields not explicitly covered by the struct
     #[serde(flatten, borrow)]
@@ -109,7 +131,7 @@ pub struct RootAdditionalFields<'a> {
 /// original [BitTorrent](https://www.bittorrent.org/beps/bep_0003.html) specification.
 /// Those who are well known are mapped directly with default values.
 #[serde_as]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Default, Debug, Deserialize, Serialize)]
 pub struct TorrentInfoAdditionalFields<'a> {
     /// Is the torrent private
     #[serde_as(as = "BoolFromInt")]
@@ -1,35 +1,6 @@
-pub(super) mod pieces {
-    use serde::{de, ser, Deserializer, Serializer};
-    use serde_with::{Bytes, DeserializeAs, SerializeAs};
+use std::ops::Range;
 
-    pub fn serialize<S>(pieces: &Vec<String>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut buf: Vec<u8> = vec![];
-        for v in pieces {
-            buf.append(&mut hex::decode(v).map_err(|e| {
-                ser::Error::custom(format!(
-                    "Every pieces must be serializable into hexadecimal: {e}"
-                ))
-            })?);
-        }
-
-        Bytes::serialize_as(&buf, serializer)
-    }
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let bytes: Vec<u8> = Bytes::deserialize_as(deserializer)?;
-        if (bytes.len() % 20) != 0 {
-            return Err(de::Error::custom("Invalid SHA1 pieces"));
-        }
-
-        Ok(bytes.chunks(20).map(|c| hex::encode(c)).collect())
-    }
-}
+use serde::{de, Deserialize, Deserializer};
 
 pub(super) mod files {
     use std::{borrow::Cow, collections::BTreeMap};
@@ -48,7 +19,7 @@ pub(super) mod files {
         let path_key: Cow<'_, [u8]> = Cow::Owned(String::from("path").into_bytes());
         for f in files {
             let mut dict: BTreeMap<Cow<'_, [u8]>, Value> = BTreeMap::new();
-            dict.insert(length_key.clone(), Value::Integer(f.length as i64));
+            dict.insert(length_key.clone(), Value::Integer(f.length));
             let mut path_partial = vec![];
             for i in f.path.split("/") {
                 path_partial.push(Value::Bytes(i.as_bytes().into()));
@@ -122,7 +93,7 @@ pub(super) mod files {
                 Value::Integer(v) => *v,
                 Value::Bytes(v) => {
                     return Err(de::Error::invalid_type(
-                        de::Unexpected::Bytes(&v),
+                        de::Unexpected::Bytes(v),
                         &"integer",
                     ))
                 }
@@ -167,12 +138,15 @@ pub(super) mod files {
                 }
                 Value::Bytes(v) => {
                     return Err(de::Error::invalid_type(
-                        de::Unexpected::Bytes(&v),
+                        de::Unexpected::Bytes(v),
                         &"list of bytes",
                     ))
                 }
                 Value::Dict(_) => {
-                    return Err(de::Error::invalid_type(de::Unexpected::Map, &"list of bytes"))
+                    return Err(de::Error::invalid_type(
+                        de::Unexpected::Map,
+                        &"list of bytes",
+                    ))
                 }
                 Value::Integer(v) => {
                     return Err(de::Error::invalid_type(
@@ -187,3 +161,81 @@ pub(super) mod files {
         Ok(torrent_files)
     }
 }
+
+/// Deserialize `piece length`, rejecting values that would leave the content without pieces.
+pub(super) fn deserialize_piece_length<'de, D>(deserializer: D) -> Result<i64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let length = i64::deserialize(deserializer)?;
+    if length <= 0 {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Signed(length),
+            &"a positive piece length",
+        ));
+    }
+
+    Ok(length)
+}
+
+/// Locate the raw bencoded `info` dictionary inside a torrent file.
+/// Only the structure is walked, keys ordering and content are not checked.
+pub(super) fn raw_info(bytes: &[u8]) -> Option<&[u8]> {
+    raw_info_span(bytes).map(|span| &bytes[span])
+}
+
+/// Offsets of the bencoded `info` value inside a torrent file, see [`raw_info`].
+pub(super) fn raw_info_span(bytes: &[u8]) -> Option<Range<usize>> {
+    if bytes.first() != Some(&b'd') {
+        return None;
+    }
+
+    let mut pos = 1;
+    while *bytes.get(pos)? != b'e' {
+        let (key, start) = raw_string(bytes, pos)?;
+        let value = start + key.len();
+        let end = raw_value_end(bytes, value)?;
+        if key == b"info" {
+            return Some(value..end);
+        }
+        pos = end;
+    }
+
+    None
+}
+
+/// Return the end offset of the bencoded value starting at `pos`, `None` if it is
+/// truncated or invalid.
+/// Nested values are walked iteratively, so deeply nested input can't overflow the stack.
+pub(crate) fn raw_value_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    let mut pos = pos;
+    // Number of lists and dictionaries left open
+    let mut depth = 0usize;
+    loop {
+        match *bytes.get(pos)? {
+            b'i' => pos = pos.checked_add(bytes[pos..].iter().position(|b| *b == b'e')? + 1)?,
+            b'l' | b'd' => {
+                depth += 1;
+                pos += 1;
+            }
+            b'e' if depth > 0 => {
+                depth -= 1;
+                pos += 1;
+            }
+            b'0'..=b'9' => pos = raw_string(bytes, pos).map(|(v, start)| start + v.len())?,
+            _ => return None,
+        }
+        if depth == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+/// Parse the bencoded byte string starting at `pos`, returning it with its content offset.
+fn raw_string(bytes: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let colon = pos + bytes[pos..].iter().position(|b| *b == b':')?;
+    let len: usize = std::str::from_utf8(&bytes[pos..colon]).ok()?.parse().ok()?;
+    let start = colon + 1;
+
+    Some((bytes.get(start..start.checked_add(len)?)?, start))
+}
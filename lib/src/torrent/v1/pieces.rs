@@ -0,0 +1,169 @@
+use std::{borrow::Cow, fmt, ops::Index};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::torrent::errors::TorrentError;
+
+/// Length of a SHA1 piece hash
+pub const PIECE_HASH_LEN: usize = 20;
+
+/// Pieces holds the SHA1 hash of every piece, concatenated as found in the torrent file.
+/// Parsed torrents borrow the original bytes instead of copying them.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Pieces<'a>(Cow<'a, [u8]>);
+
+impl<'a> Pieces<'a> {
+    /// Wrap concatenated piece hashes. Fails if the length isn't a multiple of 20 bytes.
+    pub fn new<B: Into<Cow<'a, [u8]>>>(bytes: B) -> Result<Self, TorrentError> {
+        let bytes = bytes.into();
+        if !bytes.len().is_multiple_of(PIECE_HASH_LEN) {
+            return Err(TorrentError::InvalidPieces(bytes.len()));
+        }
+
+        Ok(Self(bytes))
+    }
+
+    /// Number of pieces
+    pub fn len(&self) -> usize {
+        self.0.len() / PIECE_HASH_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Hash of the piece at `index`
+    pub fn get(&self, index: usize) -> Option<&[u8; PIECE_HASH_LEN]> {
+        let start = index.checked_mul(PIECE_HASH_LEN)?;
+        self.0
+            .get(start..start + PIECE_HASH_LEN)
+            .map(|c| c.try_into().expect("slice should be 20 bytes long"))
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &[u8; PIECE_HASH_LEN]> {
+        self.0
+            .chunks_exact(PIECE_HASH_LEN)
+            .map(|c| c.try_into().expect("chunk should be 20 bytes long"))
+    }
+
+    /// Concatenated hashes, as stored in the torrent file
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_owned(self) -> Pieces<'static> {
+        Pieces(Cow::Owned(self.0.into_owned()))
+    }
+}
+
+impl FromIterator<[u8; PIECE_HASH_LEN]> for Pieces<'static> {
+    fn from_iter<T: IntoIterator<Item = [u8; PIECE_HASH_LEN]>>(iter: T) -> Self {
+        Pieces(Cow::Owned(iter.into_iter().flatten().collect()))
+    }
+}
+
+impl Index<usize> for Pieces<'_> {
+    type Output = [u8; PIECE_HASH_LEN];
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("piece index out of bounds")
+    }
+}
+
+impl fmt::Debug for Pieces<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pieces({})", self.len())
+    }
+}
+
+impl Serialize for Pieces<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Pieces<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Pieces<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "concatenated SHA1 hashes")
+            }
+
+            fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+                Pieces::new(v).map_err(|_| E::custom("Invalid SHA1 pieces"))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Pieces::new(v.to_vec()).map_err(|_| E::custom("Invalid SHA1 pieces"))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Pieces::new(v).map_err(|_| E::custom("Invalid SHA1 pieces"))
+            }
+        }
+
+        deserializer.deserialize_bytes(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bendy::serde::{from_bytes, to_bytes};
+
+    use super::*;
+
+    fn bytes() -> Vec<u8> {
+        (0..60).collect()
+    }
+
+    #[test]
+    fn access_each_hash() {
+        let bytes = bytes();
+        let pieces = Pieces::new(&bytes[..]).unwrap();
+
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces.get(1).unwrap()[..], bytes[20..40]);
+        assert_eq!(pieces[2][..], bytes[40..60]);
+        assert_eq!(pieces.get(3), None);
+        assert_eq!(pieces.get(usize::MAX), None);
+        assert_eq!(pieces.iter().len(), 3);
+        assert_eq!(pieces.iter().flatten().copied().collect::<Vec<_>>(), bytes);
+        assert_eq!(pieces.iter().copied().collect::<Pieces>(), pieces);
+    }
+
+    #[test]
+    #[should_panic(expected = "piece index out of bounds")]
+    fn index_out_of_bounds_panics() {
+        let _ = Pieces::default()[0];
+    }
+
+    #[test]
+    fn reject_partial_hash() {
+        assert!(matches!(
+            Pieces::new(vec![0; 41]),
+            Err(TorrentError::InvalidPieces(41))
+        ));
+        assert!(Pieces::new(vec![]).unwrap().is_empty());
+        assert!(from_bytes::<Pieces>(b"3:abc").is_err());
+    }
+
+    #[test]
+    fn bencode_round_trip() {
+        let bytes = bytes();
+        let pieces = Pieces::new(bytes.clone()).unwrap();
+        let encoded = to_bytes(&pieces).unwrap();
+
+        assert_eq!(encoded[..3], *b"60:");
+        assert_eq!(from_bytes::<Pieces>(&encoded).unwrap(), pieces);
+    }
+}
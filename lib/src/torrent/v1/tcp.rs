@@ -1,5 +1,562 @@
-use crate::torrent::errors::TcpError;
+//! Peer wire protocol, as defined by [BEP 0003](https://www.bittorrent.org/beps/bep_0003.html).
 
-pub async fn start(_ip: String) -> Result<(), TcpError> {
-    todo!()
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    net::TcpStream,
+    time,
+};
+
+use crate::{
+    peer::PeerId,
+    torrent::{errors::TcpError, InfoHash},
+};
+
+/// Protocol string sent at the start of the handshake
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+/// Handshake length: protocol string and its length, reserved bytes, info hash and peer ID
+pub const HANDSHAKE_LEN: usize = 1 + PROTOCOL.len() + 8 + 20 + 20;
+/// Biggest block accepted in `request`, `piece` and `cancel` messages (128 KiB).
+/// Most clients only request 16 KiB blocks.
+pub const MAX_BLOCK_LEN: u32 = 128 * 1024;
+/// Biggest message accepted, length prefix excluded (2 MiB).
+/// Large enough for a `piece` message or the `bitfield` of a torrent with 16M pieces.
+pub const MAX_MESSAGE_LEN: u32 = 2 * 1024 * 1024;
+/// Time allowed to open the connection and to receive the remote handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ID_CHOKE: u8 = 0;
+const ID_UNCHOKE: u8 = 1;
+const ID_INTERESTED: u8 = 2;
+const ID_NOT_INTERESTED: u8 = 3;
+const ID_HAVE: u8 = 4;
+const ID_BITFIELD: u8 = 5;
+const ID_REQUEST: u8 = 6;
+const ID_PIECE: u8 = 7;
+const ID_CANCEL: u8 = 8;
+const ID_EXTENDED: u8 = 20;
+/// Reserved bit advertising the extension protocol (`BEP 0010`): byte 5, `0x10`
+const EXTENSION_PROTOCOL_BYTE: usize = 5;
+const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+
+/// Handshake exchanged by both peers before any other message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    /// Bits advertising protocol extensions
+    pub reserved: [u8; 8],
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+}
+
+impl Handshake {
+    /// Create a handshake without any extension bit set
+    pub fn new(info_hash: InfoHash, peer_id: PeerId) -> Self {
+        Self {
+            reserved: [0; 8],
+            info_hash,
+            peer_id,
+        }
+    }
+
+    /// Advertise the extension protocol (`BEP 0010`)
+    pub fn with_extension_protocol(mut self) -> Self {
+        self.reserved[EXTENSION_PROTOCOL_BYTE] |= EXTENSION_PROTOCOL_BIT;
+        self
+    }
+
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.reserved[EXTENSION_PROTOCOL_BYTE] & EXTENSION_PROTOCOL_BIT != 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut bytes = [0u8; HANDSHAKE_LEN];
+        bytes[0] = PROTOCOL.len() as u8;
+        bytes[1..20].copy_from_slice(PROTOCOL);
+        bytes[20..28].copy_from_slice(&self.reserved);
+        bytes[28..48].copy_from_slice(self.info_hash.as_bytes());
+        bytes[48..68].copy_from_slice(self.peer_id.as_bytes());
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; HANDSHAKE_LEN]) -> Result<Self, TcpError> {
+        if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+            return Err(TcpError::InvalidProtocol(
+                String::from_utf8_lossy(&bytes[1..20]).into_owned(),
+            ));
+        }
+
+        Ok(Self {
+            reserved: bytes[20..28]
+                .try_into()
+                .expect("slice should be 8 bytes long"),
+            info_hash: InfoHash(
+                bytes[28..48]
+                    .try_into()
+                    .expect("slice should be 20 bytes long"),
+            ),
+            peer_id: PeerId(
+                bytes[48..68]
+                    .try_into()
+                    .expect("slice should be 20 bytes long"),
+            ),
+        })
+    }
+}
+
+/// Messages exchanged once the handshake is done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    /// The peer has downloaded and verified the piece at this index
+    Have(u32),
+    /// Pieces owned by the peer, the highest bit of the first byte being piece 0.
+    /// Only valid as the first message after the handshake.
+    Bitfield(Vec<u8>),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    /// Message of the extension protocol (`BEP 0010`). `id` is `0` for the extended
+    /// handshake, or the ID the receiver assigned to the extension in its handshake.
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
+}
+
+impl Message {
+    /// Encode the message with its length prefix
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        match self {
+            Message::KeepAlive => {}
+            Message::Choke => payload.push(ID_CHOKE),
+            Message::Unchoke => payload.push(ID_UNCHOKE),
+            Message::Interested => payload.push(ID_INTERESTED),
+            Message::NotInterested => payload.push(ID_NOT_INTERESTED),
+            Message::Have(index) => {
+                payload.push(ID_HAVE);
+                payload.extend_from_slice(&index.to_be_bytes());
+            }
+            Message::Bitfield(v) => {
+                payload.push(ID_BITFIELD);
+                payload.extend_from_slice(v);
+            }
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => {
+                payload.push(ID_REQUEST);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                payload.push(ID_PIECE);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(block);
+            }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                payload.push(ID_CANCEL);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Extended { id, payload: v } => {
+                payload.push(ID_EXTENDED);
+                payload.push(*id);
+                payload.extend_from_slice(v);
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(4 + payload.len());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+
+        bytes
+    }
+
+    /// Decode a message from its payload, length prefix excluded.
+    /// An empty payload is a keep-alive.
+    pub fn from_payload(payload: &[u8]) -> Result<Self, TcpError> {
+        let (id, body) = match payload.split_first() {
+            Some((id, body)) => (*id, body),
+            None => return Ok(Message::KeepAlive),
+        };
+        let expect_len = |len: usize| {
+            if body.len() != len {
+                return Err(TcpError::InvalidMessageLength {
+                    id,
+                    len: body.len(),
+                });
+            }
+            Ok(())
+        };
+
+        match id {
+            ID_CHOKE => expect_len(0).map(|_| Message::Choke),
+            ID_UNCHOKE => expect_len(0).map(|_| Message::Unchoke),
+            ID_INTERESTED => expect_len(0).map(|_| Message::Interested),
+            ID_NOT_INTERESTED => expect_len(0).map(|_| Message::NotInterested),
+            ID_HAVE => {
+                expect_len(4)?;
+                Ok(Message::Have(read_u32(&body[0..4])))
+            }
+            ID_BITFIELD => Ok(Message::Bitfield(body.to_vec())),
+            ID_REQUEST | ID_CANCEL => {
+                expect_len(12)?;
+                let (index, begin, length) = (
+                    read_u32(&body[0..4]),
+                    read_u32(&body[4..8]),
+                    read_u32(&body[8..12]),
+                );
+                if length > MAX_BLOCK_LEN {
+                    return Err(TcpError::BlockTooLarge(length));
+                }
+
+                Ok(if id == ID_REQUEST {
+                    Message::Request {
+                        index,
+                        begin,
+                        length,
+                    }
+                } else {
+                    Message::Cancel {
+                        index,
+                        begin,
+                        length,
+                    }
+                })
+            }
+            ID_PIECE => {
+                if body.len() < 8 {
+                    return Err(TcpError::InvalidMessageLength {
+                        id,
+                        len: body.len(),
+                    });
+                }
+                if body.len() - 8 > MAX_BLOCK_LEN as usize {
+                    return Err(TcpError::BlockTooLarge((body.len() - 8) as u32));
+                }
+
+                Ok(Message::Piece {
+                    index: read_u32(&body[0..4]),
+                    begin: read_u32(&body[4..8]),
+                    block: body[8..].to_vec(),
+                })
+            }
+            ID_EXTENDED => match body.split_first() {
+                Some((ext, payload)) => Ok(Message::Extended {
+                    id: *ext,
+                    payload: payload.to_vec(),
+                }),
+                None => Err(TcpError::InvalidMessageLength { id, len: 0 }),
+            },
+            id => Err(TcpError::UnknownMessage(id)),
+        }
+    }
+}
+
+/// PeerConnection is an established connection with a remote peer, handshake included.
+#[derive(Debug)]
+pub struct PeerConnection<S = TcpStream> {
+    stream: S,
+    remote: Handshake,
+}
+
+impl PeerConnection<TcpStream> {
+    /// Open a TCP connection to `addr` and perform the handshake.
+    pub async fn connect(addr: SocketAddr, local: Handshake) -> Result<Self, TcpError> {
+        let stream = time::timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| TcpError::Timeout)??;
+
+        Self::handshake(stream, local).await
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, TcpError> {
+        Ok(self.stream.peer_addr()?)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> PeerConnection<S> {
+    /// Send our handshake on an outgoing connection and wait for the remote one.
+    /// The remote info hash must match ours.
+    pub async fn handshake(mut stream: S, local: Handshake) -> Result<Self, TcpError> {
+        stream.write_all(&local.to_bytes()).await?;
+        let remote = time::timeout(HANDSHAKE_TIMEOUT, read_handshake(&mut stream))
+            .await
+            .map_err(|_| TcpError::Timeout)??;
+        if remote.info_hash != local.info_hash {
+            return Err(TcpError::InfoHashMismatch {
+                expected: local.info_hash,
+                found: remote.info_hash,
+            });
+        }
+
+        Ok(Self { stream, remote })
+    }
+
+    /// Wait for the remote handshake on an incoming connection, then answer with the
+    /// handshake returned by `respond`. Returning `None` rejects an unknown info hash.
+    pub async fn accept<F>(mut stream: S, respond: F) -> Result<Self, TcpError>
+    where
+        F: FnOnce(&Handshake) -> Option<Handshake>,
+    {
+        let remote = time::timeout(HANDSHAKE_TIMEOUT, read_handshake(&mut stream))
+            .await
+            .map_err(|_| TcpError::Timeout)??;
+        let local = respond(&remote).ok_or(TcpError::UnknownInfoHash(remote.info_hash))?;
+        stream.write_all(&local.to_bytes()).await?;
+
+        Ok(Self { stream, remote })
+    }
+
+    /// Handshake sent by the remote peer
+    pub fn remote(&self) -> &Handshake {
+        &self.remote
+    }
+
+    pub async fn send(&mut self, msg: &Message) -> Result<(), TcpError> {
+        write_message(&mut self.stream, msg).await
+    }
+
+    /// Wait for the next message. Messages with an unknown ID are skipped.
+    pub async fn recv(&mut self) -> Result<Message, TcpError> {
+        read_message(&mut self.stream).await
+    }
+
+    /// Split the connection to read and write messages from different tasks
+    pub fn into_split(self) -> (PeerReader<ReadHalf<S>>, PeerWriter<WriteHalf<S>>) {
+        let (r, w) = io::split(self.stream);
+        (PeerReader(r), PeerWriter(w))
+    }
+}
+
+/// Reading half of a [`PeerConnection`]
+#[derive(Debug)]
+pub struct PeerReader<R>(R);
+
+impl<R: AsyncRead + Unpin> PeerReader<R> {
+    /// Wait for the next message. Messages with an unknown ID are skipped.
+    pub async fn recv(&mut self) -> Result<Message, TcpError> {
+        read_message(&mut self.0).await
+    }
+}
+
+/// Writing half of a [`PeerConnection`]
+#[derive(Debug)]
+pub struct PeerWriter<W>(W);
+
+impl<W: AsyncWrite + Unpin> PeerWriter<W> {
+    pub async fn send(&mut self, msg: &Message) -> Result<(), TcpError> {
+        write_message(&mut self.0, msg).await
+    }
+}
+
+async fn read_handshake<R: AsyncRead + Unpin>(r: &mut R) -> Result<Handshake, TcpError> {
+    let mut bytes = [0u8; HANDSHAKE_LEN];
+    read_exact(r, &mut bytes).await?;
+
+    Handshake::from_bytes(&bytes)
+}
+
+async fn read_message<R: AsyncRead + Unpin>(r: &mut R) -> Result<Message, TcpError> {
+    loop {
+        let mut len = [0u8; 4];
+        read_exact(r, &mut len).await?;
+        let len = u32::from_be_bytes(len);
+        if len > MAX_MESSAGE_LEN {
+            return Err(TcpError::MessageTooLarge(len));
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        read_exact(r, &mut payload).await?;
+        match Message::from_payload(&payload) {
+            Err(TcpError::UnknownMessage(_)) => continue,
+            res => return res,
+        }
+    }
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(w: &mut W, msg: &Message) -> Result<(), TcpError> {
+    w.write_all(&msg.to_bytes()).await?;
+    w.flush().await?;
+
+    Ok(())
+}
+
+/// Fill `buf`, reporting a closed connection instead of an unexpected EOF
+async fn read_exact<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> Result<(), TcpError> {
+    match r.read_exact(buf).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(TcpError::ConnectionClosed),
+        Err(e) => Err(TcpError::Io(e)),
+    }
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes(b.try_into().expect("slice should be 4 bytes long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake() -> Handshake {
+        Handshake::new(InfoHash([1; 20]), PeerId([2; 20]))
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::KeepAlive,
+            Message::Choke,
+            Message::Unchoke,
+            Message::Interested,
+            Message::NotInterested,
+            Message::Have(7),
+            Message::Bitfield(vec![0b1010_0000]),
+            Message::Request {
+                index: 1,
+                begin: 16384,
+                length: 16384,
+            },
+            Message::Piece {
+                index: 1,
+                begin: 0,
+                block: vec![9; 10],
+            },
+            Message::Cancel {
+                index: 1,
+                begin: 16384,
+                length: 16384,
+            },
+            Message::Extended {
+                id: 0,
+                payload: b"de".to_vec(),
+            },
+        ];
+        for msg in messages {
+            let bytes = msg.to_bytes();
+            let len = read_u32(&bytes[..4]) as usize;
+
+            assert_eq!(len, bytes.len() - 4);
+            assert_eq!(Message::from_payload(&bytes[4..]).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn request_encoding() {
+        let msg = Message::Request {
+            index: 1,
+            begin: 2,
+            length: 3,
+        };
+
+        assert_eq!(
+            msg.to_bytes(),
+            [0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]
+        );
+    }
+
+    #[test]
+    fn from_payload_rejects_invalid_messages() {
+        assert!(matches!(
+            Message::from_payload(&[ID_HAVE, 0, 0]),
+            Err(TcpError::InvalidMessageLength {
+                id: ID_HAVE,
+                len: 2
+            })
+        ));
+        assert!(matches!(
+            Message::from_payload(&[ID_PIECE, 0, 0, 0, 1]),
+            Err(TcpError::InvalidMessageLength { id: ID_PIECE, .. })
+        ));
+        let mut request = vec![ID_REQUEST];
+        request.extend_from_slice(&[0; 8]);
+        request.extend_from_slice(&(MAX_BLOCK_LEN + 1).to_be_bytes());
+        assert!(matches!(
+            Message::from_payload(&request),
+            Err(TcpError::BlockTooLarge(_))
+        ));
+        assert!(matches!(
+            Message::from_payload(&[42]),
+            Err(TcpError::UnknownMessage(42))
+        ));
+    }
+
+    #[test]
+    fn handshake_round_trip() {
+        let local = handshake().with_extension_protocol();
+        let bytes = local.to_bytes();
+
+        assert_eq!(bytes[25], 0x10);
+        let remote = Handshake::from_bytes(&bytes).unwrap();
+        assert_eq!(remote, local);
+        assert!(remote.supports_extension_protocol());
+        assert!(!handshake().supports_extension_protocol());
+    }
+
+    #[test]
+    fn handshake_rejects_other_protocols() {
+        let mut bytes = handshake().to_bytes();
+        bytes[1] = b'X';
+
+        assert!(matches!(
+            Handshake::from_bytes(&bytes),
+            Err(TcpError::InvalidProtocol(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connection_skips_unknown_messages() {
+        let (a, b) = io::duplex(1024);
+        let accept = tokio::spawn(PeerConnection::accept(b, |_| Some(handshake())));
+        let mut local = PeerConnection::handshake(a, handshake()).await.unwrap();
+        let mut remote = accept.await.unwrap().unwrap();
+
+        local.stream.write_all(&[0, 0, 0, 1, 42]).await.unwrap();
+        local.send(&Message::Have(3)).await.unwrap();
+        assert_eq!(remote.recv().await.unwrap(), Message::Have(3));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_other_info_hash() {
+        let (a, b) = io::duplex(1024);
+        let other = Handshake::new(InfoHash([3; 20]), PeerId([4; 20]));
+        tokio::spawn(PeerConnection::accept(b, move |_| Some(other)));
+
+        assert!(matches!(
+            PeerConnection::handshake(a, handshake()).await,
+            Err(TcpError::InfoHashMismatch { .. })
+        ));
+    }
 }
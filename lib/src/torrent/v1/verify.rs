@@ -0,0 +1,171 @@
+use std::{fs, path::Path, sync::Arc};
+
+use crate::{storage::FileStorage, torrent::errors::TorrentError};
+
+use super::{
+    hasher::{HashProgress, PieceHasher},
+    Torrent,
+};
+
+/// Result of checking the data available on disk against a torrent.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    /// Validity of each piece, indexed by piece number
+    pub pieces: Vec<bool>,
+    /// State of each file, in the same order as `TorrentInfo.files`
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    /// File path relative to the torrent root, as found in the torrent
+    pub path: String,
+    /// Expected file size
+    pub length: i64,
+    /// Size found on disk, `None` if the file doesn't exist
+    pub size_on_disk: Option<u64>,
+    /// Number of valid pieces overlapping the file
+    pub valid_pieces: usize,
+    /// Number of pieces overlapping the file
+    pub total_pieces: usize,
+    pub status: FileStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// Every piece overlapping the file is valid
+    Complete,
+    /// Some pieces overlapping the file are invalid or missing
+    Partial,
+    /// The file doesn't exist
+    Missing,
+    /// The file exists but its size doesn't match the torrent
+    WrongSize,
+}
+
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileStatus::Complete => write!(f, "complete"),
+            FileStatus::Partial => write!(f, "partial"),
+            FileStatus::Missing => write!(f, "missing"),
+            FileStatus::WrongSize => write!(f, "wrong size"),
+        }
+    }
+}
+
+impl VerifyReport {
+    pub fn valid_pieces(&self) -> usize {
+        self.pieces.iter().filter(|v| **v).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(|v| *v)
+    }
+}
+
+impl Torrent<'_> {
+    /// Check the content stored under `root` against the torrent pieces.
+    /// `root` is the directory containing the torrent content, `TorrentInfo.name` included.
+    pub fn verify(&self, root: &Path) -> Result<VerifyReport, TorrentError> {
+        self.verify_with_progress(root, 0, |_| {})
+    }
+
+    /// Same as [`Torrent::verify`], hashing on `threads` threads (`0` for the available
+    /// parallelism) and calling `progress` after each hashed piece.
+    pub fn verify_with_progress<F>(
+        &self,
+        root: &Path,
+        threads: usize,
+        progress: F,
+    ) -> Result<VerifyReport, TorrentError>
+    where
+        F: FnMut(HashProgress),
+    {
+        let files = self.content_files(root);
+        let storage = FileStorage::from_torrent(self, root);
+        let mut hasher = PieceHasher::from_storage(Arc::new(storage)).allow_missing(true);
+        if threads > 0 {
+            hasher = hasher.threads(threads);
+        }
+
+        let hashes = hasher.hash(progress)?;
+        let pieces: Vec<bool> = self
+            .info
+            .pieces
+            .iter()
+            .enumerate()
+            .map(|(i, expected)| match hashes.get(i) {
+                Some(Some(h)) => h == expected,
+                _ => false,
+            })
+            .collect();
+
+        let piece_length = self.info.piece_length;
+        let mut offset = 0;
+        let mut reports = vec![];
+        for (i, (path, length)) in files.iter().enumerate() {
+            let size_on_disk = fs::metadata(path).ok().map(|m| m.len());
+            // Empty files don't overlap any piece
+            let (valid_pieces, total_pieces) = if *length > 0 {
+                let (first, last) = (offset / piece_length, (offset + length - 1) / piece_length);
+                let start = (first as usize).min(pieces.len());
+                let end = (last as usize + 1).min(pieces.len());
+                let valid = pieces[start..end].iter().filter(|v| **v).count();
+                (valid, (last - first + 1) as usize)
+            } else {
+                (0, 0)
+            };
+            offset += length;
+
+            let status = match size_on_disk {
+                None => FileStatus::Missing,
+                Some(s) if s != *length as u64 => FileStatus::WrongSize,
+                Some(_) if valid_pieces == total_pieces => FileStatus::Complete,
+                Some(_) => FileStatus::Partial,
+            };
+            reports.push(FileReport {
+                path: match self.info.files.get(i) {
+                    Some(f) => f.path.clone(),
+                    None => self.info.name.clone(),
+                },
+                length: *length,
+                size_on_disk,
+                valid_pieces,
+                total_pieces,
+                status,
+            });
+        }
+
+        Ok(VerifyReport {
+            pieces,
+            files: reports,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::torrent::v1::TorrentBuilder;
+
+    use super::*;
+
+    #[test]
+    fn verify_skips_empty_files() {
+        let root = env::temp_dir().join(format!("brs-verify-{}", std::process::id()));
+        let dir = root.join("content");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.bin"), vec![1u8; 40_000]).unwrap();
+        fs::write(dir.join("empty"), b"").unwrap();
+
+        let torrent = TorrentBuilder::new(&dir).piece_length(16384).build().unwrap();
+        let report = torrent.verify(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(report.is_complete());
+        assert_eq!(report.files[1].total_pieces, 0);
+        assert_eq!(report.files[1].status, FileStatus::Complete);
+    }
+}
@@ -1,20 +1,60 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
 
 use bendy::{serde::from_bytes, value::Value};
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, BoolFromInt};
 
-use super::{errors::TrackerError, parsing_modules, Peer, Tracker};
+use crate::{
+    peer::PeerId,
+    torrent::{urlencode, InfoHash},
+};
 
-#[derive(Debug, Clone)]
+use super::{errors::TrackerError, parsing_modules, tracker::Protocol, Peer, Tracker};
+
+/// Tracker response to an announce request
+#[derive(Debug, Clone, Default)]
 pub struct AnnounceRsp<'a> {
+    /// Interval in seconds to wait before the next announce
     pub interval: u64,
+    /// Minimum interval in seconds between two announces
+    pub min_interval: Option<u64>,
+    /// ID to send back on the next announces
+    pub tracker_id: Option<String>,
+    /// Warning message. The response is still valid
+    pub warning_message: Option<String>,
+    /// Number of peers with the entire file (seeders)
+    pub complete: Option<u64>,
+    /// Number of peers still downloading (leechers)
+    pub incomplete: Option<u64>,
+    /// Our IP address as seen by the tracker (`BEP 0024`)
+    pub external_ip: Option<IpAddr>,
+    /// When the tracker wants the next announce to happen (`BEP 0031`)
+    pub retry_in: Option<RetryIn>,
     pub peers: Vec<Peer>,
+    /// Fields not explicitly covered by the struct
     pub additional_fields: HashMap<String, Value<'a>>,
 }
 
+/// Delay requested by the tracker before retrying an announce (`BEP 0031`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryIn {
+    Minutes(u64),
+    Never,
+}
+
+impl std::fmt::Display for RetryIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryIn::Minutes(v) => write!(f, "{v} minutes"),
+            RetryIn::Never => write!(f, "never"),
+        }
+    }
+}
+
 /// Possible events sent when doing the announce request
-#[derive(Default, Serialize)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AnnounceEv {
     Started,
@@ -24,90 +64,189 @@ pub enum AnnounceEv {
     Empty,
 }
 
+impl AnnounceEv {
+    /// Value of the `event` query parameter. Empty for regular announces.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnounceEv::Started => "started",
+            AnnounceEv::Completed => "completed",
+            AnnounceEv::Stopped => "stopped",
+            AnnounceEv::Empty => "",
+        }
+    }
+}
+
 /// Tracker query parameters.
-#[serde_as]
-#[derive(Default, Serialize)]
+#[derive(Default, Debug, Clone)]
 pub struct AnnounceReq {
-    /// SHA1 hash of the bencode form. Must be 20 bytes long.
+    /// SHA1 hash of the bencoded `info` dictionary.
     /// REQUIRED
-    #[serde(serialize_with = "parsing_modules::serialize_bytes_urlencoded")]
-    pub info_hash: Vec<u8>,
-    /// 20 characters ID generate before a download request.
+    pub info_hash: InfoHash,
+    /// 20 bytes ID generated before a download request.
     /// REQUIRED
-    pub peer_id: String,
-    /// Client's IP address.
-    #[serde(skip_serializing_if = "String::is_empty")]
-    pub ip: String,
+    pub peer_id: PeerId,
     /// Client's listening port.
     /// Usually, downloader will try common range: `6881` to `6889`.
     /// REQUIRED
     pub port: u16,
-    /// Total amount of bytes uploaded encoded in base 10 `ASCII`.
+    /// Total amount of bytes uploaded since the `started` event.
     /// REQUIRED
-    pub uploaded: String,
-    /// Total amount of bytes downloaded encoded in base 10 `ASCII`.
+    pub uploaded: u64,
+    /// Total amount of bytes downloaded since the `started` event.
     /// REQUIRED
-    pub downloaded: String,
-    /// Total amount of bytes left to download encoded in base 10 `ASCII`.
+    pub downloaded: u64,
+    /// Total amount of bytes left to download.
     /// REQUIRED
-    pub left: String,
+    pub left: u64,
     /// Annoucement event.
-    #[serde(default)]
     pub event: AnnounceEv,
-    /// Should the tracker respond with a compact peers list
-    #[serde_as(as = "BoolFromInt")]
-    #[serde(default)]
+    /// Should the tracker respond with a compact peers list (`BEP 0023`)
     pub compact: bool,
+    /// Should the tracker omit peer IDs in a non compact peers list
+    pub no_peer_id: bool,
+    /// Client's IP address, when it differs from the request source address.
+    pub ip: Option<IpAddr>,
+    /// Client's IPv4 address, announced alongside an IPv6 one (`BEP 0007`)
+    pub ipv4: Option<Ipv4Addr>,
+    /// Client's IPv6 address, announced alongside an IPv4 one (`BEP 0007`)
+    pub ipv6: Option<Ipv6Addr>,
+    /// Number of peers wanted. The tracker decides when empty.
+    pub numwant: Option<u32>,
+    /// Random value identifying the client across IP address changes
+    pub key: Option<u32>,
+    /// ID previously sent by the tracker.
+    /// Filled automatically by [`Tracker::announce`] when empty.
+    pub trackerid: Option<String>,
+}
+
+impl AnnounceReq {
+    /// Build the HTTP query string.
+    /// `info_hash` and `peer_id` are percent-encoded byte by byte, so any value is sent as is.
+    pub fn to_query(&self) -> String {
+        let mut params = vec![
+            ("info_hash", self.info_hash.urlencode()),
+            ("peer_id", self.peer_id.urlencode()),
+            ("port", self.port.to_string()),
+            ("uploaded", self.uploaded.to_string()),
+            ("downloaded", self.downloaded.to_string()),
+            ("left", self.left.to_string()),
+            ("compact", (self.compact as u8).to_string()),
+        ];
+        if self.event != AnnounceEv::Empty {
+            params.push(("event", self.event.as_str().to_string()));
+        }
+        if self.no_peer_id {
+            params.push(("no_peer_id", "1".to_string()));
+        }
+        if let Some(v) = self.ip {
+            params.push(("ip", urlencode(v.to_string().as_bytes())));
+        }
+        if let Some(v) = self.ipv4 {
+            params.push(("ipv4", v.to_string()));
+        }
+        if let Some(v) = self.ipv6 {
+            params.push(("ipv6", urlencode(v.to_string().as_bytes())));
+        }
+        if let Some(v) = self.numwant {
+            params.push(("numwant", v.to_string()));
+        }
+        if let Some(v) = self.key {
+            params.push(("key", format!("{v:08X}")));
+        }
+        if let Some(v) = &self.trackerid {
+            params.push(("trackerid", urlencode(v.as_bytes())));
+        }
+
+        params
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum Body<'a> {
-    /// Tracker responded with an error
-    Error {
-        /// REQUIRED
-        #[serde(rename = "failure reason")]
-        failure_reason: String,
-    },
-    /// Tracker responded with a peer list - Normal response
-    Success {
-        /// Interval in seconds to query the tracker
-        /// REQUIRED
-        interval: u64,
-        /// List of peers
-        /// REQUIRED
-        peers: Vec<Peer>,
-    },
-    /// Tracker responded with a peer list - Compact response
-    SuccessCompact {
-        /// Interval in seconds to query the tracker
-        /// REQUIRED
-        interval: u64,
-        /// List of peers in BigEndian order.
-        /// 4 bytes allocated for the IPv4 address and 2 bytes for the port.
-        /// REQUIRED - if `peers6` key is not present. Both can be present
-        #[serde(default, deserialize_with = "parsing_modules::deserialize_ipv4")]
-        peers: Vec<Peer>,
-        /// List of peers in BigEndian order.
-        /// 16 bytes allocated for the IPv4 address and 2 bytes for the port.
-        /// REQUIRED - if `peers` key is not present. Both can be present
-        #[serde(default, deserialize_with = "parsing_modules::deserialize_ipv6")]
-        peers6: Vec<Peer>,
-        #[serde(flatten, borrow)]
-        additional_fields: HashMap<String, Value<'a>>,
-    },
+struct Body<'a> {
+    /// Tracker responded with an error. No other key is required
+    #[serde(default, rename = "failure reason")]
+    failure_reason: String,
+    #[serde(default, rename = "warning message")]
+    warning_message: String,
+    /// Interval in seconds to query the tracker
+    /// REQUIRED
+    #[serde(default)]
+    interval: u64,
+    #[serde(
+        default,
+        rename = "min interval",
+        deserialize_with = "parsing_modules::deserialize_some"
+    )]
+    min_interval: Option<u64>,
+    #[serde(default, rename = "tracker id")]
+    tracker_id: String,
+    #[serde(default, deserialize_with = "parsing_modules::deserialize_some")]
+    complete: Option<u64>,
+    #[serde(default, deserialize_with = "parsing_modules::deserialize_some")]
+    incomplete: Option<u64>,
+    /// List of peers, either as a list of dictionaries or in the compact form.
+    /// REQUIRED - if `peers6` key is not present. Both can be present
+    #[serde(default, deserialize_with = "parsing_modules::deserialize_peers")]
+    peers: Vec<Peer>,
+    /// List of peers in BigEndian order.
+    /// 16 bytes allocated for the IPv6 address and 2 bytes for the port.
+    #[serde(default, deserialize_with = "parsing_modules::deserialize_ipv6")]
+    peers6: Vec<Peer>,
+    #[serde(
+        default,
+        rename = "external ip",
+        deserialize_with = "parsing_modules::deserialize_external_ip"
+    )]
+    external_ip: Option<IpAddr>,
+    #[serde(
+        default,
+        rename = "retry in",
+        deserialize_with = "parsing_modules::deserialize_retry_in"
+    )]
+    retry_in: Option<RetryIn>,
+    #[serde(flatten, borrow)]
+    additional_fields: HashMap<String, Value<'a>>,
 }
 
 impl Tracker {
     /// Trigger an annouce request to the torrent's tracker.
+    /// The protocol used depends on the tracker URL scheme (`http(s)://` or `udp://`).
     /// Response will contain a peer list.
-    pub async fn announce(&mut self, req: AnnounceReq) -> Result<Vec<u8>, TrackerError> {
-        let req = reqwest::Client::new()
-            .get(&self.url)
-            .query(&req)
+    pub async fn announce(
+        &mut self,
+        req: AnnounceReq,
+    ) -> Result<AnnounceRsp<'static>, TrackerError> {
+        match self.protocol()? {
+            Protocol::Http => {
+                let mut req = req;
+                if req.trackerid.is_none() && !self.tracker_id.is_empty() {
+                    req.trackerid = Some(self.tracker_id.clone());
+                }
+                let bytes = self.announce_http(&req).await?;
+                let rsp = self.convert_bytes(&bytes).await?;
+                if let Some(id) = &rsp.tracker_id {
+                    self.tracker_id = id.clone();
+                }
+
+                Ok(rsp)
+            }
+            Protocol::Udp => self.announce_udp(&req).await,
+        }
+    }
+
+    async fn announce_http(&self, req: &AnnounceReq) -> Result<Vec<u8>, TrackerError> {
+        // Query is built by hand: serializers would encode the raw bytes of `info_hash` twice
+        let sep = if self.url.contains('?') { '&' } else { '?' };
+        let req = self
+            .http
+            .get(format!("{}{sep}{}", self.url, req.to_query()))
             .send()
             .await
-            .unwrap();
+            .map_err(TrackerError::Request)?;
         if !req.status().is_success() {
             return Err(TrackerError::InvalidStatus(req.status().as_u16()));
         }
@@ -115,32 +254,337 @@ impl Tracker {
         Ok(req.bytes().await?.to_vec())
     }
 
-    pub async fn convert_bytes(&self, bytes: &'_ [u8]) -> Result<AnnounceRsp<'_>, TrackerError> {
-        match from_bytes::<'_, Body>(bytes).map_err(|e| TrackerError::BencodeDecode(e))? {
-            Body::Error { failure_reason } => {
-                return Err(TrackerError::AnnounceFailed(failure_reason))
-            }
-            Body::Success { interval, peers } => Ok(AnnounceRsp {
-                interval,
-                peers,
-                additional_fields: HashMap::new(),
-            }),
-            Body::SuccessCompact {
-                interval,
-                mut peers,
-                peers6,
-                additional_fields,
-            } => {
-                peers.extend(peers6);
-                Ok(AnnounceRsp {
-                    interval,
-                    peers,
-                    additional_fields: additional_fields
-                        .into_iter()
-                        .map(|(k, v)| (k, v.into_owned()))
-                        .collect(),
-                })
-            }
+    /// Parse the bencoded body of an HTTP tracker response.
+    pub async fn convert_bytes(&self, bytes: &[u8]) -> Result<AnnounceRsp<'static>, TrackerError> {
+        let body = from_bytes::<'_, Body>(bytes).map_err(TrackerError::BencodeDecode)?;
+        if !body.failure_reason.is_empty() {
+            return Err(match body.retry_in {
+                Some(retry_in) => TrackerError::AnnounceRetry {
+                    reason: body.failure_reason,
+                    retry_in,
+                },
+                None => TrackerError::AnnounceFailed(body.failure_reason),
+            });
         }
+
+        let mut peers = body.peers;
+        peers.extend(body.peers6);
+        Ok(AnnounceRsp {
+            interval: body.interval,
+            min_interval: body.min_interval,
+            tracker_id: Some(body.tracker_id).filter(|v| !v.is_empty()),
+            warning_message: Some(body.warning_message).filter(|v| !v.is_empty()),
+            complete: body.complete,
+            incomplete: body.incomplete,
+            external_ip: body.external_ip,
+            retry_in: body.retry_in,
+            peers,
+            additional_fields: body
+                .additional_fields
+                .into_iter()
+                .map(|(k, v)| (k, v.into_owned()))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        sync::{Arc, Mutex},
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const BASE_QUERY: &str = "info_hash=%80%FF%20~aaaaaaaaaaaaaaaa&peer_id=-BR0001-abcdefghijkl\
+        &port=6881&uploaded=1&downloaded=2&left=3&compact=1";
+
+    fn query_req() -> AnnounceReq {
+        let mut hash = [b'a'; 20];
+        hash[..4].copy_from_slice(&[0x80, 0xff, b' ', b'~']);
+        AnnounceReq {
+            info_hash: InfoHash(hash),
+            peer_id: PeerId(*b"-BR0001-abcdefghijkl"),
+            port: 6881,
+            uploaded: 1,
+            downloaded: 2,
+            left: 3,
+            compact: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn query_required_fields() {
+        assert_eq!(query_req().to_query(), BASE_QUERY);
+
+        let req = AnnounceReq {
+            compact: false,
+            ..query_req()
+        };
+        assert_eq!(req.to_query(), BASE_QUERY.replace("compact=1", "compact=0"));
+    }
+
+    #[test]
+    fn query_optional_fields() {
+        let cases = [
+            (
+                AnnounceReq {
+                    event: AnnounceEv::Started,
+                    ..query_req()
+                },
+                "&event=started",
+            ),
+            (
+                AnnounceReq {
+                    no_peer_id: true,
+                    ..query_req()
+                },
+                "&no_peer_id=1",
+            ),
+            (
+                AnnounceReq {
+                    ip: Some(Ipv4Addr::new(1, 2, 3, 4).into()),
+                    ..query_req()
+                },
+                "&ip=1.2.3.4",
+            ),
+            (
+                AnnounceReq {
+                    ip: Some(Ipv6Addr::LOCALHOST.into()),
+                    ..query_req()
+                },
+                "&ip=%3A%3A1",
+            ),
+            (
+                AnnounceReq {
+                    ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+                    ..query_req()
+                },
+                "&ipv4=1.2.3.4",
+            ),
+            (
+                AnnounceReq {
+                    ipv6: Some("2001:db8::1".parse().unwrap()),
+                    ..query_req()
+                },
+                "&ipv6=2001%3Adb8%3A%3A1",
+            ),
+            (
+                AnnounceReq {
+                    numwant: Some(50),
+                    ..query_req()
+                },
+                "&numwant=50",
+            ),
+            (
+                AnnounceReq {
+                    key: Some(0xdeadbeef),
+                    ..query_req()
+                },
+                "&key=DEADBEEF",
+            ),
+            (
+                AnnounceReq {
+                    key: Some(1),
+                    ..query_req()
+                },
+                "&key=00000001",
+            ),
+            (
+                AnnounceReq {
+                    trackerid: Some("a b/c".into()),
+                    ..query_req()
+                },
+                "&trackerid=a%20b%2Fc",
+            ),
+        ];
+
+        for (req, param) in cases {
+            assert_eq!(req.to_query(), format!("{BASE_QUERY}{param}"));
+        }
+    }
+
+    #[test]
+    fn query_all_fields() {
+        let req = AnnounceReq {
+            event: AnnounceEv::Stopped,
+            no_peer_id: true,
+            ip: Some(Ipv4Addr::new(1, 2, 3, 4).into()),
+            ipv4: Some(Ipv4Addr::new(5, 6, 7, 8)),
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+            numwant: Some(0),
+            key: Some(0xabc),
+            trackerid: Some("xyz".into()),
+            ..query_req()
+        };
+
+        assert_eq!(
+            req.to_query(),
+            format!(
+                "{BASE_QUERY}&event=stopped&no_peer_id=1&ip=1.2.3.4&ipv4=5.6.7.8&ipv6=%3A%3A1\
+                &numwant=0&key=00000ABC&trackerid=xyz"
+            )
+        );
+    }
+
+    async fn convert(body: &[u8]) -> Result<AnnounceRsp<'static>, TrackerError> {
+        Tracker::new("http://t.org/announce".into())
+            .convert_bytes(body)
+            .await
+    }
+
+    fn addrs(peers: &[Peer]) -> Vec<(IpAddr, u16)> {
+        peers.iter().map(|p| (p.ip, p.port)).collect()
+    }
+
+    #[tokio::test]
+    async fn convert_full_response() {
+        let rsp = convert(
+            b"d8:completei10e11:external ip4:\x0a\x00\x00\x0110:incompletei3e\
+            8:intervali1800e12:min intervali60e5:peers9:\x7f\x00\x00\x01\x1a\xe1abc\
+            6:peers618:\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1a\xe2\
+            10:tracker id3:xyz15:warning message4:slow1:xi1ee",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(rsp.interval, 1800);
+        assert_eq!(rsp.min_interval, Some(60));
+        assert_eq!(rsp.tracker_id.as_deref(), Some("xyz"));
+        assert_eq!(rsp.warning_message.as_deref(), Some("slow"));
+        assert_eq!((rsp.complete, rsp.incomplete), (Some(10), Some(3)));
+        assert_eq!(rsp.external_ip, Some(Ipv4Addr::new(10, 0, 0, 1).into()));
+        assert_eq!(rsp.retry_in, None);
+        // Compact peers, trailing incomplete entry ignored, then peers6
+        assert_eq!(
+            addrs(&rsp.peers),
+            [
+                (Ipv4Addr::LOCALHOST.into(), 6881),
+                (Ipv6Addr::LOCALHOST.into(), 6882)
+            ]
+        );
+        assert!(rsp.peers.iter().all(|p| p.id.is_none()));
+        assert_eq!(rsp.additional_fields.get("x"), Some(&Value::Integer(1)));
+    }
+
+    #[tokio::test]
+    async fn convert_minimal_response() {
+        let rsp = convert(b"d8:intervali900e5:peers0:e").await.unwrap();
+
+        assert_eq!(rsp.interval, 900);
+        assert_eq!(rsp.min_interval, None);
+        assert_eq!(rsp.tracker_id, None);
+        assert_eq!(rsp.warning_message, None);
+        assert_eq!((rsp.complete, rsp.incomplete), (None, None));
+        assert!(rsp.peers.is_empty());
+        assert!(rsp.additional_fields.is_empty());
+    }
+
+    #[tokio::test]
+    async fn convert_dict_peers() {
+        let rsp = convert(
+            b"d8:intervali900e5:peersld2:ip9:127.0.0.17:peer id20:-BR0001-aaaaaaaaaaaa\
+            4:porti6881eed2:ip3:::14:porti6882eed2:ip8:t.org.ab4:porti1eeee",
+        )
+        .await
+        .unwrap();
+
+        // Peers advertised with a DNS name are ignored
+        assert_eq!(
+            addrs(&rsp.peers),
+            [
+                (Ipv4Addr::LOCALHOST.into(), 6881),
+                (Ipv6Addr::LOCALHOST.into(), 6882)
+            ]
+        );
+        assert_eq!(rsp.peers[0].id.as_deref(), Some("-BR0001-aaaaaaaaaaaa"));
+        assert_eq!(rsp.peers[1].id, None);
+    }
+
+    #[tokio::test]
+    async fn convert_failure_reason() {
+        assert!(matches!(
+            convert(b"d14:failure reason6:bannede").await,
+            Err(TrackerError::AnnounceFailed(r)) if r == "banned"
+        ));
+        assert!(matches!(
+            convert(b"d14:failure reason4:busy8:retry ini5ee").await,
+            Err(TrackerError::AnnounceRetry { reason, retry_in: RetryIn::Minutes(5) }) if reason == "busy"
+        ));
+        assert!(matches!(
+            convert(b"d14:failure reason6:banned8:retry in5:nevere").await,
+            Err(TrackerError::AnnounceRetry {
+                retry_in: RetryIn::Never,
+                ..
+            })
+        ));
+        assert!(matches!(
+            convert(b"d8:interval").await,
+            Err(TrackerError::BencodeDecode(_))
+        ));
+    }
+
+    /// HTTP tracker answering `body` to every announce, recording the request targets
+    async fn stub(body: &'static [u8]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let targets = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&targets);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                recorded.lock().unwrap().push(target.to_string());
+
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes()).await;
+                let _ = stream.write_all(body).await;
+            }
+        });
+
+        (url, targets)
+    }
+
+    #[tokio::test]
+    async fn echo_tracker_id() {
+        let (url, targets) = stub(b"d8:intervali1800e5:peers0:10:tracker id3:xyze").await;
+        let mut tracker = Tracker::new(url);
+        let req = AnnounceReq {
+            info_hash: InfoHash([b'a'; 20]),
+            ..Default::default()
+        };
+
+        tracker.announce(req.clone()).await.unwrap();
+        tracker.announce(req.clone()).await.unwrap();
+        tracker
+            .announce(AnnounceReq {
+                trackerid: Some("mine".into()),
+                ..req
+            })
+            .await
+            .unwrap();
+
+        let targets = targets.lock().unwrap();
+        assert!(!targets[0].contains("trackerid="));
+        assert!(targets[1].contains("&trackerid=xyz"));
+        assert!(targets[2].contains("&trackerid=mine"));
     }
 }
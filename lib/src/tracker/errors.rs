@@ -1,5 +1,9 @@
+use std::io;
+
 use thiserror::Error;
 
+use super::announce::RetryIn;
+
 #[derive(Debug, Error)]
 pub enum TrackerError {
     #[error("Failed to decode response body: {0}")]
@@ -8,6 +12,30 @@ pub enum TrackerError {
     InvalidStatus(u16),
     #[error("Announce request failed: {0}")]
     AnnounceFailed(String),
+    #[error("Announce request failed: {reason} (retry in {retry_in})")]
+    AnnounceRetry { reason: String, retry_in: RetryIn },
     #[error("Failed to get response body: {0}")]
     BodyDecode(#[from] reqwest::Error),
+    #[error("Failed to send request to tracker: {0}")]
+    Request(reqwest::Error),
+    #[error("No tracker available")]
+    NoTracker,
+    #[error("Invalid tracker URL {0}")]
+    InvalidUrl(String),
+    #[error("Unsupported tracker protocol: {0}")]
+    UnsupportedProtocol(String),
+    #[error("Invalid announce request: {0}")]
+    InvalidRequest(String),
+    #[error("Tracker sent an invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Tracker does not support scrape requests: {0}")]
+    ScrapeUnsupported(String),
+    #[error("Scrape request failed: {0}")]
+    ScrapeFailed(String),
+    #[error("Tracker returned an error: {0}")]
+    Remote(String),
+    #[error("Tracker did not respond in time")]
+    Timeout,
+    #[error("Tracker socket error: {0}")]
+    Io(#[from] io::Error),
 }
@@ -0,0 +1,367 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::torrent::v1::Torrent;
+
+use super::{
+    announce::{AnnounceReq, AnnounceRsp},
+    errors::TrackerError,
+    tracker::UDP_DEFAULT_RETRIES,
+    Tracker,
+};
+
+/// State of a tracker worth keeping between two sessions
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrackerState {
+    pub url: String,
+    /// Last `tracker id` sent by the tracker
+    #[serde(
+        default,
+        rename = "tracker id",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub tracker_id: String,
+}
+
+/// TrackerManager announces to a tiered list of trackers following
+/// [BEP 0012](https://www.bittorrent.org/beps/bep_0012.html).
+///
+/// Trackers are shuffled within their tier once, when the manager is created. Tiers are
+/// tried in order, and each tracker of a tier is tried before moving to the next one.
+/// A tracker responding successfully is moved to the front of its tier.
+#[derive(Debug, Clone, Default)]
+pub struct TrackerManager {
+    tiers: Vec<Vec<Tracker>>,
+}
+
+impl TrackerManager {
+    /// Create a new manager from tiers of tracker URLs.
+    /// UDP trackers are retried [`UDP_DEFAULT_RETRIES`] times, see
+    /// [`TrackerManager::udp_retries`].
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        let mut rng = rand::thread_rng();
+        let tiers = tiers
+            .into_iter()
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                let mut tier: Vec<Tracker> = t.into_iter().map(Tracker::new).collect();
+                tier.shuffle(&mut rng);
+                tier
+            })
+            .collect();
+
+        Self { tiers }.udp_retries(UDP_DEFAULT_RETRIES)
+    }
+
+    /// Create a manager from the torrent's `announce-list`, falling back to `announce`
+    /// when the list is empty. Trackerless torrents get an empty manager.
+    pub fn from_torrent(torrent: &Torrent) -> Self {
+        if !torrent.announce_list.is_empty() {
+            return Self::new(torrent.announce_list.tiers().to_vec());
+        }
+
+        if torrent.announce.is_empty() {
+            return Self::default();
+        }
+
+        Self::new(vec![vec![torrent.announce.clone()]])
+    }
+
+    pub fn tiers(&self) -> &[Vec<Tracker>] {
+        &self.tiers
+    }
+
+    /// Number of retransmissions before giving up on each UDP tracker, see
+    /// [`Tracker::udp_retries`]
+    pub fn udp_retries(mut self, retries: u32) -> Self {
+        for tracker in self.tiers.iter_mut().flatten() {
+            tracker.udp_retries = retries;
+        }
+        self
+    }
+
+    /// Share `client` between every HTTP tracker
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        for tracker in self.tiers.iter_mut().flatten() {
+            tracker.http = client.clone();
+        }
+        self
+    }
+
+    /// Current order of each tier along with the tracker IDs received, to be restored with
+    /// [`TrackerManager::restore`]
+    pub fn state(&self) -> Vec<Vec<TrackerState>> {
+        self.tiers
+            .iter()
+            .map(|t| {
+                t.iter()
+                    .map(|tracker| TrackerState {
+                        url: tracker.url.clone(),
+                        tracker_id: tracker.tracker_id.clone(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Restore the tier order and tracker IDs of a previous session.
+    /// Trackers are matched by URL, unknown ones are ignored.
+    pub fn restore(&mut self, state: &[Vec<TrackerState>]) {
+        let saved: Vec<&TrackerState> = state.iter().flatten().collect();
+        for tier in &mut self.tiers {
+            tier.sort_by_key(|t| {
+                saved
+                    .iter()
+                    .position(|s| s.url == t.url)
+                    .unwrap_or(usize::MAX)
+            });
+            for tracker in tier.iter_mut() {
+                if let Some(s) = saved.iter().find(|s| s.url == tracker.url) {
+                    tracker.tracker_id = s.tracker_id.clone();
+                }
+            }
+        }
+    }
+
+    /// Announce to the first tracker responding successfully.
+    /// If every tracker fails, the last error is returned.
+    pub async fn announce(
+        &mut self,
+        req: AnnounceReq,
+    ) -> Result<AnnounceRsp<'static>, TrackerError> {
+        let mut last_err = TrackerError::NoTracker;
+        for tier in self.tiers.iter_mut() {
+            for i in 0..tier.len() {
+                match tier[i].announce(req.clone()).await {
+                    Ok(rsp) => {
+                        let tracker = tier.remove(i);
+                        tier.insert(0, tracker);
+                        return Ok(rsp);
+                    }
+                    Err(e) => last_err = e,
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::tracker::tracker::UDP_MAX_RETRIES;
+
+    /// HTTP tracker answering every announce with `status`, counting the announces
+    async fn stub(status: u16) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let body = "d8:intervali1800e5:peers0:e";
+                let rsp = format!(
+                    "HTTP/1.1 {status} Stub\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(rsp.as_bytes()).await;
+            }
+        });
+
+        (url, hits)
+    }
+
+    fn urls(tier: &[Tracker]) -> Vec<&str> {
+        tier.iter().map(|t| t.url.as_str()).collect()
+    }
+
+    #[test]
+    fn udp_retries_defaults() {
+        assert_eq!(Tracker::default().udp_retries, UDP_MAX_RETRIES);
+        assert_eq!(Tracker::new("udp://a".into()).udp_retries, UDP_MAX_RETRIES);
+
+        let manager = TrackerManager::new(vec![vec!["udp://a".into(), "udp://b".into()]]);
+        assert!(manager
+            .tiers()
+            .iter()
+            .flatten()
+            .all(|t| t.udp_retries == UDP_DEFAULT_RETRIES));
+
+        let manager = manager.udp_retries(UDP_MAX_RETRIES);
+        assert!(manager
+            .tiers()
+            .iter()
+            .flatten()
+            .all(|t| t.udp_retries == UDP_MAX_RETRIES));
+    }
+
+    #[test]
+    fn tiers_are_shuffled_in_place() {
+        let tiers = vec![
+            (0..5).map(|i| format!("http://a{i}")).collect::<Vec<_>>(),
+            vec![],
+            vec!["http://b".to_string()],
+        ];
+        let orders: Vec<Vec<String>> = (0..50)
+            .map(|_| {
+                let manager = TrackerManager::new(tiers.clone());
+                assert_eq!(manager.tiers().len(), 2, "empty tiers are dropped");
+                assert_eq!(urls(&manager.tiers()[1]), ["http://b"]);
+
+                let mut order: Vec<String> = urls(&manager.tiers()[0])
+                    .into_iter()
+                    .map(String::from)
+                    .collect();
+                let shuffled = order.clone();
+                order.sort();
+                assert_eq!(order, tiers[0]);
+                shuffled
+            })
+            .collect();
+
+        assert!(orders.iter().any(|o| *o != orders[0]));
+    }
+
+    #[tokio::test]
+    async fn trackers_are_tried_in_order_and_promoted() {
+        let (fail_a, hits_a) = stub(500).await;
+        let (fail_b, hits_b) = stub(500).await;
+        let (ok, hits_ok) = stub(200).await;
+        let mut manager = TrackerManager::new(vec![vec![fail_a.clone(), ok.clone(), fail_b]]);
+        let order: Vec<String> = urls(&manager.tiers()[0])
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let ok_pos = order.iter().position(|u| *u == ok).unwrap();
+
+        manager.announce(AnnounceReq::default()).await.unwrap();
+        assert_eq!(hits_ok.load(Ordering::SeqCst), 1);
+        // Only the trackers before the responding one were tried
+        let fail_a_pos = order.iter().position(|u| *u == fail_a).unwrap();
+        let (tried_a, tried_b) = (hits_a.load(Ordering::SeqCst), hits_b.load(Ordering::SeqCst));
+        assert_eq!(tried_a, (fail_a_pos < ok_pos) as usize);
+        assert_eq!(tried_a + tried_b, ok_pos);
+
+        let mut promoted = order.clone();
+        let tracker = promoted.remove(ok_pos);
+        promoted.insert(0, tracker);
+        assert_eq!(urls(&manager.tiers()[0]), promoted);
+
+        manager.announce(AnnounceReq::default()).await.unwrap();
+        assert_eq!(hits_ok.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            tried_a + tried_b,
+            hits_a.load(Ordering::SeqCst) + hits_b.load(Ordering::SeqCst)
+        );
+    }
+
+    #[tokio::test]
+    async fn next_tier_is_tried_when_a_tier_fails() {
+        let (fail_a, hits_a) = stub(500).await;
+        let (fail_b, hits_b) = stub(500).await;
+        let (ok, hits_ok) = stub(200).await;
+        let mut manager = TrackerManager::new(vec![vec![fail_a, fail_b], vec![ok]]);
+        let first_tier: Vec<String> = urls(&manager.tiers()[0])
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        manager.announce(AnnounceReq::default()).await.unwrap();
+        assert_eq!(hits_a.load(Ordering::SeqCst), 1);
+        assert_eq!(hits_b.load(Ordering::SeqCst), 1);
+        assert_eq!(hits_ok.load(Ordering::SeqCst), 1);
+        assert_eq!(urls(&manager.tiers()[0]), first_tier);
+    }
+
+    #[tokio::test]
+    async fn last_error_is_returned() {
+        let (fail, hits) = stub(503).await;
+        let mut manager = TrackerManager::new(vec![vec![fail]]);
+
+        assert!(matches!(
+            manager.announce(AnnounceReq::default()).await,
+            Err(TrackerError::InvalidStatus(503))
+        ));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            TrackerManager::default()
+                .announce(AnnounceReq::default())
+                .await,
+            Err(TrackerError::NoTracker)
+        ));
+    }
+
+    fn state(url: &str, tracker_id: &str) -> TrackerState {
+        TrackerState {
+            url: url.to_string(),
+            tracker_id: tracker_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn restore_tier_order_and_tracker_ids() {
+        let mut manager = TrackerManager::new(vec![
+            vec!["a".into(), "b".into(), "c".into()],
+            vec!["d".into()],
+        ]);
+        manager.restore(&[
+            vec![state("c", "x"), state("gone", "y"), state("a", "")],
+            vec![state("d", "z")],
+        ]);
+
+        // Trackers missing from the saved state go last
+        assert_eq!(
+            manager.state(),
+            [
+                vec![state("c", "x"), state("a", ""), state("b", "")],
+                vec![state("d", "z")]
+            ]
+        );
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let tiers = vec![(0..5).map(|i| format!("http://t{i}")).collect::<Vec<_>>()];
+        let mut saved = TrackerManager::new(tiers.clone()).state();
+        saved[0][2].tracker_id = "id".into();
+
+        for _ in 0..10 {
+            let mut manager = TrackerManager::new(tiers.clone());
+            manager.restore(&saved);
+            assert_eq!(manager.state(), saved);
+        }
+    }
+
+    #[test]
+    fn from_torrent_falls_back_to_announce() {
+        let torrent = Torrent::parse_bytes(
+            b"d8:announce8:http://a13:announce-listle4:infod6:lengthi1e4:name1:a\
+            12:piece lengthi1e6:pieces20:aaaaaaaaaaaaaaaaaaaaee",
+        )
+        .unwrap();
+        let manager = TrackerManager::from_torrent(&torrent);
+
+        assert_eq!(manager.tiers().len(), 1);
+        assert_eq!(urls(&manager.tiers()[0]), ["http://a"]);
+    }
+}
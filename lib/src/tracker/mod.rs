@@ -1,11 +1,22 @@
 pub mod announce;
 mod errors;
+pub mod manager;
 mod parsing_modules;
+pub mod scrape;
+#[allow(clippy::module_inception)]
 pub mod tracker;
+mod udp;
 
-use std::net::IpAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Instant,
+};
 
 use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+pub use errors::TrackerError;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Peer {
@@ -20,8 +31,21 @@ pub struct Peer {
     pub port: u16,
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug, Clone)]
 pub struct Tracker {
     /// Tracker URL
     pub url: String,
+    /// Number of retransmissions before giving up on a UDP tracker.
+    /// Each one doubles the response timeout, starting at 15 seconds (`BEP 0015`).
+    /// Defaults to [`tracker::UDP_MAX_RETRIES`], the full schedule.
+    pub udp_retries: u32,
+    /// Socket connected to a UDP tracker, kept so the connection ID is always sent from
+    /// the address it was issued to
+    udp_socket: Option<(Arc<UdpSocket>, SocketAddr)>,
+    /// UDP connection ID and the time it was obtained
+    udp_connection: Option<(u64, Instant)>,
+    /// Last `tracker id` sent by the tracker, echoed back on the next announces
+    tracker_id: String,
+    /// HTTP client, possibly shared with other trackers
+    http: reqwest::Client,
 }
@@ -1,21 +1,112 @@
 use std::net::IpAddr;
 
-use serde::{Deserializer, Serialize, Serializer};
+use bendy::value::Value;
+use serde::{de, Deserialize, Deserializer};
 use serde_with::{Bytes, DeserializeAs};
 
-use super::Peer;
+use super::{announce::RetryIn, Peer};
 
 const PEER_IPV4_CHUNK_LEN: u8 = 6;
 const PEER_IPV6_CHUNK_LEN: u8 = 18;
 
-pub fn deserialize_ipv4<'de, D>(deserializer: D) -> Result<Vec<Peer>, D::Error>
+pub fn deserialize_ipv6<'de, D>(deserializer: D) -> Result<Vec<Peer>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bytes: &[u8] = Bytes::deserialize_as(deserializer)?;
+
+    Ok(parse_ipv6(bytes))
+}
+
+/// Deserialize a peer list sent either as a list of dictionaries or in the compact IPv4 form.
+/// Peers advertised with a DNS name instead of an IP address are ignored.
+pub fn deserialize_peers<'de, D>(deserializer: D) -> Result<Vec<Peer>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Bytes(v) => Ok(parse_ipv4(&v)),
+        Value::List(v) => Ok(v.iter().filter_map(dict_peer).collect()),
+        Value::Integer(v) => Err(de::Error::invalid_type(
+            de::Unexpected::Signed(v),
+            &"list of dict or compact peers",
+        )),
+        Value::Dict(_) => Err(de::Error::invalid_type(
+            de::Unexpected::Map,
+            &"list of dict or compact peers",
+        )),
+    }
+}
+
+fn dict_peer(v: &Value) -> Option<Peer> {
+    let dict = match v {
+        Value::Dict(v) => v,
+        _ => return None,
+    };
+    let ip = match dict.get(&b"ip"[..])? {
+        Value::Bytes(v) => std::str::from_utf8(v).ok()?.parse().ok()?,
+        _ => return None,
+    };
+    let port = match dict.get(&b"port"[..])? {
+        Value::Integer(v) => u16::try_from(*v).ok()?,
+        _ => return None,
+    };
+    let id = match dict.get(&b"peer id"[..]) {
+        Some(Value::Bytes(v)) => String::from_utf8(v.to_vec()).ok(),
+        _ => None,
+    };
+
+    Some(Peer { id, ip, port })
+}
+
+/// Wrap a present value in `Some`. Absent values rely on `#[serde(default)]`.
+pub fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Deserialize an IP address in its binary form: 4 bytes for IPv4, 16 bytes for IPv6.
+pub fn deserialize_external_ip<'de, D>(deserializer: D) -> Result<Option<IpAddr>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let bytes: &[u8] = Bytes::deserialize_as(deserializer)?;
+    if let Ok(v) = TryInto::<[u8; 4]>::try_into(bytes) {
+        return Ok(Some(IpAddr::from(v)));
+    }
+    if let Ok(v) = TryInto::<[u8; 16]>::try_into(bytes) {
+        return Ok(Some(IpAddr::from(v)));
+    }
+
+    Err(de::Error::invalid_length(bytes.len(), &"4 or 16 bytes"))
+}
+
+/// Deserialize `retry in`: a number of minutes or the string `never`.
+pub fn deserialize_retry_in<'de, D>(deserializer: D) -> Result<Option<RetryIn>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Integer(v) => Ok(Some(RetryIn::Minutes(v.max(0) as u64))),
+        Value::Bytes(v) if v.as_ref() == b"never" => Ok(Some(RetryIn::Never)),
+        Value::Bytes(v) => Err(de::Error::invalid_value(
+            de::Unexpected::Bytes(&v),
+            &"integer or \"never\"",
+        )),
+        _ => Err(de::Error::custom(
+            "retry in must be an integer or \"never\"",
+        )),
+    }
+}
 
+/// Parse a compact IPv4 peer list: 4 bytes for the address and 2 for the port, BigEndian.
+/// Trailing incomplete entries are ignored.
+pub fn parse_ipv4(bytes: &[u8]) -> Vec<Peer> {
     let mut peers = vec![];
-    for c in bytes.chunks(PEER_IPV4_CHUNK_LEN as usize) {
+    for c in bytes.chunks_exact(PEER_IPV4_CHUNK_LEN as usize) {
         let (ip_c, port_c) = c.split_at(4);
         peers.push(Peer {
             id: None,
@@ -28,17 +119,14 @@ where
         });
     }
 
-    Ok(peers)
+    peers
 }
 
-pub fn deserialize_ipv6<'de, D>(deserializer: D) -> Result<Vec<Peer>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let bytes: &[u8] = Bytes::deserialize_as(deserializer)?;
-
+/// Parse a compact IPv6 peer list: 16 bytes for the address and 2 for the port, BigEndian.
+/// Trailing incomplete entries are ignored.
+pub fn parse_ipv6(bytes: &[u8]) -> Vec<Peer> {
     let mut peers = vec![];
-    for c in bytes.chunks(PEER_IPV6_CHUNK_LEN as usize) {
+    for c in bytes.chunks_exact(PEER_IPV6_CHUNK_LEN as usize) {
         let (ip_c, port_c) = c.split_at(16);
         peers.push(Peer {
             id: None,
@@ -51,12 +139,5 @@ where
         });
     }
 
-    Ok(peers)
-}
-
-pub fn serialize_bytes_urlencoded<S>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    unsafe { std::str::from_utf8_unchecked(bytes) }.serialize(serializer)
+    peers
 }
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use bendy::serde::from_bytes;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_with::{serde_as, Bytes, Same};
+
+use crate::torrent::InfoHash;
+
+use super::{errors::TrackerError, tracker::Protocol, Tracker};
+
+/// Swarm statistics of a torrent returned by a scrape request
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ScrapeStats {
+    /// Number of peers with the entire file (seeders)
+    #[serde(default)]
+    pub complete: u64,
+    /// Number of times the tracker registered a completion
+    #[serde(default)]
+    pub downloaded: u64,
+    /// Number of peers still downloading (leechers)
+    #[serde(default)]
+    pub incomplete: u64,
+    /// Torrent name, as found in the torrent `info` dictionary.
+    /// Only sent by some HTTP trackers, empty otherwise.
+    #[serde(default)]
+    pub name: String,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct Body {
+    #[serde(default, rename = "failure reason")]
+    failure_reason: String,
+    /// Statistics indexed by the raw info hash bytes
+    #[serde_as(as = "HashMap<Bytes, Same>")]
+    #[serde(default)]
+    files: HashMap<Vec<u8>, ScrapeStats>,
+}
+
+impl Tracker {
+    /// Retrieve swarm statistics for each info hash without announcing
+    /// ([BEP 0048](https://www.bittorrent.org/beps/bep_0048.html) for HTTP trackers).
+    /// Info hashes unknown to the tracker are missing from the result.
+    pub async fn scrape(
+        &mut self,
+        info_hashes: &[InfoHash],
+    ) -> Result<HashMap<InfoHash, ScrapeStats>, TrackerError> {
+        match self.protocol()? {
+            Protocol::Http => self.scrape_http(info_hashes).await,
+            Protocol::Udp => self.scrape_udp(info_hashes).await,
+        }
+    }
+
+    /// Derive the scrape URL from the announce URL: the last path component must start
+    /// with `announce`, which is replaced by `scrape`.
+    pub fn scrape_url(&self) -> Result<String, TrackerError> {
+        let mut url =
+            Url::parse(&self.url).map_err(|_| TrackerError::InvalidUrl(self.url.clone()))?;
+        let path = url.path().to_string();
+        let (dir, last) = path.rsplit_once('/').unwrap_or(("", &path));
+        match last.strip_prefix("announce") {
+            Some(rest) => url.set_path(&format!("{dir}/scrape{rest}")),
+            None => return Err(TrackerError::ScrapeUnsupported(self.url.clone())),
+        }
+
+        Ok(url.to_string())
+    }
+
+    async fn scrape_http(
+        &self,
+        info_hashes: &[InfoHash],
+    ) -> Result<HashMap<InfoHash, ScrapeStats>, TrackerError> {
+        let mut url = self.scrape_url()?;
+        for (i, h) in info_hashes.iter().enumerate() {
+            let sep = if i == 0 && !url.contains('?') {
+                '?'
+            } else {
+                '&'
+            };
+            url.push_str(&format!("{sep}info_hash={}", h.urlencode()));
+        }
+
+        let rsp = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .map_err(TrackerError::Request)?;
+        if !rsp.status().is_success() {
+            return Err(TrackerError::InvalidStatus(rsp.status().as_u16()));
+        }
+        let bytes = rsp.bytes().await?;
+
+        decode_http(&bytes)
+    }
+}
+
+/// Decode an HTTP scrape response body. Entries whose key isn't a valid info hash are ignored.
+fn decode_http(bytes: &[u8]) -> Result<HashMap<InfoHash, ScrapeStats>, TrackerError> {
+    let body: Body = from_bytes(bytes).map_err(TrackerError::BencodeDecode)?;
+    if !body.failure_reason.is_empty() {
+        return Err(TrackerError::ScrapeFailed(body.failure_reason));
+    }
+
+    Ok(body
+        .files
+        .into_iter()
+        .filter_map(|(k, v)| Some((InfoHash::try_from(k.as_slice()).ok()?, v)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrape_url(url: &str) -> Result<String, TrackerError> {
+        Tracker::new(url.to_string()).scrape_url()
+    }
+
+    #[test]
+    fn replace_announce_in_last_segment() {
+        assert_eq!(
+            scrape_url("http://t.org/announce").unwrap(),
+            "http://t.org/scrape"
+        );
+        assert_eq!(
+            scrape_url("http://t.org/x/announce.php").unwrap(),
+            "http://t.org/x/scrape.php"
+        );
+        assert_eq!(
+            scrape_url("http://t.org/announce?passkey=abc").unwrap(),
+            "http://t.org/scrape?passkey=abc"
+        );
+        assert_eq!(
+            scrape_url("http://t.org/announce.php?a=1&b=2").unwrap(),
+            "http://t.org/scrape.php?a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn reject_url_without_announce() {
+        for url in [
+            "http://t.org/a",
+            "http://t.org/announce/x",
+            "http://t.org/x?p=announce",
+            "http://t.org/",
+        ] {
+            assert!(
+                matches!(scrape_url(url), Err(TrackerError::ScrapeUnsupported(u)) if u == url),
+                "{url}"
+            );
+        }
+        assert!(matches!(
+            scrape_url("not a url"),
+            Err(TrackerError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn decode_files() {
+        let a = InfoHash([b'a'; 20]);
+        let b = InfoHash([b'b'; 20]);
+        let rsp = decode_http(
+            b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei5e10:downloadedi50e10:incompletei10e\
+            4:name3:fooe3:badd8:completei9ee20:bbbbbbbbbbbbbbbbbbbbd8:completei1eeee",
+        )
+        .unwrap();
+
+        assert_eq!(rsp.len(), 2, "keys that aren't info hashes are ignored");
+        let stats = &rsp[&a];
+        assert_eq!(
+            (stats.complete, stats.downloaded, stats.incomplete),
+            (5, 50, 10)
+        );
+        assert_eq!(stats.name, "foo");
+        let stats = &rsp[&b];
+        assert_eq!(
+            (stats.complete, stats.downloaded, stats.incomplete),
+            (1, 0, 0)
+        );
+        assert!(stats.name.is_empty());
+        assert!(decode_http(b"de").unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_failure() {
+        assert!(matches!(
+            decode_http(b"d14:failure reason9:not founde"),
+            Err(TrackerError::ScrapeFailed(r)) if r == "not found"
+        ));
+        assert!(matches!(
+            decode_http(b"d5:files"),
+            Err(TrackerError::BencodeDecode(_))
+        ));
+    }
+}
@@ -1,11 +1,52 @@
-use super::Tracker;
+use reqwest::Url;
+
+use super::{errors::TrackerError, Tracker};
+
+/// Maximum number of UDP retransmissions defined by `BEP 0015` (3840 seconds timeout).
+pub const UDP_MAX_RETRIES: u32 = 8;
+/// Number of UDP retransmissions used by [`TrackerManager`](super::manager::TrackerManager).
+/// A dead tracker is given up after 105 seconds instead of the hours the full `BEP 0015`
+/// schedule takes, so the next tracker of the tier gets a chance.
+pub const UDP_DEFAULT_RETRIES: u32 = 2;
+
+/// Protocol spoken by a tracker, deduced from its URL scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Protocol {
+    Http,
+    Udp,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
 
 impl Tracker {
     /// Create a new instance of `Tracker`
     pub fn new(url: String) -> Self {
         Self {
             url,
-            ..Default::default()
+            udp_retries: UDP_MAX_RETRIES,
+            udp_socket: None,
+            udp_connection: None,
+            tracker_id: String::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Send HTTP announces with `client`, to share its connection pool
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http = client;
+        self
+    }
+
+    pub(super) fn protocol(&self) -> Result<Protocol, TrackerError> {
+        let url = Url::parse(&self.url).map_err(|_| TrackerError::InvalidUrl(self.url.clone()))?;
+        match url.scheme() {
+            "http" | "https" => Ok(Protocol::Http),
+            "udp" => Ok(Protocol::Udp),
+            s => Err(TrackerError::UnsupportedProtocol(s.to_string())),
         }
     }
 }
@@ -0,0 +1,371 @@
+//! UDP tracker protocol, as defined by [BEP 0015](https://www.bittorrent.org/beps/bep_0015.html).
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use reqwest::Url;
+use tokio::{net::UdpSocket, time};
+
+use crate::torrent::InfoHash;
+
+use super::{
+    announce::{AnnounceEv, AnnounceReq, AnnounceRsp},
+    errors::TrackerError,
+    parsing_modules,
+    scrape::ScrapeStats,
+    Tracker,
+};
+
+const PROTOCOL_ID: u64 = 0x41727101980;
+/// A connection ID can be used for one minute after it was received
+const CONNECTION_ID_TTL: Duration = Duration::from_secs(60);
+/// Base timeout, doubled on each retransmission
+const BASE_TIMEOUT_SECS: u64 = 15;
+const MAX_PACKET_LEN: usize = 65535;
+/// Maximum number of info hashes in a single scrape request
+const MAX_SCRAPE_HASHES: usize = 74;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+
+impl Tracker {
+    pub(super) async fn announce_udp(
+        &mut self,
+        req: &AnnounceReq,
+    ) -> Result<AnnounceRsp<'static>, TrackerError> {
+        let ip = match (req.ipv4, req.ip) {
+            (Some(v), _) | (None, Some(IpAddr::V4(v))) => v,
+            _ => Ipv4Addr::UNSPECIFIED,
+        };
+
+        let mut body = Vec::with_capacity(82);
+        body.extend_from_slice(req.info_hash.as_bytes());
+        body.extend_from_slice(req.peer_id.as_bytes());
+        body.extend_from_slice(&req.downloaded.to_be_bytes());
+        body.extend_from_slice(&req.left.to_be_bytes());
+        body.extend_from_slice(&req.uploaded.to_be_bytes());
+        body.extend_from_slice(&event_id(req.event).to_be_bytes());
+        body.extend_from_slice(&ip.octets());
+        body.extend_from_slice(&req.key.unwrap_or_default().to_be_bytes());
+        // -1 lets the tracker decide
+        let num_want = req.numwant.map_or(-1, |v| v.min(i32::MAX as u32) as i32);
+        body.extend_from_slice(&num_want.to_be_bytes());
+        body.extend_from_slice(&req.port.to_be_bytes());
+
+        let (socket, addr) = self.udp_socket().await?;
+        let rsp = self.udp_request(&socket, ACTION_ANNOUNCE, &body).await?;
+        if rsp.len() < 20 {
+            return Err(TrackerError::InvalidResponse(
+                "announce response is too short".into(),
+            ));
+        }
+
+        let interval = read_u32(&rsp[8..12]);
+        let leechers = read_u32(&rsp[12..16]);
+        let seeders = read_u32(&rsp[16..20]);
+        let peers = match addr {
+            SocketAddr::V4(_) => parsing_modules::parse_ipv4(&rsp[20..]),
+            SocketAddr::V6(_) => parsing_modules::parse_ipv6(&rsp[20..]),
+        };
+
+        Ok(AnnounceRsp {
+            interval: interval as u64,
+            complete: Some(seeders as u64),
+            incomplete: Some(leechers as u64),
+            peers,
+            ..Default::default()
+        })
+    }
+
+    pub(super) async fn scrape_udp(
+        &mut self,
+        info_hashes: &[InfoHash],
+    ) -> Result<HashMap<InfoHash, ScrapeStats>, TrackerError> {
+        let (socket, _) = self.udp_socket().await?;
+        let mut stats = HashMap::new();
+        for chunk in info_hashes.chunks(MAX_SCRAPE_HASHES) {
+            let body: Vec<u8> = chunk.iter().flat_map(|h| h.0).collect();
+            let rsp = self.udp_request(&socket, ACTION_SCRAPE, &body).await?;
+            for (h, c) in chunk.iter().zip(rsp[8..].chunks_exact(12)) {
+                stats.insert(
+                    *h,
+                    ScrapeStats {
+                        complete: read_u32(&c[0..4]) as u64,
+                        downloaded: read_u32(&c[4..8]) as u64,
+                        incomplete: read_u32(&c[8..12]) as u64,
+                        name: String::new(),
+                    },
+                );
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Socket connected to the tracker. It is opened on first use, resolving the tracker
+    /// address, and reused by the next requests.
+    async fn udp_socket(&mut self) -> Result<(Arc<UdpSocket>, SocketAddr), TrackerError> {
+        if let Some((socket, addr)) = &self.udp_socket {
+            return Ok((socket.clone(), *addr));
+        }
+
+        let url = Url::parse(&self.url).map_err(|_| TrackerError::InvalidUrl(self.url.clone()))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| TrackerError::InvalidUrl(self.url.clone()))?;
+        let port = url
+            .port()
+            .ok_or_else(|| TrackerError::InvalidUrl(self.url.clone()))?;
+        let addr = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port))
+            .await?
+            .next()
+            .ok_or_else(|| TrackerError::InvalidUrl(self.url.clone()))?;
+
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0").await?,
+            SocketAddr::V6(_) => UdpSocket::bind("[::]:0").await?,
+        };
+        socket.connect(addr).await?;
+        let socket = Arc::new(socket);
+        // A connection ID is only valid for the address it was sent to
+        self.udp_connection = None;
+        self.udp_socket = Some((socket.clone(), addr));
+
+        Ok((socket, addr))
+    }
+
+    /// Send a request, connecting first if needed, and wait for its response.
+    /// Requests are retransmitted following the `BEP 0015` timeout schedule.
+    async fn udp_request(
+        &mut self,
+        socket: &UdpSocket,
+        action: u32,
+        body: &[u8],
+    ) -> Result<Vec<u8>, TrackerError> {
+        let mut retries = 0;
+        loop {
+            let timeout = Duration::from_secs(BASE_TIMEOUT_SECS << retries);
+            let connection_id = match self.udp_connection {
+                Some((id, at)) if at.elapsed() < CONNECTION_ID_TTL => Some(id),
+                _ => None,
+            };
+
+            let rsp = match connection_id {
+                Some(id) => {
+                    let transaction_id = rand::random();
+                    let packet = header(id, action, transaction_id, body);
+                    socket.send(&packet).await?;
+                    time::timeout(timeout, receive(socket, action, transaction_id)).await
+                }
+                None => {
+                    let transaction_id = rand::random();
+                    let packet = header(PROTOCOL_ID, ACTION_CONNECT, transaction_id, &[]);
+                    socket.send(&packet).await?;
+                    match time::timeout(timeout, receive(socket, ACTION_CONNECT, transaction_id))
+                        .await
+                    {
+                        Ok(rsp) => {
+                            let rsp = rsp?;
+                            if rsp.len() < 16 {
+                                return Err(TrackerError::InvalidResponse(
+                                    "connect response is too short".into(),
+                                ));
+                            }
+                            self.udp_connection = Some((read_u64(&rsp[8..16]), Instant::now()));
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+
+            match rsp {
+                Ok(rsp) => return rsp,
+                Err(_) if retries < self.udp_retries.min(super::tracker::UDP_MAX_RETRIES) => {
+                    retries += 1
+                }
+                Err(_) => return Err(TrackerError::Timeout),
+            }
+        }
+    }
+}
+
+/// Wait for the response matching `transaction_id`, ignoring unrelated packets
+async fn receive(
+    socket: &UdpSocket,
+    action: u32,
+    transaction_id: u32,
+) -> Result<Vec<u8>, TrackerError> {
+    let mut buf = vec![0u8; MAX_PACKET_LEN];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        if len < 8 || read_u32(&buf[4..8]) != transaction_id {
+            continue;
+        }
+
+        return match read_u32(&buf[0..4]) {
+            ACTION_ERROR => Err(TrackerError::Remote(
+                String::from_utf8_lossy(&buf[8..len]).into_owned(),
+            )),
+            a if a == action => Ok(buf[..len].to_vec()),
+            a => Err(TrackerError::InvalidResponse(format!(
+                "unexpected action {a}, expected {action}"
+            ))),
+        };
+    }
+}
+
+fn header(connection_id: u64, action: u32, transaction_id: u32, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(16 + body.len());
+    packet.extend_from_slice(&connection_id.to_be_bytes());
+    packet.extend_from_slice(&action.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    packet.extend_from_slice(body);
+
+    packet
+}
+
+fn event_id(event: AnnounceEv) -> u32 {
+    match event {
+        AnnounceEv::Empty => 0,
+        AnnounceEv::Completed => 1,
+        AnnounceEv::Started => 2,
+        AnnounceEv::Stopped => 3,
+    }
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes(b.try_into().expect("slice should be 4 bytes long"))
+}
+
+fn read_u64(b: &[u8]) -> u64 {
+    u64::from_be_bytes(b.try_into().expect("slice should be 8 bytes long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::peer::PeerId;
+
+    use super::*;
+
+    const CONNECTION_ID: u64 = 0x1122334455667788;
+
+    /// Answer connect, announce and scrape requests, recording the source of every packet
+    async fn fake_tracker(sources: Arc<Mutex<Vec<(u32, SocketAddr)>>>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_PACKET_LEN];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let (connection_id, action) = (read_u64(&buf[0..8]), read_u32(&buf[8..12]));
+                let transaction_id = read_u32(&buf[12..16]);
+                sources.lock().unwrap().push((action, from));
+
+                let mut rsp = action.to_be_bytes().to_vec();
+                rsp.extend_from_slice(&transaction_id.to_be_bytes());
+                match action {
+                    ACTION_CONNECT => {
+                        assert_eq!(connection_id, PROTOCOL_ID);
+                        rsp.extend_from_slice(&CONNECTION_ID.to_be_bytes());
+                    }
+                    ACTION_ANNOUNCE => {
+                        assert_eq!(connection_id, CONNECTION_ID);
+                        for v in [1800u32, 3, 5] {
+                            rsp.extend_from_slice(&v.to_be_bytes());
+                        }
+                        rsp.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+                        rsp.extend_from_slice(&[10, 0, 0, 2, 0xc8, 0xd5]);
+                    }
+                    ACTION_SCRAPE => {
+                        assert_eq!(connection_id, CONNECTION_ID);
+                        for i in 0..(len - 16) / 20 {
+                            for v in [i as u32 + 1, 10, 2] {
+                                rsp.extend_from_slice(&v.to_be_bytes());
+                            }
+                        }
+                    }
+                    a => panic!("unexpected action {a}"),
+                }
+                socket.send_to(&rsp, from).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn announce_and_scrape_share_connection() {
+        let sources = Arc::new(Mutex::new(vec![]));
+        let addr = fake_tracker(sources.clone()).await;
+        let mut tracker = Tracker::new(format!("udp://{addr}/announce"));
+
+        let req = AnnounceReq {
+            info_hash: InfoHash([1; 20]),
+            peer_id: PeerId::generate(None),
+            port: 6881,
+            ..Default::default()
+        };
+        let rsp = tracker.announce(req).await.unwrap();
+        assert_eq!(rsp.interval, 1800);
+        assert_eq!((rsp.complete, rsp.incomplete), (Some(5), Some(3)));
+        let peers: Vec<SocketAddr> = rsp
+            .peers
+            .iter()
+            .map(|p| SocketAddr::new(p.ip, p.port))
+            .collect();
+        assert_eq!(
+            peers,
+            [
+                "10.0.0.1:6881".parse().unwrap(),
+                "10.0.0.2:51413".parse().unwrap()
+            ]
+        );
+
+        let hashes = [InfoHash([1; 20]), InfoHash([2; 20])];
+        let stats = tracker.scrape(&hashes).await.unwrap();
+        assert_eq!(stats[&hashes[0]].complete, 1);
+        assert_eq!(stats[&hashes[1]].complete, 2);
+        assert_eq!(stats[&hashes[1]].downloaded, 10);
+        assert_eq!(stats[&hashes[1]].incomplete, 2);
+
+        let sources = sources.lock().unwrap();
+        let actions: Vec<u32> = sources.iter().map(|(a, _)| *a).collect();
+        assert_eq!(actions, [ACTION_CONNECT, ACTION_ANNOUNCE, ACTION_SCRAPE]);
+        assert!(sources.iter().all(|(_, from)| *from == sources[0].1));
+    }
+
+    #[tokio::test]
+    async fn error_action_is_a_remote_error() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_PACKET_LEN];
+            loop {
+                let (_, from) = socket.recv_from(&mut buf).await.unwrap();
+                let (action, body) = match read_u32(&buf[8..12]) {
+                    ACTION_CONNECT => (ACTION_CONNECT, CONNECTION_ID.to_be_bytes().to_vec()),
+                    _ => (ACTION_ERROR, b"scrape is disabled".to_vec()),
+                };
+                let mut rsp = action.to_be_bytes().to_vec();
+                rsp.extend_from_slice(&buf[12..16]);
+                rsp.extend_from_slice(&body);
+                socket.send_to(&rsp, from).await.unwrap();
+            }
+        });
+
+        let mut tracker = Tracker::new(format!("udp://{addr}/announce"));
+        match tracker.scrape(&[InfoHash([1; 20])]).await {
+            Err(TrackerError::Remote(msg)) => assert_eq!(msg, "scrape is disabled"),
+            v => panic!("expected a remote error, got {v:?}"),
+        }
+    }
+}